@@ -0,0 +1,30 @@
+use project::*;
+use std::time::Instant;
+
+const FIB: &str = "
+(define (fib n)
+    (if (< n 2)
+        1
+        (+ (fib (- n 1))
+           (fib (- n 2)))))
+
+(fib 25)
+";
+
+fn main() {
+    let mut best = None;
+
+    for _ in 0..5 {
+        let mut ctx = ScmExecContext::new();
+        let callables = Parser::new(FIB).parse().unwrap();
+
+        let start = Instant::now();
+        for callable in callables.iter() {
+            exec_callable(&mut ctx, callable, &Vec::new()).unwrap();
+        }
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |best: std::time::Duration| best.min(elapsed)));
+    }
+
+    println!("fib(25): {:?}", best.unwrap());
+}
@@ -0,0 +1,69 @@
+use project::*;
+use std::time::{Duration, Instant};
+
+const FIB: &str = "
+(define (fib n)
+    (if (< n 2)
+        1
+        (+ (fib (- n 1))
+           (fib (- n 2)))))
+
+(fib 25)
+";
+
+// Creates a closure on every call
+const CLOSURES: &str = "
+(define (adder x) (lambda (y) (+ x y)))
+
+(define (sum-adders n)
+    (if (= n 0)
+        0
+        (+ ((adder n) 1)
+           (sum-adders (- n 1)))))
+
+(define (repeat n)
+    (if (= n 0)
+        0
+        (+ (sum-adders 1000)
+           (repeat (- n 1)))))
+
+(repeat 200)
+";
+
+// Best time of several runs
+fn best_of(run: impl Fn() -> Duration) -> Duration {
+    (0..5).map(|_| run()).min().unwrap()
+}
+
+fn vm(code: &str) -> Duration {
+    best_of(|| {
+        let mut ctx = ScmExecContext::new();
+        let callables = Parser::new(code).parse().unwrap();
+
+        let start = Instant::now();
+        for callable in callables.iter() {
+            exec_callable(&mut ctx, callable, &Vec::new()).unwrap();
+        }
+        start.elapsed()
+    })
+}
+
+// `old` is the time of the tree-walking interpreter the VM replaced
+fn bench(name: &str, code: &str, old: Duration) {
+    let new = vm(code);
+    println!(
+        "{}: {:?} (tree-walking {:?}, {:.2}x)",
+        name,
+        new,
+        old,
+        old.as_secs_f64() / new.as_secs_f64()
+    );
+}
+
+fn main() {
+    // Times of the tree-walking interpreter were recorded with the same
+    // programs on the machine the bench was written on, so the ratio is
+    // meaningful only there
+    bench("fib(25)", FIB, Duration::from_millis(63));
+    bench("closures", CLOSURES, Duration::from_millis(178));
+}
@@ -1,10 +1,39 @@
 pub mod scm_builtins;
+pub mod scm_convert;
 pub mod scm_core;
+pub mod scm_error;
+pub mod scm_foreign;
+pub mod scm_hash;
+pub mod scm_library;
+pub mod scm_limits;
+pub mod scm_native;
+pub mod scm_port;
+pub mod scm_prelude;
+pub mod scm_print;
+pub mod scm_record;
+pub mod scm_sandbox;
 pub mod scm_utils;
+pub mod symbol;
+#[cfg(test)]
+pub(crate) mod test_utils;
 pub mod typed_num;
 pub mod util;
+pub mod vm;
 
 pub use scm_builtins::*;
+pub use scm_convert::*;
 pub use scm_core::*;
+pub use scm_error::*;
+pub use scm_foreign::*;
+pub use scm_hash::*;
+pub use scm_library::*;
+pub use scm_limits::*;
+pub use scm_native::*;
+pub use scm_port::*;
+pub use scm_print::*;
+pub use scm_record::*;
+pub use scm_sandbox::*;
 pub use scm_utils::*;
+pub use symbol::Symbol;
 pub use util::*;
+pub use vm::*;
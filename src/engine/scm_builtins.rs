@@ -1,6 +1,19 @@
 use crate::{
-    scm_core::*, scm_list_len, scm_list_to_vec, scm_utils::scm_is_list, typed_num::TypedNum,
+    frontend::{compile_datum, parse_file, read_datum},
+    scm_core::*,
+    scm_error::*,
+    scm_hash::{ScmHashKind, ScmHashTable},
+    scm_library::{import_set, standard_bindings, ScmBindings, ScmEnvironment},
+    scm_port::ScmPort,
+    scm_print::{format_number, scm_display, scm_write, ScmPrinter},
+    scm_utils::{
+        scm_eq, scm_equal_polled, scm_eqv, scm_is_true, scm_list_len_polled, scm_list_to_vec,
+    },
+    symbol::{symbol_size, Symbol},
+    typed_num::TypedNum,
+    vm::exec_callable,
 };
+use std::{cell::RefCell, cmp::Ordering, fmt, mem, path::PathBuf, rc::Rc};
 
 macro_rules! scm_builtin_impl {
     ($name:expr,$func:expr) => {
@@ -8,119 +21,1010 @@ macro_rules! scm_builtin_impl {
     };
 }
 
-pub const BUILTINS_LIST: &[(&str, ScmValue)] = &[
-    //
-    // System
-    //
-    scm_builtin_impl!("apply", |ctx, args| -> ScmValue {
-        assert!(args.len() == 2);
-        assert!(scm_is_list(&args[1]));
+macro_rules! scm_primitive_impl {
+    ($name:expr,$primitive:expr) => {
+        (
+            $name,
+            ScmValue::Procedure(ScmCallable::Primitive($primitive)),
+        )
+    };
+}
 
-        let proc = match &args[0] {
-            ScmValue::Procedure(proc) => proc,
-            _ => {
-                panic!("Only procedures can be called");
+fn gensym_prefix(args: &[ScmValue]) -> Result<String, ScmError> {
+    scm_ensure!(args.len() <= 1, "GENSYM accepts at most 1 argument");
+    match args.first() {
+        None => Ok(String::from("g")),
+        Some(ScmValue::String(s)) => Ok(s.borrow().clone()),
+        Some(ScmValue::Symbol(sym)) => Ok(String::from(sym.as_str())),
+        Some(_) => Err(scm_error!("Gensym prefix must be a String or Symbol")),
+    }
+}
+
+// Symbols are never freed, so the budget is checked before creating one
+fn reserve_symbol(ctx: &ScmExecContext, name: &str) -> Result<(), ScmError> {
+    ctx.reserve_alloc(symbol_size(name))
+}
+
+fn expect_error_object(name: &str, args: &[ScmValue]) -> Result<Rc<ScmErrorObject>, ScmError> {
+    scm_ensure!(args.len() == 1, "{} requires exactly 1 argument", name);
+    match &args[0] {
+        ScmValue::Error(err) => Ok(err.clone()),
+        _ => Err(scm_error!("{} requires argument of type Error", name)),
+    }
+}
+
+fn is_error_of_kind(args: &[ScmValue], kind: ScmErrorKind) -> ScmResult {
+    scm_ensure!(
+        args.len() == 1,
+        "Error type predicate requires exactly 1 argument"
+    );
+    Ok(ScmValue::Bool(
+        matches!(&args[0], ScmValue::Error(err) if err.kind == kind),
+    ))
+}
+
+pub(crate) fn builtin_value(name: &str) -> Option<&'static ScmValue> {
+    BUILTINS_LIST
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, val)| val)
+}
+
+// Hash tables support only the standard equality predicates, so that hashes
+// are consistent with them
+fn hash_kind_of(comparator: &ScmValue) -> Result<ScmHashKind, ScmError> {
+    let kinds = [
+        ("eq?", ScmHashKind::Eq),
+        ("eqv?", ScmHashKind::Eqv),
+        ("equal?", ScmHashKind::Equal),
+        ("string=?", ScmHashKind::String),
+    ];
+    for (name, kind) in kinds {
+        if builtin_value(name).is_some_and(|builtin| scm_eqv(builtin, comparator)) {
+            return Ok(kind);
+        }
+    }
+    Err(ScmError::new(
+        ScmErrorKind::Error,
+        String::from("Unsupported hash table equality predicate"),
+        vec![comparator.clone()],
+    ))
+}
+
+fn expect_hash_table(
+    name: &str,
+    args: &[ScmValue],
+    min_args: usize,
+    max_args: usize,
+) -> Result<Rc<RefCell<ScmHashTable>>, ScmError> {
+    scm_ensure!(
+        min_args <= args.len() && args.len() <= max_args,
+        "{} requires from {} to {} arguments",
+        name,
+        min_args,
+        max_args
+    );
+    match &args[0] {
+        ScmValue::HashTable(table) => Ok(table.clone()),
+        _ => Err(scm_error!("{} requires argument of type HashTable", name)),
+    }
+}
+
+// Entries of the hash table, copied so that procedures called on them can
+// change the table
+fn hash_table_entries(
+    ctx: &ScmExecContext,
+    table: &RefCell<ScmHashTable>,
+) -> Result<Vec<(ScmValue, ScmValue)>, ScmError> {
+    ctx.usage.tick_n(table.borrow().len())?;
+    Ok(table.borrow().entries())
+}
+
+fn expect_procedure<'a>(name: &str, val: &'a ScmValue) -> Result<&'a ScmCallable, ScmError> {
+    match val {
+        ScmValue::Procedure(proc) => Ok(proc),
+        _ => Err(scm_error!("{} requires argument of type Procedure", name)),
+    }
+}
+
+fn expect_string(name: &str, val: &ScmValue) -> Result<Rc<RefCell<String>>, ScmError> {
+    match val {
+        ScmValue::String(s) => Ok(s.clone()),
+        _ => Err(scm_error!("{} requires argument of type String", name)),
+    }
+}
+
+// Builtins looping over data tick the evaluation for every item, so they
+// stop soon after it is interrupted or runs out of time
+fn expect_list(
+    ctx: &ScmExecContext,
+    name: &str,
+    val: &ScmValue,
+) -> Result<Vec<ScmValue>, ScmError> {
+    let len = scm_list_len_polled(val, || ctx.usage.tick())?;
+    scm_ensure!(len.is_some(), "{} requires argument of type List", name);
+    let mut res = Vec::new();
+    let mut cur = val.clone();
+    while let ScmValue::DotPair(pair) = cur {
+        ctx.usage.tick()?;
+        res.push(pair.borrow().car.clone());
+        cur = pair.borrow().cdr.clone();
+    }
+    Ok(res)
+}
+
+// List of the items ending with the tail, the pairs are checked against the
+// allocation budget first
+fn build_list(ctx: &ScmExecContext, items: Vec<ScmValue>, tail: ScmValue) -> ScmResult {
+    reserve_pairs(ctx, items.len())?;
+    let mut res = tail;
+    for item in items.into_iter().rev() {
+        ctx.usage.tick()?;
+        res = ScmValue::cons(item, res);
+    }
+    Ok(res)
+}
+
+// Equivalence predicates of `member`, `assoc` and `delete`
+type ScmEquivalence = fn(&ScmExecContext, &ScmValue, &ScmValue) -> Result<bool, ScmError>;
+
+fn is_eq(_: &ScmExecContext, a: &ScmValue, b: &ScmValue) -> Result<bool, ScmError> {
+    Ok(scm_eq(a, b))
+}
+
+fn is_eqv(_: &ScmExecContext, a: &ScmValue, b: &ScmValue) -> Result<bool, ScmError> {
+    Ok(scm_eqv(a, b))
+}
+
+fn is_equal(ctx: &ScmExecContext, a: &ScmValue, b: &ScmValue) -> Result<bool, ScmError> {
+    scm_equal_polled(a, b, || ctx.usage.tick())
+}
+
+// Applies car (a) and cdr (d) from the end of the path: cadr is car of cdr
+fn cxr(name: &str, path: &str, args: &[ScmValue]) -> ScmResult {
+    scm_ensure!(args.len() == 1, "{} requires exactly 1 argument", name);
+    let mut val = args[0].clone();
+    for op in path.chars().rev() {
+        let next = match (&val, op) {
+            (ScmValue::DotPair(pair), 'a') => pair.borrow().car.clone(),
+            (ScmValue::DotPair(pair), _) => pair.borrow().cdr.clone(),
+            _ => return Err(scm_error!("{} requires argument of type DotPair", name)),
+        };
+        val = next;
+    }
+    Ok(val)
+}
+
+// Pair of the list whose car satisfies the predicate, or #f if there is none
+fn find_tail(
+    name: &str,
+    list: &ScmValue,
+    mut pred: impl FnMut(&ScmValue) -> Result<bool, ScmError>,
+) -> ScmResult {
+    let mut cur = list.clone();
+    loop {
+        let next = match &cur {
+            ScmValue::Nil => return Ok(ScmValue::Bool(false)),
+            ScmValue::DotPair(pair) => {
+                let item = pair.borrow().car.clone();
+                if pred(&item)? {
+                    return Ok(cur);
+                }
+                pair.borrow().cdr.clone()
             }
+            _ => return Err(scm_error!("{} requires argument of type List", name)),
         };
+        cur = next;
+    }
+}
+
+// (member obj list [compare]), the compare procedure replaces `eq`
+fn member(
+    ctx: &mut ScmExecContext,
+    name: &str,
+    args: &[ScmValue],
+    eq: ScmEquivalence,
+) -> ScmResult {
+    match args.get(2) {
+        Some(compare) => {
+            let compare = expect_procedure(name, compare)?;
+            find_tail(name, &args[1], |item| {
+                ctx.usage.tick()?;
+                let res = exec_callable(ctx, compare, &[args[0].clone(), item.clone()])?;
+                Ok(scm_is_true(&res))
+            })
+        }
+        None => find_tail(name, &args[1], |item| {
+            ctx.usage.tick()?;
+            eq(ctx, &args[0], item)
+        }),
+    }
+}
+
+// (assoc key alist [compare]), returns the first pair with the key
+fn assoc(ctx: &mut ScmExecContext, name: &str, args: &[ScmValue], eq: ScmEquivalence) -> ScmResult {
+    let compare = args
+        .get(2)
+        .map(|compare| expect_procedure(name, compare))
+        .transpose()?;
+    let tail = find_tail(name, &args[1], |item| {
+        let ScmValue::DotPair(entry) = item else {
+            return Err(scm_error!("{} requires list of pairs", name));
+        };
+        let key = entry.borrow().car.clone();
+        ctx.usage.tick()?;
+        match compare {
+            Some(compare) => Ok(scm_is_true(&exec_callable(
+                ctx,
+                compare,
+                &[args[0].clone(), key],
+            )?)),
+            None => eq(ctx, &args[0], &key),
+        }
+    })?;
+    match tail {
+        ScmValue::DotPair(pair) => Ok(pair.borrow().car.clone()),
+        _ => Ok(tail),
+    }
+}
+
+// Pair reached after `k` cdrs
+fn list_tail(ctx: &ScmExecContext, name: &str, list: &ScmValue, k: &ScmValue) -> ScmResult {
+    let mut cur = list.clone();
+    for _ in 0..expect_index(name, k)? {
+        ctx.usage.tick()?;
+        let next = match &cur {
+            ScmValue::DotPair(pair) => pair.borrow().cdr.clone(),
+            _ => return Err(scm_error!("{} index out of range", name)),
+        };
+        cur = next;
+    }
+    Ok(cur)
+}
+
+// Checks the allocation budget before creating `count` pairs
+fn reserve_pairs(ctx: &ScmExecContext, count: usize) -> Result<(), ScmError> {
+    ctx.reserve_alloc(count.saturating_mul(mem::size_of::<ScmPair>()))
+}
+
+// Prints into the port piece by piece, so the output is checked as it grows
+// instead of being built in memory first. Printing a large value takes long,
+// so the interrupt flag and the deadline are checked too.
+fn print_to_port(ctx: &ScmExecContext, port: &ScmPort, printer: ScmPrinter) -> ScmResult {
+    struct PortWriter<'a> {
+        ctx: &'a ScmExecContext,
+        port: &'a ScmPort,
+        err: Option<ScmError>,
+    }
+
+    impl fmt::Write for PortWriter<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.ctx
+                .usage
+                .tick()
+                .and_then(|()| self.port.write_tracked(self.ctx, s))
+                .map_err(|err| {
+                    self.err = Some(err);
+                    fmt::Error
+                })
+        }
+    }
+
+    let printer = printer.with_labels(|| ctx.usage.tick())?;
+    let mut writer = PortWriter {
+        ctx,
+        port,
+        err: None,
+    };
+    match fmt::write(&mut writer, format_args!("{}", printer)) {
+        Ok(()) => Ok(ScmValue::Nil),
+        Err(_) => Err(writer
+            .err
+            .unwrap_or_else(|| scm_error!("Value cannot be printed"))),
+    }
+}
+
+// Output of the string port as a new string
+fn port_output(ctx: &ScmExecContext, port: &ScmPort) -> ScmResult {
+    let output = port.output()?;
+    ctx.usage.tick_n(output.len())?;
+    ctx.reserve_alloc(output.len())?;
+    Ok(ScmValue::from_string(output))
+}
+
+// Strings of a size given by the arguments are allocated fallibly, so that
+// a huge size raises an error instead of aborting the process
+fn try_string(name: &str, bytes: usize) -> Result<String, ScmError> {
+    let mut res = String::new();
+    match res.try_reserve_exact(bytes) {
+        Ok(()) => Ok(res),
+        Err(_) => Err(scm_error!("{}: cannot allocate {} bytes", name, bytes)),
+    }
+}
+
+fn expect_char(name: &str, val: &ScmValue) -> Result<char, ScmError> {
+    match val {
+        ScmValue::Char(c) => Ok(*c),
+        _ => Err(scm_error!("{} requires argument of type Char", name)),
+    }
+}
+
+fn expect_index(name: &str, val: &ScmValue) -> Result<usize, ScmError> {
+    match val {
+        ScmValue::Number(TypedNum::Integer(idx)) if *idx >= 0 => Ok(*idx as usize),
+        _ => Err(scm_error!("{} requires non-negative Integer index", name)),
+    }
+}
+
+fn bindings_environment(bindings: ScmBindings) -> ScmValue {
+    ScmValue::Environment(Rc::new(ScmEnvironment::Bindings(Rc::new(
+        bindings.into_iter().collect(),
+    ))))
+}
+
+fn expect_report_version(name: &str, args: &[ScmValue]) -> Result<(), ScmError> {
+    scm_ensure!(args.len() == 1, "{} requires exactly 1 argument", name);
+    scm_ensure!(
+        matches!(args[0], ScmValue::Number(TypedNum::Integer(5))),
+        "{} supports only version 5",
+        name
+    );
+    Ok(())
+}
+
+fn expect_port(name: &str, val: &ScmValue) -> Result<Rc<ScmPort>, ScmError> {
+    match val {
+        ScmValue::Port(port) => Ok(port.clone()),
+        _ => Err(scm_error!("{} requires argument of type Port", name)),
+    }
+}
+
+// Port passed as the optional argument with the index, by default the
+// current input port
+fn input_port(
+    ctx: &ScmExecContext,
+    name: &str,
+    args: &[ScmValue],
+    idx: usize,
+) -> Result<Rc<ScmPort>, ScmError> {
+    let port = match args.get(idx) {
+        Some(port) => expect_port(name, port)?,
+        None => expect_port(name, &ctx.dynamic_state.param_value(&ctx.ports.input))?,
+    };
+    scm_ensure!(port.input, "{} requires input port", name);
+    Ok(port)
+}
+
+// Same for output ports, by default the current output port
+fn output_port(
+    ctx: &ScmExecContext,
+    name: &str,
+    args: &[ScmValue],
+    idx: usize,
+) -> Result<Rc<ScmPort>, ScmError> {
+    let port = match args.get(idx) {
+        Some(port) => expect_port(name, port)?,
+        None => expect_port(name, &ctx.dynamic_state.param_value(&ctx.ports.output))?,
+    };
+    scm_ensure!(!port.input, "{} requires output port", name);
+    Ok(port)
+}
+
+fn expect_path(name: &str, val: &ScmValue) -> Result<PathBuf, ScmError> {
+    Ok(PathBuf::from(&*expect_string(name, val)?.borrow()))
+}
+
+// Path of a file to open, checked against the file access of the context
+fn accessible_path(ctx: &ScmExecContext, name: &str, val: &ScmValue) -> Result<PathBuf, ScmError> {
+    let path = expect_path(name, val)?;
+    ctx.fs_access.check(&path)?;
+    Ok(path)
+}
+
+// Calls the thunk with the parameter bound to the value, as `parameterize`
+// does
+fn call_with_param(
+    ctx: &mut ScmExecContext,
+    param: Rc<ScmParameter>,
+    value: ScmValue,
+    thunk: &ScmCallable,
+) -> ScmResult {
+    let saved = ctx.dynamic_state.clone();
+    ctx.dynamic_state.params = Some(Rc::new(ScmParamBinding {
+        param,
+        value,
+        parent: saved.params.clone(),
+    }));
+    let res = exec_callable(ctx, thunk, &[]);
+    ctx.dynamic_state = saved;
+    res
+}
+
+fn char_or_eof(c: Option<char>) -> ScmValue {
+    c.map_or(ScmValue::Eof, ScmValue::Char)
+}
+
+// Strings are scanned in chunks of this many bytes, with a tick per byte
+const STRING_CHUNK: usize = 4096;
+
+// Number of chars in the string
+fn char_count(ctx: &ScmExecContext, s: &str) -> Result<usize, ScmError> {
+    let mut res = 0;
+    for chunk in s.as_bytes().chunks(STRING_CHUNK) {
+        ctx.usage.tick_n(chunk.len())?;
+        // Continuation bytes of UTF-8 are 0b10xxxxxx, each char has one
+        // byte of other kind
+        res += chunk.iter().filter(|byte| (**byte as i8) >= -0x40).count();
+    }
+    Ok(res)
+}
+
+// Byte offset of the position `cnt` chars after the byte offset `from`, None
+// if the string ends before. The end of the string is a position too.
+fn char_offset(
+    ctx: &ScmExecContext,
+    s: &str,
+    from: usize,
+    cnt: usize,
+) -> Result<Option<usize>, ScmError> {
+    let mut offsets = s[from..]
+        .char_indices()
+        .map(|(pos, _)| from + pos)
+        .chain(std::iter::once(s.len()));
+    for _ in 0..cnt {
+        ctx.usage.tick()?;
+        if offsets.next().is_none() {
+            return Ok(None);
+        }
+    }
+    Ok(offsets.next())
+}
+
+// Strings are indexed by characters. Returns byte offsets of the optional
+// [start [end]] character positions, which default to the whole string.
+fn byte_range(
+    ctx: &ScmExecContext,
+    name: &str,
+    s: &str,
+    bounds: &[ScmValue],
+) -> Result<(usize, usize), ScmError> {
+    let start = match bounds.first() {
+        Some(start) => expect_index(name, start)?,
+        None => 0,
+    };
+    let end = match bounds.get(1) {
+        Some(end) => Some(expect_index(name, end)?),
+        None => None,
+    };
+    let start_pos = char_offset(ctx, s, 0, start)?;
+    let end_pos = match (start_pos, end) {
+        (Some(pos), Some(end)) if start <= end => char_offset(ctx, s, pos, end - start)?,
+        (Some(_), None) => Some(s.len()),
+        _ => None,
+    };
+    match (start_pos, end_pos) {
+        (Some(start_pos), Some(end_pos)) => Ok((start_pos, end_pos)),
+        _ => Err(scm_error!(
+            "{}: range {}..{} is out of string bounds",
+            name,
+            start,
+            match end {
+                Some(end) => end.to_string(),
+                None => String::from("end"),
+            }
+        )),
+    }
+}
+
+// Case mapping of the string, applied to chunks of it. Lowercasing maps
+// sigma by its neighbours, so with `context` the chunks end between two
+// ASCII chars that are not case-ignorable, which the mapping of sigma
+// doesn't look past.
+fn map_case(
+    ctx: &ScmExecContext,
+    s: &str,
+    context: bool,
+    mapping: fn(&str) -> String,
+) -> Result<String, ScmError> {
+    let is_boundary = |pos: usize| {
+        let bytes = s.as_bytes();
+        let solid = |byte: u8| byte.is_ascii() && !b"'.:^`".contains(&byte);
+        s.is_char_boundary(pos) && (!context || solid(bytes[pos - 1]) && solid(bytes[pos]))
+    };
+    let mut res = String::new();
+    let mut start = 0;
+    while start < s.len() {
+        let mut end = (start + STRING_CHUNK).min(s.len());
+        while end < s.len() && !is_boundary(end) {
+            end += 1;
+        }
+        ctx.usage.tick_n(end - start)?;
+        res.push_str(&mapping(&s[start..end]));
+        start = end;
+    }
+    Ok(res)
+}
+
+// Unicode has no simple per-character case folding in std, case mapping in
+// both directions folds special cases like ß
+fn fold_case(s: &str) -> String {
+    s.to_uppercase().to_lowercase()
+}
+
+fn upcase(s: &str) -> String {
+    s.to_uppercase()
+}
+
+fn downcase(s: &str) -> String {
+    s.to_lowercase()
+}
+
+// First code points of the Unicode decimal digit (Nd) ranges, each of them
+// holds digits from 0 to 9
+const DIGIT_ZEROS: &[u32] = &[
+    0x30, 0x660, 0x6F0, 0x7C0, 0x966, 0x9E6, 0xA66, 0xAE6, 0xB66, 0xBE6, 0xC66, 0xCE6, 0xD66,
+    0xDE6, 0xE50, 0xED0, 0xF20, 0x1040, 0x1090, 0x17E0, 0x1810, 0x1946, 0x19D0, 0x1A80, 0x1A90,
+    0x1B50, 0x1BB0, 0x1C40, 0x1C50, 0xA620, 0xA8D0, 0xA900, 0xA9D0, 0xA9F0, 0xAA50, 0xABF0, 0xFF10,
+    0x104A0, 0x10D30, 0x11066, 0x110F0, 0x11136, 0x111D0, 0x112F0, 0x11450, 0x114D0, 0x11650,
+    0x116C0, 0x11730, 0x118E0, 0x11950, 0x11C50, 0x11D50, 0x11DA0, 0x16A60, 0x16AC0, 0x16B50,
+    0x1D7CE, 0x1D7D8, 0x1D7E2, 0x1D7EC, 0x1D7F6, 0x1E140, 0x1E2F0, 0x1E950, 0x1FBF0,
+];
 
-        let call_args = &scm_list_to_vec(&args[1]);
+fn digit_value(c: char) -> Option<u32> {
+    DIGIT_ZEROS
+        .iter()
+        .find(|zero| (**zero..**zero + 10).contains(&(c as u32)))
+        .map(|zero| c as u32 - zero)
+}
+
+// Characters are mapped one to one, so mappings to several characters
+// (like ß to SS) leave the character as is
+fn single_char(mut mapping: impl Iterator<Item = char>, c: char) -> char {
+    match (mapping.next(), mapping.next()) {
+        (Some(mapped), None) => mapped,
+        _ => c,
+    }
+}
+
+fn char_upcase(c: char) -> char {
+    single_char(c.to_uppercase(), c)
+}
+
+fn char_downcase(c: char) -> char {
+    single_char(c.to_lowercase(), c)
+}
+
+fn char_foldcase(c: char) -> char {
+    char_downcase(char_upcase(c))
+}
+
+fn compare_chars(name: &str, args: &[ScmValue], ci: bool, pred: fn(Ordering) -> bool) -> ScmResult {
+    scm_ensure!(!args.is_empty(), "{} requires at least 1 argument", name);
+    let mut chars = Vec::new();
+    for arg in args.iter() {
+        let c = expect_char(name, arg)?;
+        chars.push(if ci { char_foldcase(c) } else { c });
+    }
+    Ok(ScmValue::Bool(
+        chars.windows(2).all(|pair| pred(pair[0].cmp(&pair[1]))),
+    ))
+}
+
+fn char_predicate(name: &str, args: &[ScmValue], pred: fn(char) -> bool) -> ScmResult {
+    scm_ensure!(args.len() == 1, "{} requires exactly 1 argument", name);
+    Ok(ScmValue::Bool(pred(expect_char(name, &args[0])?)))
+}
+
+fn char_mapping(name: &str, args: &[ScmValue], mapping: fn(char) -> char) -> ScmResult {
+    scm_ensure!(args.len() == 1, "{} requires exactly 1 argument", name);
+    Ok(ScmValue::Char(mapping(expect_char(name, &args[0])?)))
+}
+
+fn compare_strings(
+    ctx: &ScmExecContext,
+    name: &str,
+    args: &[ScmValue],
+    ci: bool,
+    pred: fn(Ordering) -> bool,
+) -> ScmResult {
+    scm_ensure!(!args.is_empty(), "{} requires at least 1 argument", name);
+    let mut strings = Vec::new();
+    for arg in args.iter() {
+        let s = expect_string(name, arg)?.borrow().clone();
+        strings.push(match ci {
+            true => map_case(ctx, &s, true, fold_case)?,
+            false => {
+                ctx.usage.tick_n(s.len())?;
+                s
+            }
+        });
+    }
+    Ok(ScmValue::Bool(
+        strings.windows(2).all(|pair| pred(pair[0].cmp(&pair[1]))),
+    ))
+}
+
+fn expect_radix(name: &str, val: Option<&ScmValue>) -> Result<u32, ScmError> {
+    match val {
+        None => Ok(10),
+        Some(ScmValue::Number(TypedNum::Integer(radix))) if (2..=36).contains(radix) => {
+            Ok(*radix as u32)
+        }
+        Some(_) => Err(scm_error!("{} requires radix from 2 to 36", name)),
+    }
+}
 
-        exec_callable(ctx, proc, call_args)
+pub const BUILTINS_LIST: &[(&str, ScmValue)] = &[
+    //
+    // System
+    //
+    scm_primitive_impl!("apply", ScmPrimitive::Apply),
+    scm_primitive_impl!("call-with-current-continuation", ScmPrimitive::CallCC),
+    scm_primitive_impl!("call/cc", ScmPrimitive::CallCC),
+    scm_primitive_impl!("call-with-escape-continuation", ScmPrimitive::CallEC),
+    scm_primitive_impl!("call/ec", ScmPrimitive::CallEC),
+    scm_primitive_impl!("dynamic-wind", ScmPrimitive::DynamicWind),
+    scm_builtin_impl!("make-parameter", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1 || args.len() == 2,
+            "Make-parameter requires 1 or 2 arguments"
+        );
+        let converter = match args.get(1) {
+            None => None,
+            Some(ScmValue::Procedure(converter)) => Some(converter.clone()),
+            Some(_) => return Err(scm_error!("Parameter converter must be a procedure")),
+        };
+        let value = match &converter {
+            Some(converter) => exec_callable(ctx, converter, &args[..1])?,
+            None => args[0].clone(),
+        };
+        Ok(ScmValue::Procedure(ScmCallable::Parameter(Rc::new(
+            ScmParameter { value, converter },
+        ))))
     }),
-    scm_builtin_impl!("display", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        print!("{:?}", args[0]);
-        ScmValue::Nil
+    //
+    // Evaluation
+    //
+    scm_primitive_impl!("eval", ScmPrimitive::Prelude("eval")),
+    scm_builtin_impl!("environment", |ctx, args| -> ScmResult {
+        let mut bindings = Vec::new();
+        for spec in args.iter() {
+            bindings.extend(import_set(ctx, spec)?);
+        }
+        Ok(bindings_environment(bindings))
+    }),
+    scm_primitive_impl!("load", ScmPrimitive::Prelude("load")),
+    scm_builtin_impl!("interaction-environment", |_, args| -> ScmResult {
+        scm_ensure!(
+            args.is_empty(),
+            "INTERACTION-ENVIRONMENT accepts no arguments"
+        );
+        Ok(ScmValue::Environment(Rc::new(ScmEnvironment::Interaction)))
     }),
-    scm_builtin_impl!("error", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        eprint!("{:?}", args[0]);
-        ScmValue::Nil
+    scm_builtin_impl!("scheme-report-environment", |ctx, args| -> ScmResult {
+        expect_report_version("SCHEME-REPORT-ENVIRONMENT", args)?;
+        Ok(bindings_environment(standard_bindings(ctx)))
     }),
-    scm_builtin_impl!("newline", |_, args| -> ScmValue {
-        assert!(args.is_empty());
-        println!("");
-        ScmValue::Nil
+    scm_builtin_impl!("null-environment", |_, args| -> ScmResult {
+        // Only the syntax, which is available in every environment
+        expect_report_version("NULL-ENVIRONMENT", args)?;
+        Ok(bindings_environment(Vec::new()))
+    }),
+    scm_builtin_impl!("set-interrupt-handler!", |ctx, args| -> ScmResult {
+        // The thunk is called when the host interrupts the evaluation, #f
+        // removes it
+        scm_ensure!(
+            args.len() == 1,
+            "SET-INTERRUPT-HANDLER! requires exactly 1 argument"
+        );
+        ctx.interrupt_handler = match &args[0] {
+            ScmValue::Bool(false) => None,
+            val => Some(expect_procedure("SET-INTERRUPT-HANDLER!", val)?.clone()),
+        };
+        Ok(ScmValue::Nil)
+    }),
+    //
+    // Input and output
+    //
+    scm_builtin_impl!("display", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            (1..=2).contains(&args.len()),
+            "DISPLAY requires 1 or 2 arguments"
+        );
+        let port = output_port(ctx, "DISPLAY", args, 1)?;
+        print_to_port(ctx, &port, scm_display(&args[0]))
+    }),
+    scm_builtin_impl!("write", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            (1..=2).contains(&args.len()),
+            "WRITE requires 1 or 2 arguments"
+        );
+        let port = output_port(ctx, "WRITE", args, 1)?;
+        print_to_port(ctx, &port, scm_write(&args[0]))
+    }),
+    scm_builtin_impl!("newline", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() <= 1, "NEWLINE accepts at most 1 argument");
+        output_port(ctx, "NEWLINE", args, 0)?.write_tracked(ctx, "\n")?;
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("write-char", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            (1..=2).contains(&args.len()),
+            "WRITE-CHAR requires 1 or 2 arguments"
+        );
+        let c = expect_char("WRITE-CHAR", &args[0])?;
+        let port = output_port(ctx, "WRITE-CHAR", args, 1)?;
+        port.write_tracked(ctx, c.encode_utf8(&mut [0; 4]))?;
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("write-string", |ctx, args| -> ScmResult {
+        // (write-string string [port [start [end]]])
+        scm_ensure!(
+            (1..=4).contains(&args.len()),
+            "WRITE-STRING requires from 1 to 4 arguments"
+        );
+        let s = expect_string("WRITE-STRING", &args[0])?;
+        let port = output_port(ctx, "WRITE-STRING", args, 1)?;
+        let s = s.borrow();
+        let (start, end) = byte_range(ctx, "WRITE-STRING", &s, args.get(2..).unwrap_or(&[]))?;
+        port.write_tracked(ctx, &s[start..end])?;
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("flush-output-port", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() <= 1,
+            "FLUSH-OUTPUT-PORT accepts at most 1 argument"
+        );
+        output_port(ctx, "FLUSH-OUTPUT-PORT", args, 0)?.flush()?;
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("read", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() <= 1, "READ accepts at most 1 argument");
+        let port = input_port(ctx, "READ", args, 0)?;
+        read_datum(ctx, &port)
+    }),
+    scm_builtin_impl!("read-char", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() <= 1, "READ-CHAR accepts at most 1 argument");
+        let port = input_port(ctx, "READ-CHAR", args, 0)?;
+        Ok(char_or_eof(port.read_char()?))
+    }),
+    scm_builtin_impl!("peek-char", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() <= 1, "PEEK-CHAR accepts at most 1 argument");
+        let port = input_port(ctx, "PEEK-CHAR", args, 0)?;
+        Ok(char_or_eof(port.peek_char()?))
+    }),
+    scm_builtin_impl!("read-line", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() <= 1, "READ-LINE accepts at most 1 argument");
+        let port = input_port(ctx, "READ-LINE", args, 0)?;
+        let line = port.read_line()?;
+        ctx.usage.tick_n(line.as_ref().map_or(0, String::len))?;
+        Ok(line.map_or(ScmValue::Eof, ScmValue::from_string))
+    }),
+    scm_builtin_impl!("read-string", |ctx, args| -> ScmResult {
+        // (read-string k [port])
+        scm_ensure!(
+            (1..=2).contains(&args.len()),
+            "READ-STRING requires 1 or 2 arguments"
+        );
+        let cnt = expect_index("READ-STRING", &args[0])?;
+        let port = input_port(ctx, "READ-STRING", args, 1)?;
+        let s = port.read_string(cnt)?;
+        ctx.usage.tick_n(s.as_ref().map_or(0, String::len))?;
+        Ok(s.map_or(ScmValue::Eof, ScmValue::from_string))
+    }),
+    scm_builtin_impl!("char-ready?", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() <= 1, "CHAR-READY? accepts at most 1 argument");
+        let port = input_port(ctx, "CHAR-READY?", args, 0)?;
+        Ok(ScmValue::Bool(port.char_ready()?))
+    }),
+    scm_builtin_impl!("open-input-file", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1,
+            "OPEN-INPUT-FILE requires exactly 1 argument"
+        );
+        let path = accessible_path(ctx, "OPEN-INPUT-FILE", &args[0])?;
+        Ok(ScmValue::Port(Rc::new(ScmPort::open_input_file(&path)?)))
+    }),
+    scm_builtin_impl!("open-output-file", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1,
+            "OPEN-OUTPUT-FILE requires exactly 1 argument"
+        );
+        let path = accessible_path(ctx, "OPEN-OUTPUT-FILE", &args[0])?;
+        Ok(ScmValue::Port(Rc::new(ScmPort::open_output_file(&path)?)))
+    }),
+    scm_builtin_impl!("call-with-output-file", |ctx, args| -> ScmResult {
+        // The port is closed when the procedure returns
+        scm_ensure!(
+            args.len() == 2,
+            "CALL-WITH-OUTPUT-FILE requires exactly 2 arguments"
+        );
+        let path = accessible_path(ctx, "CALL-WITH-OUTPUT-FILE", &args[0])?;
+        let proc = expect_procedure("CALL-WITH-OUTPUT-FILE", &args[1])?;
+        let port = Rc::new(ScmPort::open_output_file(&path)?);
+        let res = exec_callable(ctx, proc, &[ScmValue::Port(port.clone())]);
+        port.close()?;
+        res
+    }),
+    scm_builtin_impl!("with-output-to-file", |ctx, args| -> ScmResult {
+        // The file is the current output port while the thunk runs
+        scm_ensure!(
+            args.len() == 2,
+            "WITH-OUTPUT-TO-FILE requires exactly 2 arguments"
+        );
+        let path = accessible_path(ctx, "WITH-OUTPUT-TO-FILE", &args[0])?;
+        let thunk = expect_procedure("WITH-OUTPUT-TO-FILE", &args[1])?;
+        let port = Rc::new(ScmPort::open_output_file(&path)?);
+        let param = ctx.ports.output.clone();
+        let res = call_with_param(ctx, param, ScmValue::Port(port.clone()), thunk);
+        port.close()?;
+        res
+    }),
+    scm_builtin_impl!("open-input-string", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1,
+            "OPEN-INPUT-STRING requires exactly 1 argument"
+        );
+        let s = expect_string("OPEN-INPUT-STRING", &args[0])?;
+        ctx.usage.tick_n(s.borrow().len())?;
+        let port = ScmPort::input_string(&s.borrow());
+        Ok(ScmValue::Port(Rc::new(port)))
+    }),
+    scm_builtin_impl!("open-output-string", |_, args| -> ScmResult {
+        scm_ensure!(args.is_empty(), "OPEN-OUTPUT-STRING accepts no arguments");
+        Ok(ScmValue::Port(Rc::new(ScmPort::output_string())))
+    }),
+    scm_builtin_impl!("get-output-string", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1,
+            "GET-OUTPUT-STRING requires exactly 1 argument"
+        );
+        let port = expect_port("GET-OUTPUT-STRING", &args[0])?;
+        port_output(ctx, &port)
+    }),
+    scm_builtin_impl!("call-with-output-string", |ctx, args| -> ScmResult {
+        // Returns the output of the procedure called with a string port
+        scm_ensure!(
+            args.len() == 1,
+            "CALL-WITH-OUTPUT-STRING requires exactly 1 argument"
+        );
+        let proc = expect_procedure("CALL-WITH-OUTPUT-STRING", &args[0])?;
+        let port = Rc::new(ScmPort::output_string());
+        exec_callable(ctx, proc, &[ScmValue::Port(port.clone())])?;
+        port_output(ctx, &port)
+    }),
+    scm_builtin_impl!("with-output-to-string", |ctx, args| -> ScmResult {
+        // Returns everything the thunk printed to the current output port
+        scm_ensure!(
+            args.len() == 1,
+            "WITH-OUTPUT-TO-STRING requires exactly 1 argument"
+        );
+        let thunk = expect_procedure("WITH-OUTPUT-TO-STRING", &args[0])?;
+        let port = Rc::new(ScmPort::output_string());
+        let param = ctx.ports.output.clone();
+        call_with_param(ctx, param, ScmValue::Port(port.clone()), thunk)?;
+        port_output(ctx, &port)
+    }),
+    scm_builtin_impl!("close-port", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "CLOSE-PORT requires exactly 1 argument");
+        expect_port("CLOSE-PORT", &args[0])?.close()?;
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("eof-object", |_, args| -> ScmResult {
+        scm_ensure!(args.is_empty(), "EOF-OBJECT accepts no arguments");
+        Ok(ScmValue::Eof)
+    }),
+    //
+    // Exceptions
+    //
+    scm_primitive_impl!("raise", ScmPrimitive::Raise),
+    scm_primitive_impl!("raise-continuable", ScmPrimitive::RaiseContinuable),
+    scm_primitive_impl!("with-exception-handler", ScmPrimitive::WithExceptionHandler),
+    scm_builtin_impl!("error", |_, args| -> ScmResult {
+        scm_ensure!(!args.is_empty(), "ERROR requires at least 1 argument");
+        let message = match &args[0] {
+            ScmValue::String(s) => s.borrow().clone(),
+            _ => return Err(scm_error!("Error message must be a String")),
+        };
+        Err(ScmError::new(
+            ScmErrorKind::Error,
+            message,
+            args[1..].to_vec(),
+        ))
+    }),
+    scm_builtin_impl!("error-object?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "ERROR-OBJECT? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::Error(_))))
+    }),
+    scm_builtin_impl!("error-object-message", |_, args| -> ScmResult {
+        let err = expect_error_object("ERROR-OBJECT-MESSAGE", args)?;
+        Ok(ScmValue::new_string(&err.message))
+    }),
+    scm_builtin_impl!("error-object-irritants", |ctx, args| -> ScmResult {
+        let err = expect_error_object("ERROR-OBJECT-IRRITANTS", args)?;
+        build_list(ctx, err.irritants.clone(), ScmValue::Nil)
+    }),
+    scm_builtin_impl!("file-error?", |_, args| -> ScmResult {
+        is_error_of_kind(args, ScmErrorKind::File)
+    }),
+    scm_builtin_impl!("read-error?", |_, args| -> ScmResult {
+        is_error_of_kind(args, ScmErrorKind::Read)
     }),
     //
     // Arithmetics
     //
-    scm_builtin_impl!("+", |_, args| -> ScmValue {
-        if args.is_empty() {
-            ScmValue::Number(TypedNum::Integer(0))
-        } else {
-            let mut res = TypedNum::Integer(0);
-            for arg in args.iter() {
-                if let ScmValue::Number(val) = arg {
-                    res = res + *val;
-                } else {
-                    panic!("Adding non numeric values");
-                }
+    scm_builtin_impl!("+", |_, args| -> ScmResult {
+        let mut res = TypedNum::Integer(0);
+        for arg in args.iter() {
+            if let ScmValue::Number(val) = arg {
+                res = res + *val;
+            } else {
+                return Err(scm_error!("Adding non numeric values"));
             }
-            ScmValue::Number(res)
         }
+        Ok(ScmValue::Number(res))
     }),
-    scm_builtin_impl!("-", |_, args| -> ScmValue {
+    scm_builtin_impl!("-", |_, args| -> ScmResult {
         if args.is_empty() {
-            return ScmValue::Number(TypedNum::Integer(0)); // TODO
+            return Ok(ScmValue::Number(TypedNum::Integer(0))); // TODO
         }
 
         if args.len() == 1 {
             if let ScmValue::Number(n) = args[0] {
-                return ScmValue::Number(-n);
+                return Ok(ScmValue::Number(-n));
             } else {
-                panic!("Unsupported value");
+                return Err(scm_error!("Subtracting non numeric values"));
             }
         }
 
         let mut res = if let ScmValue::Number(n) = args[0] {
             n
         } else {
-            panic!("Unsupported value");
+            return Err(scm_error!("Subtracting non numeric values"));
         };
 
         for arg in args.iter().skip(1) {
             if let ScmValue::Number(n) = arg {
                 res = res - *n;
             } else {
-                panic!("Unsupported value");
+                return Err(scm_error!("Subtracting non numeric values"));
             }
         }
 
-        ScmValue::Number(res)
+        Ok(ScmValue::Number(res))
     }),
-    scm_builtin_impl!("*", |_, args| -> ScmValue {
+    scm_builtin_impl!("*", |_, args| -> ScmResult {
         if args.is_empty() {
-            ScmValue::Number(TypedNum::Integer(1))
+            Ok(ScmValue::Number(TypedNum::Integer(1)))
         } else {
             let mut res = TypedNum::Integer(0);
             for arg in args.iter() {
                 if let ScmValue::Number(val) = arg {
                     res = res * *val;
                 } else {
-                    panic!("Multiplying non numeric values");
+                    return Err(scm_error!("Multiplying non numeric values"));
                 }
             }
-            ScmValue::Number(res)
+            Ok(ScmValue::Number(res))
         }
     }),
-    scm_builtin_impl!("/", |_, args| -> ScmValue {
+    scm_builtin_impl!("/", |_, args| -> ScmResult {
         if args.is_empty() {
-            return ScmValue::Number(TypedNum::Integer(0)); // TODO
+            return Ok(ScmValue::Number(TypedNum::Integer(0))); // TODO
         }
 
         if args.len() == 1 {
             if let ScmValue::Number(n) = args[0] {
-                return ScmValue::Number(TypedNum::Float(1f64) / n);
+                return Ok(ScmValue::Number(TypedNum::Float(1f64) / n));
             } else {
-                panic!("Unsupported value");
+                return Err(scm_error!("Dividing non numeric values"));
             }
         }
 
         let numer = if let ScmValue::Number(n) = args[0] {
             n
         } else {
-            panic!("Unsupported value");
+            return Err(scm_error!("Dividing non numeric values"));
         };
 
         let mut denom = TypedNum::Float(1f64);
@@ -128,90 +1032,1002 @@ pub const BUILTINS_LIST: &[(&str, ScmValue)] = &[
             if let ScmValue::Number(n) = arg {
                 denom = denom * *n;
             } else {
-                panic!("Unsupported value");
+                return Err(scm_error!("Dividing non numeric values"));
             }
         }
 
-        ScmValue::Number(numer / denom)
+        Ok(ScmValue::Number(numer / denom))
     }),
-    scm_builtin_impl!("abs", |_, args| -> ScmValue {
-        assert!(args.len() == 1, "ABS requires exactly 1 argument");
+    scm_builtin_impl!("abs", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "ABS requires exactly 1 argument");
         if let ScmValue::Number(val) = args[0] {
-            ScmValue::Number(val.abs())
+            Ok(ScmValue::Number(val.abs()))
         } else {
-            panic!("ABS requires numeric argument");
+            Err(scm_error!("ABS requires numeric argument"))
         }
     }),
     //
     // Comparison
     //
-    scm_builtin_impl!("=", |_, args| -> ScmValue {
-        assert!(args.len() == 2);
-        if let ScmValue::Number(v1) = args[0] {
-            if let ScmValue::Number(v2) = args[1] {
-                ScmValue::Bool(v1 == v2)
-            } else {
-                panic!("");
+    scm_builtin_impl!("=", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "EQ requires exactly 2 arguments");
+        if let (ScmValue::Number(v1), ScmValue::Number(v2)) = (&args[0], &args[1]) {
+            Ok(ScmValue::Bool(v1 == v2))
+        } else {
+            Err(scm_error!("EQ requires numeric arguments"))
+        }
+    }),
+    scm_builtin_impl!("<", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "LT requires exactly 2 arguments");
+        if let (ScmValue::Number(v1), ScmValue::Number(v2)) = (&args[0], &args[1]) {
+            Ok(ScmValue::Bool(v1 < v2))
+        } else {
+            Err(scm_error!("LT requires numeric arguments"))
+        }
+    }),
+    scm_builtin_impl!("eq?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "EQ? requires exactly 2 arguments");
+        Ok(ScmValue::Bool(scm_eq(&args[0], &args[1])))
+    }),
+    scm_builtin_impl!("eqv?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "EQV? requires exactly 2 arguments");
+        Ok(ScmValue::Bool(scm_eqv(&args[0], &args[1])))
+    }),
+    scm_builtin_impl!("equal?", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "EQUAL? requires exactly 2 arguments");
+        Ok(ScmValue::Bool(is_equal(ctx, &args[0], &args[1])?))
+    }),
+    //
+    // Pairs and lists
+    //
+    scm_builtin_impl!("cons", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "CONS requires exactly 2 arguments");
+        Ok(ScmValue::cons(args[0].clone(), args[1].clone()))
+    }),
+    scm_builtin_impl!("car", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "CAR requires exactly 1 argument");
+        match &args[0] {
+            ScmValue::DotPair(pair) => Ok(pair.borrow().car.clone()),
+            _ => Err(scm_error!("Car requires argument of type DotPair")),
+        }
+    }),
+    scm_builtin_impl!("cdr", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "CDR requires exactly 1 argument");
+        match &args[0] {
+            ScmValue::DotPair(pair) => Ok(pair.borrow().cdr.clone()),
+            _ => Err(scm_error!("Cdr requires argument of type DotPair")),
+        }
+    }),
+    scm_builtin_impl!("set-car!", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "SET-CAR! requires exactly 2 arguments");
+        match &args[0] {
+            ScmValue::DotPair(pair) => pair.borrow_mut().car = args[1].clone(),
+            _ => return Err(scm_error!("Set-car! requires argument of type DotPair")),
+        }
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("set-cdr!", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "SET-CDR! requires exactly 2 arguments");
+        match &args[0] {
+            ScmValue::DotPair(pair) => pair.borrow_mut().cdr = args[1].clone(),
+            _ => return Err(scm_error!("Set-cdr! requires argument of type DotPair")),
+        }
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("list", |ctx, args| -> ScmResult {
+        build_list(ctx, args.to_vec(), ScmValue::Nil)
+    }),
+    scm_builtin_impl!("length", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "LENGTH requires exactly 1 argument");
+        match scm_list_len_polled(&args[0], || ctx.usage.tick())? {
+            Some(len) => Ok(ScmValue::Number(TypedNum::Integer(len))),
+            None => Err(scm_error!("Length requires argument of type List")),
+        }
+    }),
+    scm_builtin_impl!("caar", |_, args| -> ScmResult { cxr("CAAR", "aa", args) }),
+    scm_builtin_impl!("cadr", |_, args| -> ScmResult { cxr("CADR", "ad", args) }),
+    scm_builtin_impl!("cdar", |_, args| -> ScmResult { cxr("CDAR", "da", args) }),
+    scm_builtin_impl!("cddr", |_, args| -> ScmResult { cxr("CDDR", "dd", args) }),
+    scm_builtin_impl!("caaar", |_, args| -> ScmResult {
+        cxr("CAAAR", "aaa", args)
+    }),
+    scm_builtin_impl!("caadr", |_, args| -> ScmResult {
+        cxr("CAADR", "aad", args)
+    }),
+    scm_builtin_impl!("cadar", |_, args| -> ScmResult {
+        cxr("CADAR", "ada", args)
+    }),
+    scm_builtin_impl!("caddr", |_, args| -> ScmResult {
+        cxr("CADDR", "add", args)
+    }),
+    scm_builtin_impl!("cdaar", |_, args| -> ScmResult {
+        cxr("CDAAR", "daa", args)
+    }),
+    scm_builtin_impl!("cdadr", |_, args| -> ScmResult {
+        cxr("CDADR", "dad", args)
+    }),
+    scm_builtin_impl!("cddar", |_, args| -> ScmResult {
+        cxr("CDDAR", "dda", args)
+    }),
+    scm_builtin_impl!("cdddr", |_, args| -> ScmResult {
+        cxr("CDDDR", "ddd", args)
+    }),
+    scm_builtin_impl!("caaaar", |_, args| -> ScmResult {
+        cxr("CAAAAR", "aaaa", args)
+    }),
+    scm_builtin_impl!("caaadr", |_, args| -> ScmResult {
+        cxr("CAAADR", "aaad", args)
+    }),
+    scm_builtin_impl!("caadar", |_, args| -> ScmResult {
+        cxr("CAADAR", "aada", args)
+    }),
+    scm_builtin_impl!("caaddr", |_, args| -> ScmResult {
+        cxr("CAADDR", "aadd", args)
+    }),
+    scm_builtin_impl!("cadaar", |_, args| -> ScmResult {
+        cxr("CADAAR", "adaa", args)
+    }),
+    scm_builtin_impl!("cadadr", |_, args| -> ScmResult {
+        cxr("CADADR", "adad", args)
+    }),
+    scm_builtin_impl!("caddar", |_, args| -> ScmResult {
+        cxr("CADDAR", "adda", args)
+    }),
+    scm_builtin_impl!("cadddr", |_, args| -> ScmResult {
+        cxr("CADDDR", "addd", args)
+    }),
+    scm_builtin_impl!("cdaaar", |_, args| -> ScmResult {
+        cxr("CDAAAR", "daaa", args)
+    }),
+    scm_builtin_impl!("cdaadr", |_, args| -> ScmResult {
+        cxr("CDAADR", "daad", args)
+    }),
+    scm_builtin_impl!("cdadar", |_, args| -> ScmResult {
+        cxr("CDADAR", "dada", args)
+    }),
+    scm_builtin_impl!("cdaddr", |_, args| -> ScmResult {
+        cxr("CDADDR", "dadd", args)
+    }),
+    scm_builtin_impl!("cddaar", |_, args| -> ScmResult {
+        cxr("CDDAAR", "ddaa", args)
+    }),
+    scm_builtin_impl!("cddadr", |_, args| -> ScmResult {
+        cxr("CDDADR", "ddad", args)
+    }),
+    scm_builtin_impl!("cdddar", |_, args| -> ScmResult {
+        cxr("CDDDAR", "ddda", args)
+    }),
+    scm_builtin_impl!("cddddr", |_, args| -> ScmResult {
+        cxr("CDDDDR", "dddd", args)
+    }),
+    scm_builtin_impl!("append", |ctx, args| -> ScmResult {
+        // The last argument is shared by the result and can be any value
+        let Some((last, lists)) = args.split_last() else {
+            return Ok(ScmValue::Nil);
+        };
+        let lists = lists
+            .iter()
+            .map(|list| expect_list(ctx, "APPEND", list))
+            .collect::<Result<Vec<_>, _>>()?;
+        reserve_pairs(ctx, lists.iter().map(Vec::len).sum())?;
+        let mut res = last.clone();
+        for list in lists.into_iter().rev() {
+            res = build_list(ctx, list, res)?;
+        }
+        Ok(res)
+    }),
+    scm_builtin_impl!("reverse", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "REVERSE requires exactly 1 argument");
+        let items = expect_list(ctx, "REVERSE", &args[0])?;
+        reserve_pairs(ctx, items.len())?;
+        let mut res = ScmValue::Nil;
+        for item in items {
+            ctx.usage.tick()?;
+            res = ScmValue::cons(item, res);
+        }
+        Ok(res)
+    }),
+    scm_builtin_impl!("list-tail", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "LIST-TAIL requires exactly 2 arguments");
+        list_tail(ctx, "LIST-TAIL", &args[0], &args[1])
+    }),
+    scm_builtin_impl!("list-ref", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "LIST-REF requires exactly 2 arguments");
+        match list_tail(ctx, "LIST-REF", &args[0], &args[1])? {
+            ScmValue::DotPair(pair) => Ok(pair.borrow().car.clone()),
+            _ => Err(scm_error!("LIST-REF index out of range")),
+        }
+    }),
+    scm_builtin_impl!("list-copy", |ctx, args| -> ScmResult {
+        // Copies the pairs of the list, the tail of an improper list is kept
+        scm_ensure!(args.len() == 1, "LIST-COPY requires exactly 1 argument");
+        let mut items = Vec::new();
+        let mut cur = args[0].clone();
+        while let ScmValue::DotPair(pair) = &cur {
+            ctx.usage.tick()?;
+            let next = {
+                let pair = pair.borrow();
+                items.push(pair.car.clone());
+                pair.cdr.clone()
+            };
+            cur = next;
+        }
+        build_list(ctx, items, cur)
+    }),
+    scm_builtin_impl!("last", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "LAST requires exactly 1 argument");
+        let mut cur = args[0].clone();
+        loop {
+            ctx.usage.tick()?;
+            let next = match &cur {
+                ScmValue::DotPair(pair) => match &pair.borrow().cdr {
+                    next @ ScmValue::DotPair(_) => next.clone(),
+                    _ => return Ok(pair.borrow().car.clone()),
+                },
+                _ => return Err(scm_error!("LAST requires argument of type DotPair")),
+            };
+            cur = next;
+        }
+    }),
+    scm_builtin_impl!("memq", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "MEMQ requires exactly 2 arguments");
+        member(ctx, "MEMQ", args, is_eq)
+    }),
+    scm_builtin_impl!("memv", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "MEMV requires exactly 2 arguments");
+        member(ctx, "MEMV", args, is_eqv)
+    }),
+    scm_builtin_impl!("member", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            (2..=3).contains(&args.len()),
+            "MEMBER requires 2 or 3 arguments"
+        );
+        member(ctx, "MEMBER", args, is_equal)
+    }),
+    scm_builtin_impl!("assq", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "ASSQ requires exactly 2 arguments");
+        assoc(ctx, "ASSQ", args, is_eq)
+    }),
+    scm_builtin_impl!("assv", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "ASSV requires exactly 2 arguments");
+        assoc(ctx, "ASSV", args, is_eqv)
+    }),
+    scm_builtin_impl!("assoc", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            (2..=3).contains(&args.len()),
+            "ASSOC requires 2 or 3 arguments"
+        );
+        assoc(ctx, "ASSOC", args, is_equal)
+    }),
+    scm_primitive_impl!("map", ScmPrimitive::Prelude("map")),
+    scm_primitive_impl!("for-each", ScmPrimitive::Prelude("for-each")),
+    scm_primitive_impl!("filter", ScmPrimitive::Prelude("filter")),
+    scm_primitive_impl!("remove", ScmPrimitive::Prelude("remove")),
+    scm_builtin_impl!("delete", |ctx, args| -> ScmResult {
+        // (delete obj list [compare])
+        scm_ensure!(
+            (2..=3).contains(&args.len()),
+            "DELETE requires 2 or 3 arguments"
+        );
+        let compare = args
+            .get(2)
+            .map(|compare| expect_procedure("DELETE", compare))
+            .transpose()?;
+        let mut res = Vec::new();
+        for item in expect_list(ctx, "DELETE", &args[1])? {
+            let same = match compare {
+                Some(compare) => scm_is_true(&exec_callable(
+                    ctx,
+                    compare,
+                    &[args[0].clone(), item.clone()],
+                )?),
+                None => is_equal(ctx, &args[0], &item)?,
+            };
+            if !same {
+                res.push(item);
             }
+        }
+        build_list(ctx, res, ScmValue::Nil)
+    }),
+    scm_primitive_impl!("fold-left", ScmPrimitive::Prelude("fold-left")),
+    scm_primitive_impl!("fold-right", ScmPrimitive::Prelude("fold-right")),
+    scm_primitive_impl!("reduce", ScmPrimitive::Prelude("reduce")),
+    scm_builtin_impl!("iota", |ctx, args| -> ScmResult {
+        // (iota count [start [step]])
+        scm_ensure!(
+            (1..=3).contains(&args.len()),
+            "IOTA requires from 1 to 3 arguments"
+        );
+        let count = expect_index("IOTA", &args[0])?;
+        let bytes = count
+            .checked_mul(mem::size_of::<ScmPair>())
+            .filter(|bytes| *bytes <= isize::MAX as usize);
+        let Some(bytes) = bytes else {
+            return Err(scm_error!("IOTA: cannot allocate {} elements", count));
+        };
+        ctx.reserve_alloc(bytes)?;
+        let mut nums = [TypedNum::Integer(0), TypedNum::Integer(1)];
+        for (num, arg) in nums.iter_mut().zip(args[1..].iter()) {
+            match arg {
+                ScmValue::Number(val) => *num = *val,
+                _ => return Err(scm_error!("IOTA requires arguments of type Number")),
+            }
+        }
+        let [start, step] = nums;
+        // Built from the end, without collecting the elements first
+        let mut res = ScmValue::Nil;
+        for idx in (0..count).rev() {
+            ctx.usage.tick()?;
+            let num = start + step * TypedNum::Integer(idx as i64);
+            res = ScmValue::cons(ScmValue::Number(num), res);
+        }
+        Ok(res)
+    }),
+    scm_primitive_impl!("list-sort", ScmPrimitive::Prelude("list-sort")),
+    //
+    // Vectors
+    //
+    scm_builtin_impl!("vector", |ctx, args| -> ScmResult {
+        ctx.usage.tick_n(args.len())?;
+        Ok(ScmValue::new_vector(args.to_vec()))
+    }),
+    scm_builtin_impl!("vector-length", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "VECTOR-LENGTH requires exactly 1 argument");
+        if let ScmValue::Vector(vec) = &args[0] {
+            Ok(ScmValue::Number(TypedNum::Integer(
+                vec.borrow().len() as i64
+            )))
         } else {
-            panic!("");
+            Err(scm_error!("Vector-length requires argument of type Vector"))
         }
     }),
-    scm_builtin_impl!("<", |_, args| -> ScmValue {
-        assert!(args.len() == 2, "LT requires exactly 2 arguments");
-
-        if let ScmValue::Number(v1) = args[0] {
-            if let ScmValue::Number(v2) = args[1] {
-                ScmValue::Bool(v1 < v2)
-            } else {
-                panic!("");
+    scm_builtin_impl!("vector-ref", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "VECTOR-REF requires exactly 2 arguments");
+        if let (ScmValue::Vector(vec), ScmValue::Number(TypedNum::Integer(idx))) =
+            (&args[0], &args[1])
+        {
+            let vec = vec.borrow();
+            scm_ensure!(
+                0 <= *idx && (*idx as usize) < vec.len(),
+                "Vector index out of range: {}",
+                idx
+            );
+            Ok(vec[*idx as usize].clone())
+        } else {
+            Err(scm_error!(
+                "Vector-ref requires Vector and Integer arguments"
+            ))
+        }
+    }),
+    scm_builtin_impl!("vector-set!", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 3, "VECTOR-SET! requires exactly 3 arguments");
+        if let (ScmValue::Vector(vec), ScmValue::Number(TypedNum::Integer(idx))) =
+            (&args[0], &args[1])
+        {
+            let mut vec = vec.borrow_mut();
+            scm_ensure!(
+                0 <= *idx && (*idx as usize) < vec.len(),
+                "Vector index out of range: {}",
+                idx
+            );
+            vec[*idx as usize] = args[2].clone();
+            Ok(ScmValue::Nil)
+        } else {
+            Err(scm_error!(
+                "Vector-set! requires Vector and Integer arguments"
+            ))
+        }
+    }),
+    //
+    // Symbols
+    //
+    scm_builtin_impl!("string->symbol", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1,
+            "STRING->SYMBOL requires exactly 1 argument"
+        );
+        if let ScmValue::String(s) = &args[0] {
+            let name = s.borrow();
+            ctx.usage.tick_n(name.len())?;
+            if Symbol::lookup(&name).is_none() {
+                reserve_symbol(ctx, &name)?;
             }
+            Ok(ScmValue::Symbol(Symbol::intern(&name)))
         } else {
-            panic!("");
+            Err(scm_error!(
+                "String->symbol requires argument of type String"
+            ))
         }
     }),
+    scm_builtin_impl!("symbol->string", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1,
+            "SYMBOL->STRING requires exactly 1 argument"
+        );
+        if let ScmValue::Symbol(sym) = &args[0] {
+            ctx.usage.tick_n(sym.as_str().len())?;
+            Ok(ScmValue::new_string(sym.as_str()))
+        } else {
+            Err(scm_error!(
+                "Symbol->string requires argument of type Symbol"
+            ))
+        }
+    }),
+    scm_builtin_impl!("symbol=?", |_, args| -> ScmResult {
+        scm_ensure!(!args.is_empty(), "SYMBOL=? requires at least 1 argument");
+        let mut syms = Vec::new();
+        for arg in args.iter() {
+            match arg {
+                ScmValue::Symbol(sym) => syms.push(*sym),
+                _ => return Err(scm_error!("Symbol=? requires arguments of type Symbol")),
+            }
+        }
+        Ok(ScmValue::Bool(
+            syms.windows(2).all(|pair| pair[0] == pair[1]),
+        ))
+    }),
+    scm_builtin_impl!("gensym", |ctx, args| -> ScmResult {
+        let prefix = gensym_prefix(args)?;
+        reserve_symbol(ctx, &prefix)?;
+        Ok(ScmValue::Symbol(Symbol::uninterned(&prefix)))
+    }),
+    scm_builtin_impl!("generate-uninterned-symbol", |ctx, args| -> ScmResult {
+        let prefix = gensym_prefix(args)?;
+        reserve_symbol(ctx, &prefix)?;
+        Ok(ScmValue::Symbol(Symbol::uninterned(&prefix)))
+    }),
     //
-    // Pairs and lists
+    // Strings
     //
-    scm_builtin_impl!("cons", |_, args| -> ScmValue {
-        assert!(args.len() == 2, "CONS requires exactly 2 arguments");
-        ScmValue::DotPair(Box::new(args[0].clone()), Box::new(args[1].clone()))
-    }),
-    scm_builtin_impl!("car", |_, args| -> ScmValue {
-        assert!(args.len() == 1, "CAR requires exactly 1 argument");
-        return match args[0].clone() {
-            ScmValue::DotPair(car, _) => (*car).clone(),
+    scm_builtin_impl!("make-string", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1 || args.len() == 2,
+            "MAKE-STRING requires 1 or 2 arguments"
+        );
+        let len = expect_index("MAKE-STRING", &args[0])?;
+        let fill = match args.get(1) {
+            Some(fill) => expect_char("MAKE-STRING", fill)?,
+            None => ' ',
+        };
+        let bytes = len.saturating_mul(fill.len_utf8());
+        ctx.reserve_alloc(bytes)?;
+        let mut res = try_string("MAKE-STRING", bytes)?;
+        for _ in 0..len {
+            ctx.usage.tick()?;
+            res.push(fill);
+        }
+        Ok(ScmValue::from_string(res))
+    }),
+    scm_builtin_impl!("string", |_, args| -> ScmResult {
+        let mut res = String::new();
+        for arg in args.iter() {
+            res.push(expect_char("STRING", arg)?);
+        }
+        Ok(ScmValue::new_string(&res))
+    }),
+    scm_builtin_impl!("string-length", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "STRING-LENGTH requires exactly 1 argument");
+        let s = expect_string("STRING-LENGTH", &args[0])?;
+        let len = char_count(ctx, &s.borrow())?;
+        Ok(ScmValue::Number(TypedNum::Integer(len as i64)))
+    }),
+    scm_builtin_impl!("string-ref", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 2, "STRING-REF requires exactly 2 arguments");
+        let s = expect_string("STRING-REF", &args[0])?;
+        let idx = expect_index("STRING-REF", &args[1])?;
+        let s = s.borrow();
+        let c = char_offset(ctx, &s, 0, idx)?.and_then(|pos| s[pos..].chars().next());
+        match c {
+            Some(c) => Ok(ScmValue::Char(c)),
+            None => Err(scm_error!("String index out of range: {}", idx)),
+        }
+    }),
+    scm_builtin_impl!("string-set!", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 3, "STRING-SET! requires exactly 3 arguments");
+        let s = expect_string("STRING-SET!", &args[0])?;
+        let idx = expect_index("STRING-SET!", &args[1])?;
+        let c = expect_char("STRING-SET!", &args[2])?;
+        let mut s = s.borrow_mut();
+        let found =
+            char_offset(ctx, &s, 0, idx)?.and_then(|pos| Some((pos, s[pos..].chars().next()?)));
+        let Some((pos, old)) = found else {
+            return Err(scm_error!("String index out of range: {}", idx));
+        };
+        s.replace_range(pos..pos + old.len_utf8(), c.encode_utf8(&mut [0; 4]));
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("string-fill!", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            (2..=4).contains(&args.len()),
+            "STRING-FILL! requires from 2 to 4 arguments"
+        );
+        let s = expect_string("STRING-FILL!", &args[0])?;
+        let c = expect_char("STRING-FILL!", &args[1])?;
+        let mut s = s.borrow_mut();
+        let (start, end) = byte_range(ctx, "STRING-FILL!", &s, &args[2..])?;
+        let mut fill = String::new();
+        for _ in s[start..end].chars() {
+            ctx.usage.tick()?;
+            fill.push(c);
+        }
+        s.replace_range(start..end, &fill);
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("substring", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 2 || args.len() == 3,
+            "SUBSTRING requires 2 or 3 arguments"
+        );
+        let s = expect_string("SUBSTRING", &args[0])?;
+        let s = s.borrow();
+        let (start, end) = byte_range(ctx, "SUBSTRING", &s, &args[1..])?;
+        ctx.usage.tick_n(end - start)?;
+        ctx.reserve_alloc(end - start)?;
+        Ok(ScmValue::new_string(&s[start..end]))
+    }),
+    scm_builtin_impl!("string-copy", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            (1..=3).contains(&args.len()),
+            "STRING-COPY requires from 1 to 3 arguments"
+        );
+        let s = expect_string("STRING-COPY", &args[0])?;
+        let s = s.borrow();
+        let (start, end) = byte_range(ctx, "STRING-COPY", &s, &args[1..])?;
+        ctx.usage.tick_n(end - start)?;
+        ctx.reserve_alloc(end - start)?;
+        Ok(ScmValue::new_string(&s[start..end]))
+    }),
+    scm_builtin_impl!("string-append", |ctx, args| -> ScmResult {
+        let strings = args
+            .iter()
+            .map(|arg| expect_string("STRING-APPEND", arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let bytes = strings.iter().map(|s| s.borrow().len()).sum();
+        ctx.reserve_alloc(bytes)?;
+        let mut res = try_string("STRING-APPEND", bytes)?;
+        for s in strings.iter() {
+            ctx.usage.tick_n(s.borrow().len())?;
+            res.push_str(&s.borrow());
+        }
+        Ok(ScmValue::from_string(res))
+    }),
+    scm_builtin_impl!("string=?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING=?", args, false, Ordering::is_eq)
+    }),
+    scm_builtin_impl!("string<?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING<?", args, false, Ordering::is_lt)
+    }),
+    scm_builtin_impl!("string>?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING>?", args, false, Ordering::is_gt)
+    }),
+    scm_builtin_impl!("string<=?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING<=?", args, false, Ordering::is_le)
+    }),
+    scm_builtin_impl!("string>=?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING>=?", args, false, Ordering::is_ge)
+    }),
+    scm_builtin_impl!("string-ci=?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING-CI=?", args, true, Ordering::is_eq)
+    }),
+    scm_builtin_impl!("string-ci<?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING-CI<?", args, true, Ordering::is_lt)
+    }),
+    scm_builtin_impl!("string-ci>?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING-CI>?", args, true, Ordering::is_gt)
+    }),
+    scm_builtin_impl!("string-ci<=?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING-CI<=?", args, true, Ordering::is_le)
+    }),
+    scm_builtin_impl!("string-ci>=?", |ctx, args| -> ScmResult {
+        compare_strings(ctx, "STRING-CI>=?", args, true, Ordering::is_ge)
+    }),
+    scm_builtin_impl!("string-upcase", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "STRING-UPCASE requires exactly 1 argument");
+        let s = expect_string("STRING-UPCASE", &args[0])?;
+        ctx.reserve_alloc(s.borrow().len())?;
+        let res = map_case(ctx, &s.borrow(), false, upcase)?;
+        Ok(ScmValue::from_string(res))
+    }),
+    scm_builtin_impl!("string-downcase", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1,
+            "STRING-DOWNCASE requires exactly 1 argument"
+        );
+        let s = expect_string("STRING-DOWNCASE", &args[0])?;
+        ctx.reserve_alloc(s.borrow().len())?;
+        let res = map_case(ctx, &s.borrow(), true, downcase)?;
+        Ok(ScmValue::from_string(res))
+    }),
+    scm_builtin_impl!("string-foldcase", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1,
+            "STRING-FOLDCASE requires exactly 1 argument"
+        );
+        let s = expect_string("STRING-FOLDCASE", &args[0])?;
+        ctx.reserve_alloc(s.borrow().len())?;
+        let res = map_case(ctx, &s.borrow(), true, fold_case)?;
+        Ok(ScmValue::from_string(res))
+    }),
+    scm_builtin_impl!("string->list", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            (1..=3).contains(&args.len()),
+            "STRING->LIST requires from 1 to 3 arguments"
+        );
+        let s = expect_string("STRING->LIST", &args[0])?;
+        let s = s.borrow();
+        let (start, end) = byte_range(ctx, "STRING->LIST", &s, &args[1..])?;
+        reserve_pairs(ctx, char_count(ctx, &s[start..end])?)?;
+        let mut chars = Vec::new();
+        for c in s[start..end].chars() {
+            ctx.usage.tick()?;
+            chars.push(ScmValue::Char(c));
+        }
+        build_list(ctx, chars, ScmValue::Nil)
+    }),
+    scm_builtin_impl!("list->string", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "LIST->STRING requires exactly 1 argument");
+        let items = expect_list(ctx, "LIST->STRING", &args[0])?;
+        let mut chars = Vec::new();
+        for item in items.iter() {
+            ctx.usage.tick()?;
+            chars.push(expect_char("LIST->STRING", item)?);
+        }
+        ctx.reserve_alloc(chars.iter().map(|c| c.len_utf8()).sum())?;
+        Ok(ScmValue::from_string(String::from_iter(chars)))
+    }),
+    scm_builtin_impl!("string->number", |ctx, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1 || args.len() == 2,
+            "STRING->NUMBER requires 1 or 2 arguments"
+        );
+        let s = expect_string("STRING->NUMBER", &args[0])?;
+        ctx.usage.tick_n(s.borrow().len())?;
+        let radix = expect_radix("STRING->NUMBER", args.get(1))?;
+        let res = TypedNum::parse_prefixed(&s.borrow(), radix);
+        Ok(res.map_or(ScmValue::Bool(false), ScmValue::Number))
+    }),
+    scm_builtin_impl!("number->string", |_, args| -> ScmResult {
+        scm_ensure!(
+            args.len() == 1 || args.len() == 2,
+            "NUMBER->STRING requires 1 or 2 arguments"
+        );
+        let radix = expect_radix("NUMBER->STRING", args.get(1))?;
+        match args[0] {
+            ScmValue::Number(num) => Ok(ScmValue::new_string(&format_number(num, radix)?)),
+            _ => Err(scm_error!(
+                "Number->string requires argument of type Number"
+            )),
+        }
+    }),
+    scm_builtin_impl!("string-index", |ctx, args| -> ScmResult {
+        // (string-index string char-or-pred [start [end]])
+        scm_ensure!(
+            (2..=4).contains(&args.len()),
+            "STRING-INDEX requires from 2 to 4 arguments"
+        );
+        let s = expect_string("STRING-INDEX", &args[0])?.borrow().clone();
+        let (start, end) = byte_range(ctx, "STRING-INDEX", &s, &args[2..])?;
+        let skipped = char_count(ctx, &s[..start])?;
+        for (idx, c) in s[start..end].chars().enumerate() {
+            ctx.usage.tick()?;
+            let found = match &args[1] {
+                ScmValue::Char(expected) => c == *expected,
+                ScmValue::Procedure(pred) => {
+                    scm_is_true(&exec_callable(ctx, pred, &[ScmValue::Char(c)])?)
+                }
+                _ => return Err(scm_error!("String-index requires Char or Procedure")),
+            };
+            if found {
+                return Ok(ScmValue::Number(TypedNum::Integer((skipped + idx) as i64)));
+            }
+        }
+        Ok(ScmValue::Bool(false))
+    }),
+    scm_builtin_impl!("string-search-forward", |ctx, args| -> ScmResult {
+        // (string-search-forward pattern string start)
+        scm_ensure!(
+            args.len() == 3,
+            "STRING-SEARCH-FORWARD requires exactly 3 arguments"
+        );
+        let pattern = expect_string("STRING-SEARCH-FORWARD", &args[0])?
+            .borrow()
+            .clone();
+        let s = expect_string("STRING-SEARCH-FORWARD", &args[1])?;
+        let s = s.borrow();
+        let (start, _) = byte_range(ctx, "STRING-SEARCH-FORWARD", &s, &args[2..])?;
+        ctx.usage.tick_n(s.len() - start)?;
+        match s[start..].find(pattern.as_str()) {
+            Some(pos) => {
+                let idx = char_count(ctx, &s[..start + pos])?;
+                Ok(ScmValue::Number(TypedNum::Integer(idx as i64)))
+            }
+            None => Ok(ScmValue::Bool(false)),
+        }
+    }),
+    scm_builtin_impl!("string-split", |ctx, args| -> ScmResult {
+        // (string-split string char-or-string)
+        scm_ensure!(args.len() == 2, "STRING-SPLIT requires exactly 2 arguments");
+        let s = expect_string("STRING-SPLIT", &args[0])?;
+        let s = s.borrow();
+        ctx.usage.tick_n(s.len())?;
+        let parts: Vec<&str> = match &args[1] {
+            ScmValue::Char(c) => s.split(*c).collect(),
+            ScmValue::String(delim) if !delim.borrow().is_empty() => {
+                s.split(delim.borrow().as_str()).collect()
+            }
             _ => {
-                panic!("Car requires argument of type DotPair");
+                return Err(scm_error!(
+                    "String-split requires Char or non-empty String delimiter"
+                ))
             }
         };
+        let part_size = mem::size_of::<ScmPair>() + mem::size_of::<String>();
+        ctx.reserve_alloc(
+            s.len()
+                .saturating_add(parts.len().saturating_mul(part_size)),
+        )?;
+        let mut res = Vec::new();
+        for part in parts {
+            ctx.usage.tick()?;
+            res.push(ScmValue::new_string(part));
+        }
+        build_list(ctx, res, ScmValue::Nil)
     }),
-    scm_builtin_impl!("cdr", |_, args| -> ScmValue {
-        assert!(args.len() == 1, "CDR requires exactly 1 argument");
-        return match args[0].clone() {
-            ScmValue::DotPair(cdr, _) => (*cdr).clone(),
-            _ => {
-                panic!("Car requires argument of type DotPair");
+    scm_builtin_impl!("string-join", |ctx, args| -> ScmResult {
+        // (string-join list [delimiter]), the delimiter is a space by default
+        scm_ensure!(
+            args.len() == 1 || args.len() == 2,
+            "STRING-JOIN requires 1 or 2 arguments"
+        );
+        let items = expect_list(ctx, "STRING-JOIN", &args[0])?;
+        let delim = match args.get(1) {
+            Some(delim) => expect_string("STRING-JOIN", delim)?.borrow().clone(),
+            None => String::from(" "),
+        };
+        let mut parts = Vec::new();
+        for item in items.iter() {
+            parts.push(expect_string("STRING-JOIN", item)?);
+        }
+        let delims = delim.len().saturating_mul(parts.len().saturating_sub(1));
+        let bytes = parts.iter().map(|s| s.borrow().len()).sum::<usize>();
+        ctx.reserve_alloc(bytes.saturating_add(delims))?;
+        let mut res = try_string("STRING-JOIN", bytes.saturating_add(delims))?;
+        for (idx, s) in parts.iter().enumerate() {
+            ctx.usage.tick_n(s.borrow().len())?;
+            if idx > 0 {
+                res.push_str(&delim);
             }
+            res.push_str(&s.borrow());
+        }
+        Ok(ScmValue::from_string(res))
+    }),
+    //
+    // Characters
+    //
+    scm_builtin_impl!("char=?", |_, args| -> ScmResult {
+        compare_chars("CHAR=?", args, false, Ordering::is_eq)
+    }),
+    scm_builtin_impl!("char<?", |_, args| -> ScmResult {
+        compare_chars("CHAR<?", args, false, Ordering::is_lt)
+    }),
+    scm_builtin_impl!("char>?", |_, args| -> ScmResult {
+        compare_chars("CHAR>?", args, false, Ordering::is_gt)
+    }),
+    scm_builtin_impl!("char<=?", |_, args| -> ScmResult {
+        compare_chars("CHAR<=?", args, false, Ordering::is_le)
+    }),
+    scm_builtin_impl!("char>=?", |_, args| -> ScmResult {
+        compare_chars("CHAR>=?", args, false, Ordering::is_ge)
+    }),
+    scm_builtin_impl!("char-ci=?", |_, args| -> ScmResult {
+        compare_chars("CHAR-CI=?", args, true, Ordering::is_eq)
+    }),
+    scm_builtin_impl!("char-ci<?", |_, args| -> ScmResult {
+        compare_chars("CHAR-CI<?", args, true, Ordering::is_lt)
+    }),
+    scm_builtin_impl!("char-ci>?", |_, args| -> ScmResult {
+        compare_chars("CHAR-CI>?", args, true, Ordering::is_gt)
+    }),
+    scm_builtin_impl!("char-ci<=?", |_, args| -> ScmResult {
+        compare_chars("CHAR-CI<=?", args, true, Ordering::is_le)
+    }),
+    scm_builtin_impl!("char-ci>=?", |_, args| -> ScmResult {
+        compare_chars("CHAR-CI>=?", args, true, Ordering::is_ge)
+    }),
+    scm_builtin_impl!("char-alphabetic?", |_, args| -> ScmResult {
+        char_predicate("CHAR-ALPHABETIC?", args, char::is_alphabetic)
+    }),
+    scm_builtin_impl!("char-numeric?", |_, args| -> ScmResult {
+        char_predicate("CHAR-NUMERIC?", args, |c| digit_value(c).is_some())
+    }),
+    scm_builtin_impl!("char-whitespace?", |_, args| -> ScmResult {
+        char_predicate("CHAR-WHITESPACE?", args, char::is_whitespace)
+    }),
+    scm_builtin_impl!("char-upper-case?", |_, args| -> ScmResult {
+        char_predicate("CHAR-UPPER-CASE?", args, char::is_uppercase)
+    }),
+    scm_builtin_impl!("char-lower-case?", |_, args| -> ScmResult {
+        char_predicate("CHAR-LOWER-CASE?", args, char::is_lowercase)
+    }),
+    scm_builtin_impl!("char-upcase", |_, args| -> ScmResult {
+        char_mapping("CHAR-UPCASE", args, char_upcase)
+    }),
+    scm_builtin_impl!("char-downcase", |_, args| -> ScmResult {
+        char_mapping("CHAR-DOWNCASE", args, char_downcase)
+    }),
+    scm_builtin_impl!("char-foldcase", |_, args| -> ScmResult {
+        char_mapping("CHAR-FOLDCASE", args, char_foldcase)
+    }),
+    scm_builtin_impl!("digit-value", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "DIGIT-VALUE requires exactly 1 argument");
+        let c = expect_char("DIGIT-VALUE", &args[0])?;
+        Ok(match digit_value(c) {
+            Some(val) => ScmValue::Number(TypedNum::Integer(val as i64)),
+            None => ScmValue::Bool(false),
+        })
+    }),
+    scm_builtin_impl!("char->integer", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "CHAR->INTEGER requires exactly 1 argument");
+        let c = expect_char("CHAR->INTEGER", &args[0])?;
+        Ok(ScmValue::Number(TypedNum::Integer(c as i64)))
+    }),
+    scm_builtin_impl!("integer->char", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "INTEGER->CHAR requires exactly 1 argument");
+        let code = expect_index("INTEGER->CHAR", &args[0])?;
+        match u32::try_from(code).ok().and_then(char::from_u32) {
+            Some(c) => Ok(ScmValue::Char(c)),
+            None => Err(scm_error!("Not a Unicode scalar value: {}", code)),
+        }
+    }),
+    //
+    // Hash tables
+    //
+    scm_builtin_impl!("make-hash-table", |_, args| -> ScmResult {
+        // The optional hash function is ignored, hashes are always
+        // computed according to the equality predicate
+        scm_ensure!(
+            args.len() <= 2,
+            "MAKE-HASH-TABLE accepts at most 2 arguments"
+        );
+        let kind = match args.first() {
+            Some(comparator) => hash_kind_of(comparator)?,
+            None => ScmHashKind::Equal,
         };
+        Ok(ScmValue::HashTable(Rc::new(RefCell::new(
+            ScmHashTable::new(kind),
+        ))))
     }),
-    scm_builtin_impl!("list", |_, args| -> ScmValue {
-        let mut res = ScmValue::Nil;
-        for arg in args.iter().rev() {
-            res = ScmValue::DotPair(Box::new(arg.clone()), Box::new(res));
+    scm_builtin_impl!("hash-table?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "HASH-TABLE? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::HashTable(_))))
+    }),
+    scm_builtin_impl!("hash-table-ref", |ctx, args| -> ScmResult {
+        // (hash-table-ref table key [fail [success]])
+        let table = expect_hash_table("HASH-TABLE-REF", args, 2, 4)?;
+        let val = table.borrow().get(&args[1]);
+        match (val, args.get(2), args.get(3)) {
+            (Some(val), _, None) => Ok(val),
+            (Some(val), _, Some(success)) => {
+                let success = expect_procedure("HASH-TABLE-REF", success)?;
+                exec_callable(ctx, success, &[val])
+            }
+            (None, Some(fail), _) => {
+                let fail = expect_procedure("HASH-TABLE-REF", fail)?;
+                exec_callable(ctx, fail, &[])
+            }
+            (None, None, _) => Err(ScmError::new(
+                ScmErrorKind::Error,
+                String::from("Key not found in hash table"),
+                vec![args[1].clone()],
+            )),
         }
-        res
     }),
-    scm_builtin_impl!("length", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        let res = scm_list_len(&args[0]);
-        ScmValue::Number(TypedNum::Integer(res.unwrap()))
+    scm_builtin_impl!("hash-table-ref/default", |_, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-REF/DEFAULT", args, 3, 3)?;
+        let val = table.borrow().get(&args[1]);
+        Ok(val.unwrap_or_else(|| args[2].clone()))
+    }),
+    scm_builtin_impl!("hash-table-set!", |ctx, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-SET!", args, 3, 3)?;
+        table.borrow().check_key(&args[1])?;
+        ctx.reserve_alloc(ScmHashTable::ENTRY_SIZE)?;
+        table.borrow_mut().insert(args[1].clone(), args[2].clone());
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("hash-table-delete!", |_, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-DELETE!", args, 2, 2)?;
+        table.borrow_mut().remove(&args[1]);
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("hash-table-contains?", |_, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-CONTAINS?", args, 2, 2)?;
+        let res = table.borrow().get(&args[1]).is_some();
+        Ok(ScmValue::Bool(res))
+    }),
+    scm_builtin_impl!("hash-table-update!", |ctx, args| -> ScmResult {
+        // (hash-table-update! table key proc [fail])
+        let table = expect_hash_table("HASH-TABLE-UPDATE!", args, 3, 4)?;
+        table.borrow().check_key(&args[1])?;
+        let update = expect_procedure("HASH-TABLE-UPDATE!", &args[2])?;
+        let val = table.borrow().get(&args[1]);
+        let val = match (val, args.get(3)) {
+            (Some(val), _) => val,
+            (None, Some(fail)) => {
+                let fail = expect_procedure("HASH-TABLE-UPDATE!", fail)?;
+                exec_callable(ctx, fail, &[])?
+            }
+            (None, None) => {
+                return Err(ScmError::new(
+                    ScmErrorKind::Error,
+                    String::from("Key not found in hash table"),
+                    vec![args[1].clone()],
+                ))
+            }
+        };
+        let val = exec_callable(ctx, update, &[val])?;
+        ctx.reserve_alloc(ScmHashTable::ENTRY_SIZE)?;
+        table.borrow_mut().insert(args[1].clone(), val);
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("hash-table-update!/default", |ctx, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-UPDATE!/DEFAULT", args, 4, 4)?;
+        table.borrow().check_key(&args[1])?;
+        let update = expect_procedure("HASH-TABLE-UPDATE!/DEFAULT", &args[2])?;
+        let val = table.borrow().get(&args[1]);
+        let val = exec_callable(ctx, update, &[val.unwrap_or_else(|| args[3].clone())])?;
+        ctx.reserve_alloc(ScmHashTable::ENTRY_SIZE)?;
+        table.borrow_mut().insert(args[1].clone(), val);
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("hash-table-size", |_, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-SIZE", args, 1, 1)?;
+        let len = table.borrow().len();
+        Ok(ScmValue::Number(TypedNum::Integer(len as i64)))
+    }),
+    scm_builtin_impl!("hash-table-keys", |ctx, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-KEYS", args, 1, 1)?;
+        reserve_pairs(ctx, table.borrow().len())?;
+        let keys = hash_table_entries(ctx, &table)?
+            .into_iter()
+            .map(|(key, _)| key);
+        build_list(ctx, keys.collect(), ScmValue::Nil)
+    }),
+    scm_builtin_impl!("hash-table-values", |ctx, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-VALUES", args, 1, 1)?;
+        reserve_pairs(ctx, table.borrow().len())?;
+        let vals = hash_table_entries(ctx, &table)?
+            .into_iter()
+            .map(|(_, val)| val);
+        build_list(ctx, vals.collect(), ScmValue::Nil)
+    }),
+    scm_builtin_impl!("hash-table->alist", |ctx, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE->ALIST", args, 1, 1)?;
+        reserve_pairs(ctx, table.borrow().len().saturating_mul(2))?;
+        let entries = hash_table_entries(ctx, &table)?;
+        let entries = entries
+            .into_iter()
+            .map(|(key, val)| ScmValue::cons(key, val));
+        build_list(ctx, entries.collect(), ScmValue::Nil)
+    }),
+    scm_builtin_impl!("hash-table-walk", |ctx, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-WALK", args, 2, 2)?;
+        let proc = expect_procedure("HASH-TABLE-WALK", &args[1])?;
+        let entries = hash_table_entries(ctx, &table)?;
+        for (key, val) in entries {
+            exec_callable(ctx, proc, &[key, val])?;
+        }
+        Ok(ScmValue::Nil)
+    }),
+    scm_builtin_impl!("hash-table-clear!", |_, args| -> ScmResult {
+        let table = expect_hash_table("HASH-TABLE-CLEAR!", args, 1, 1)?;
+        table.borrow_mut().clear();
+        Ok(ScmValue::Nil)
     }),
     //
     // Types predicates
     //
-    scm_builtin_impl!("atom?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
+    scm_builtin_impl!("atom?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "ATOM? requires exactly 1 argument");
         if let ScmValue::Bool(_)
         | ScmValue::Char(_)
         | ScmValue::Number(_)
@@ -219,77 +2035,330 @@ pub const BUILTINS_LIST: &[(&str, ScmValue)] = &[
         | ScmValue::Symbol(_)
         | ScmValue::Nil = args[0]
         {
-            ScmValue::Bool(true)
+            Ok(ScmValue::Bool(true))
         } else {
-            ScmValue::Bool(false)
+            Ok(ScmValue::Bool(false))
         }
     }),
-    scm_builtin_impl!("bool?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        if let ScmValue::Bool(_) = args[0] {
-            ScmValue::Bool(true)
-        } else {
-            ScmValue::Bool(false)
-        }
+    scm_builtin_impl!("bool?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "BOOL? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::Bool(_))))
     }),
-    scm_builtin_impl!("integer?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        if let ScmValue::Number(num) = args[0] {
-            ScmValue::Bool(matches!(num, TypedNum::Integer(_)))
-        } else {
-            ScmValue::Bool(false)
-        }
+    scm_builtin_impl!("integer?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "INTEGER? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(
+            args[0],
+            ScmValue::Number(TypedNum::Integer(_))
+        )))
     }),
-    scm_builtin_impl!("number?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        if let ScmValue::Number(_) = args[0] {
-            ScmValue::Bool(true)
-        } else {
-            ScmValue::Bool(false)
-        }
+    scm_builtin_impl!("number?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "NUMBER? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::Number(_))))
     }),
-    scm_builtin_impl!("null?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        if let ScmValue::Nil = args[0] {
-            ScmValue::Bool(true)
-        } else {
-            ScmValue::Bool(false)
-        }
+    scm_builtin_impl!("null?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "NULL? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::Nil)))
     }),
-    scm_builtin_impl!("pair?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        if let ScmValue::DotPair { .. } = args[0] {
-            ScmValue::Bool(true)
-        } else {
-            ScmValue::Bool(false)
-        }
+    scm_builtin_impl!("pair?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "PAIR? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::DotPair(_))))
     }),
-    scm_builtin_impl!("list?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        ScmValue::Bool(scm_is_list(&args[0]))
+    scm_builtin_impl!("list?", |ctx, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "LIST? requires exactly 1 argument");
+        let len = scm_list_len_polled(&args[0], || ctx.usage.tick())?;
+        Ok(ScmValue::Bool(len.is_some()))
     }),
-    scm_builtin_impl!("procedure?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        if let ScmValue::Procedure(_) = args[0] {
-            ScmValue::Bool(true)
-        } else {
-            ScmValue::Bool(false)
+    scm_builtin_impl!("procedure?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "PROCEDURE? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::Procedure(_))))
+    }),
+    scm_builtin_impl!("string?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "STRING? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::String(_))))
+    }),
+    scm_builtin_impl!("char?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "CHAR? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::Char(_))))
+    }),
+    scm_builtin_impl!("input-port?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "INPUT-PORT? requires exactly 1 argument");
+        Ok(ScmValue::Bool(
+            matches!(&args[0], ScmValue::Port(port) if port.input),
+        ))
+    }),
+    scm_builtin_impl!("output-port?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "OUTPUT-PORT? requires exactly 1 argument");
+        Ok(ScmValue::Bool(
+            matches!(&args[0], ScmValue::Port(port) if !port.input),
+        ))
+    }),
+    scm_builtin_impl!("eof-object?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "EOF-OBJECT? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::Eof)))
+    }),
+    scm_builtin_impl!("vector?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "VECTOR? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::Vector(_))))
+    }),
+    scm_builtin_impl!("symbol?", |_, args| -> ScmResult {
+        scm_ensure!(args.len() == 1, "SYMBOL? requires exactly 1 argument");
+        Ok(ScmValue::Bool(matches!(args[0], ScmValue::Symbol(_))))
+    }),
+];
+
+// Helpers of the procedures written in Scheme, which see them besides the
+// builtins. They aren't globals, so the evaluated code can't call them.
+pub(crate) const PRELUDE_HELPERS: &[(&str, ScmValue)] = &[
+    scm_builtin_impl!("%expect-lists", |ctx, args| -> ScmResult {
+        // (%expect-lists name proc lists) checks the arguments of a builtin
+        // calling the procedure on items of the lists
+        let name = expect_string("%EXPECT-LISTS", &args[0])?;
+        let name = name.borrow();
+        expect_procedure(&name, &args[1])?;
+        for list in scm_list_to_vec(&args[2]) {
+            let len = scm_list_len_polled(&list, || ctx.usage.tick())?;
+            scm_ensure!(len.is_some(), "{} requires argument of type List", name);
         }
+        Ok(ScmValue::Nil)
     }),
-    scm_builtin_impl!("string?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        if let ScmValue::String(_) = args[0] {
-            ScmValue::Bool(true)
-        } else {
-            ScmValue::Bool(false)
+    scm_builtin_impl!("%heads", |ctx, args| -> ScmResult {
+        // First items of the lists, #f once one of them has ended
+        let mut res = Vec::new();
+        for list in scm_list_to_vec(&args[0]) {
+            match list {
+                ScmValue::DotPair(pair) => res.push(pair.borrow().car.clone()),
+                _ => return Ok(ScmValue::Bool(false)),
+            }
         }
+        build_list(ctx, res, ScmValue::Nil)
     }),
-    scm_builtin_impl!("symbol?", |_, args| -> ScmValue {
-        assert!(args.len() == 1);
-        if let ScmValue::Symbol(_) = args[0] {
-            ScmValue::Bool(true)
-        } else {
-            ScmValue::Bool(false)
+    scm_builtin_impl!("%tails", |ctx, args| -> ScmResult {
+        let mut res = Vec::new();
+        for list in scm_list_to_vec(&args[0]) {
+            match list {
+                ScmValue::DotPair(pair) => res.push(pair.borrow().cdr.clone()),
+                _ => return Ok(ScmValue::Bool(false)),
+            }
         }
+        build_list(ctx, res, ScmValue::Nil)
+    }),
+    scm_builtin_impl!("%compile", |ctx, args| -> ScmResult {
+        // (%compile expr [environment]) is the thunk `eval` calls, by default
+        // in the interaction environment
+        scm_ensure!(
+            (1..=2).contains(&args.len()),
+            "EVAL requires 1 or 2 arguments"
+        );
+        let bindings = match args.get(1) {
+            None => None,
+            Some(ScmValue::Environment(env)) => match &**env {
+                ScmEnvironment::Interaction => None,
+                ScmEnvironment::Bindings(bindings) => Some(bindings.clone()),
+            },
+            Some(_) => return Err(scm_error!("EVAL requires argument of type Environment")),
+        };
+        let proc = compile_datum(&args[0], bindings, &ctx.fs_access)?;
+        Ok(ScmValue::Procedure(proc))
+    }),
+    scm_builtin_impl!("%load-forms", |ctx, args| -> ScmResult {
+        // (%load-forms path) is the path of the file `load` runs and the
+        // thunks of its forms. Relative paths are resolved against the file
+        // being loaded.
+        scm_ensure!(args.len() == 1, "LOAD requires exactly 1 argument");
+        let path = expect_path("LOAD", &args[0])?;
+        let path = match ctx.loading.as_ref().and_then(|file| file.parent()) {
+            Some(dir) => dir.join(path),
+            None => path,
+        };
+        ctx.fs_access.check(&path)?;
+        let forms = parse_file(&path, &ctx.fs_access)?
+            .into_iter()
+            .map(ScmValue::Procedure)
+            .collect();
+        let forms = build_list(ctx, forms, ScmValue::Nil)?;
+        let path = ScmValue::new_string(&path.to_string_lossy());
+        Ok(ScmValue::cons(path, forms))
+    }),
+    scm_builtin_impl!("%set-loading!", |ctx, args| -> ScmResult {
+        // Sets the file being loaded, #f for none, and returns the previous one
+        let path = match &args[0] {
+            ScmValue::Bool(false) => None,
+            val => Some(expect_path("LOAD", val)?),
+        };
+        let prev = mem::replace(&mut ctx.loading, path);
+        Ok(match prev {
+            Some(prev) => ScmValue::new_string(&prev.to_string_lossy()),
+            None => ScmValue::Bool(false),
+        })
     }),
 ];
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn strings() {
+        let code = "
+            (define s (string-copy \"héllo wörld\"))
+            (string-set! s 0 (string-ref \"ж\" 0))
+            (string-fill! s #'x 6 8)
+            (list (string-length \"héllo\")
+                  s
+                  (substring s 1 5)
+                  (string-upcase \"straße\")
+                  (string-ci=? \"Straße\" \"STRASSE\")
+                  (string<? \"abc\" \"abd\" \"b\")
+                  (number->string 255 16)
+                  (number->string (- 0 10) 2)
+                  (string->number \"ff\" 16)
+                  (string->number \"abc\")
+                  (string-index \"ab cd\" #'c)
+                  (string-search-forward \"ö\" s 0)
+                  (string-join (string-split \"a,b,,c\" \",\") \"-\")
+                  (list->string (string->list \"héllo\" 1 3)))";
+        let expected = "'(5 \"жéllo xxrld\" \"éllo\" \"STRASSE\" #t #t
+                         \"ff\" \"-1010\" 255 #f 3 #f \"a-b--c\" \"él\")";
+        assert!(scm_equal(&run(code), &run(expected)));
+    }
+
+    #[test]
+    fn number_prefixes() {
+        // `string->number` takes the same prefixes as the reader
+        let code = "
+            (list (string->number \"#xff\")
+                  (string->number \"#b101\" 16)
+                  (string->number \"#o17\")
+                  (string->number \"#d10\" 2)
+                  (string->number \"#e1e3\")
+                  (string->number \"#i#x10\")
+                  (string->number \"#x#e10\")
+                  (string->number \"#e1.5\")
+                  (string->number \"#x#x1\")
+                  (string->number \"#q1\")
+                  #XFF
+                  #e2.0)";
+        let expected = "'(255 5 15 10 1000 16.0 16 #f #f #f 255 2)";
+        assert!(scm_equal(&run(code), &run(expected)));
+    }
+
+    #[test]
+    fn chars() {
+        let code = "
+            (list (char->integer #\\x3bb)
+                  (integer->char 955)
+                  (char<? #\\a #\\b #\\c)
+                  (char-ci=? #\\A #\\a #\\x61)
+                  (char-alphabetic? #\\ж)
+                  (char-numeric? #\\x663)
+                  (digit-value #\\x663)
+                  (digit-value #\\a)
+                  (char-whitespace? #\\space)
+                  (char-upcase #\\ä)
+                  (char-upcase #\\ß)
+                  (char-foldcase #\\Σ)
+                  (guard (err (#t 'invalid)) (integer->char 55296)))";
+        let expected = "'(955 #\\λ #t #t #t #t 3 #f #t #\\Ä #\\ß #\\σ invalid)";
+        assert!(scm_equal(&run(code), &run(expected)));
+    }
+
+    #[test]
+    fn lists() {
+        let code = "
+            (define circular (list 1 2))
+            (set-cdr! (cdr circular) circular)
+            (define pairs '((a . 1) (b . 2)))
+            (list (append '(1) '(2 3) 4)
+                  (reverse '(1 2 3))
+                  (list-tail '(1 2 . 3) 2)
+                  (list-ref '(a b c) 1)
+                  (list-copy '(1 2 . 3))
+                  (memv 2 '(1 2 3))
+                  (member \"b\" '(\"a\" \"b\"))
+                  (member 2 '(1 2 3) =)
+                  (assq 'b pairs)
+                  (assoc 3 pairs)
+                  (map + '(1 2 3) '(10 20))
+                  (filter (lambda (x) (< x 2)) (iota 5))
+                  (remove (lambda (x) (< x 2)) (iota 5 1))
+                  (fold-left cons '() '(1 2))
+                  (fold-right cons '() '(1 2))
+                  (reduce + 0 '(1 2 3))
+                  (delete 2 '(1 2 3 2))
+                  (last '(1 2 . 3))
+                  (caddr '(1 2 3))
+                  (cdddar '((1 2 3 4)))
+                  (list-sort (lambda (a b) (< (car a) (car b))) '((2 . a) (1 . b) (2 . c)))
+                  (list? circular)
+                  (guard (err (#t 'improper)) (length '(1 . 2))))";
+        let expected = "'((1 2 3 . 4) (3 2 1) 3 b (1 2 . 3) (2 3) (\"b\") (2 3) (b . 2) #f
+                         (11 22) (0 1) (2 3 4 5) ((() . 1) . 2) (1 2) 6 (1 3) 2 3 (4)
+                         ((1 . b) (2 . a) (2 . c)) #f improper)";
+        assert!(scm_equal(&run(code), &run(expected)));
+    }
+
+    #[test]
+    fn read() {
+        let code = "
+            (define in (open-input-string \"(a . (1 -2 2.5)) #(x \\\"s\\\" #\\\\space) sym #t\"))
+            (define first (read in))
+            (define second (read in))
+            (list first
+                  (vector-ref second 1)
+                  (read in)
+                  (read in)
+                  (eof-object? (read in))
+                  (guard (err ((read-error? err) 'bad)) (read (open-input-string \"(1 2\"))))";
+        let expected = "'((a 1 -2 2.5) \"s\" sym #t #t bad)";
+        assert!(scm_equal(&run(code), &run(expected)));
+    }
+
+    #[test]
+    fn eval() {
+        let code = "
+            (eval '(define x 40))
+            (define env (environment '(prefix (only (scheme base) car +) base:)
+                                     '(rename (scheme write) (display show))))
+            (list (eval '(+ x 2) (interaction-environment))
+                  (eval '(car '(1 2)) (environment '(scheme base)))
+                  (eval '(base:+ 1 2) env)
+                  (procedure? (eval 'show env))
+                  (eval '(if #t 'syntax 'none) (null-environment 5))
+                  (eval '(let ((y 1)) y) (scheme-report-environment 5))
+                  (guard (err (#t (error-object-irritants err))) (eval 'cdr env))
+                  (guard (err (#t 'immutable)) (eval '(define base:car 1) env)))";
+        let expected = "'(42 1 3 #t syntax 1 (cdr) immutable)";
+        assert!(scm_equal(&run(code), &run(expected)));
+    }
+
+    #[test]
+    fn load() {
+        let dir = std::env::temp_dir().join(format!("scm_load_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let files = [
+            (
+                "main.scm",
+                "(load \"lib/a.scm\") (define (f) (include \"lib/inner.scm\") (g 1))",
+            ),
+            ("lib/a.scm", "(load \"b.scm\") (define a (+ b 1))"),
+            ("lib/b.scm", "(define b 1)"),
+            ("lib/inner.scm", "(define (g x) (+ x y)) (define y 10)"),
+        ];
+        for (name, code) in files.iter() {
+            std::fs::write(dir.join(name), code).unwrap();
+        }
+        let code = format!(
+            "
+            (load {:?})
+            (list a
+                  b
+                  (f)
+                  (guard (err (#t 'unbound)) g)
+                  (guard (err ((file-error? err) 'missing)) (load \"missing.scm\")))",
+            dir.join("main.scm").display().to_string()
+        );
+        let res = run(&code);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(scm_equal(&res, &run("'(2 1 11 unbound missing)")));
+    }
+}
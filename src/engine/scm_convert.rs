@@ -0,0 +1,391 @@
+use crate::engine::{
+    scm_core::{ScmExecContext, ScmValue},
+    scm_error::*,
+    scm_hash::{ScmHashKind, ScmHashTable},
+    scm_native::ScmArity,
+    scm_utils::{scm_is_list, scm_list_to_vec},
+    typed_num::TypedNum,
+};
+use std::{cell::RefCell, collections::HashMap, hash::Hash, rc::Rc};
+
+// Rust value that can be taken from a Scheme value. `TYPE` names the
+// expected Scheme type in error messages.
+pub trait FromScm: Sized {
+    const TYPE: &'static str;
+
+    fn from_scm(val: &ScmValue) -> Option<Self>;
+}
+
+pub trait IntoScm {
+    fn into_scm(self) -> ScmValue;
+}
+
+// Result of a function registered by `define_fn`: a value or a Scheme error
+pub trait IntoScmResult {
+    fn into_scm_result(self) -> ScmResult;
+}
+
+impl<T: IntoScm> IntoScmResult for T {
+    fn into_scm_result(self) -> ScmResult {
+        Ok(self.into_scm())
+    }
+}
+
+impl<T: IntoScm> IntoScmResult for Result<T, ScmError> {
+    fn into_scm_result(self) -> ScmResult {
+        self.map(IntoScm::into_scm)
+    }
+}
+
+pub fn expect_arg<T: FromScm>(name: &str, val: &ScmValue) -> Result<T, ScmError> {
+    T::from_scm(val).ok_or_else(|| {
+        scm_error!(
+            "{} requires argument of type {}",
+            name.to_uppercase(),
+            T::TYPE
+        )
+    })
+}
+
+impl FromScm for ScmValue {
+    const TYPE: &'static str = "Value";
+
+    fn from_scm(val: &ScmValue) -> Option<Self> {
+        Some(val.clone())
+    }
+}
+
+impl IntoScm for ScmValue {
+    fn into_scm(self) -> ScmValue {
+        self
+    }
+}
+
+impl IntoScm for () {
+    fn into_scm(self) -> ScmValue {
+        ScmValue::Nil
+    }
+}
+
+// Exact integers only, values out of the range of the type don't match it.
+// Integers are i64 in Scheme, so `u64` and `usize` values above `i64::MAX`
+// become the nearest float, and converting them back fails.
+macro_rules! scm_convert_integer {
+    ($($t:ty),*) => {$(
+        impl FromScm for $t {
+            const TYPE: &'static str = "Integer";
+
+            fn from_scm(val: &ScmValue) -> Option<Self> {
+                match val {
+                    ScmValue::Number(TypedNum::Integer(num)) => <$t>::try_from(*num).ok(),
+                    _ => None,
+                }
+            }
+        }
+
+        impl IntoScm for $t {
+            fn into_scm(self) -> ScmValue {
+                ScmValue::Number(match i64::try_from(self) {
+                    Ok(num) => TypedNum::Integer(num),
+                    Err(_) => TypedNum::Float(self as f64),
+                })
+            }
+        }
+    )*};
+}
+
+scm_convert_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! scm_convert_float {
+    ($($t:ty),*) => {$(
+        impl FromScm for $t {
+            const TYPE: &'static str = "Number";
+
+            fn from_scm(val: &ScmValue) -> Option<Self> {
+                match val {
+                    ScmValue::Number(num) => Some(num.to_float() as $t),
+                    _ => None,
+                }
+            }
+        }
+
+        impl IntoScm for $t {
+            fn into_scm(self) -> ScmValue {
+                ScmValue::Number(TypedNum::Float(self as f64))
+            }
+        }
+    )*};
+}
+
+scm_convert_float!(f32, f64);
+
+impl FromScm for bool {
+    const TYPE: &'static str = "Bool";
+
+    fn from_scm(val: &ScmValue) -> Option<Self> {
+        match val {
+            ScmValue::Bool(val) => Some(*val),
+            _ => None,
+        }
+    }
+}
+
+impl IntoScm for bool {
+    fn into_scm(self) -> ScmValue {
+        ScmValue::Bool(self)
+    }
+}
+
+impl FromScm for char {
+    const TYPE: &'static str = "Char";
+
+    fn from_scm(val: &ScmValue) -> Option<Self> {
+        match val {
+            ScmValue::Char(c) => Some(*c),
+            _ => None,
+        }
+    }
+}
+
+impl IntoScm for char {
+    fn into_scm(self) -> ScmValue {
+        ScmValue::Char(self)
+    }
+}
+
+impl FromScm for String {
+    const TYPE: &'static str = "String";
+
+    fn from_scm(val: &ScmValue) -> Option<Self> {
+        match val {
+            ScmValue::String(s) => Some(s.borrow().clone()),
+            _ => None,
+        }
+    }
+}
+
+impl IntoScm for String {
+    fn into_scm(self) -> ScmValue {
+        ScmValue::new_string(&self)
+    }
+}
+
+impl IntoScm for &str {
+    fn into_scm(self) -> ScmValue {
+        ScmValue::new_string(self)
+    }
+}
+
+// #f is None
+impl<T: FromScm> FromScm for Option<T> {
+    const TYPE: &'static str = T::TYPE;
+
+    fn from_scm(val: &ScmValue) -> Option<Self> {
+        match val {
+            ScmValue::Bool(false) => Some(None),
+            _ => T::from_scm(val).map(Some),
+        }
+    }
+}
+
+impl<T: IntoScm> IntoScm for Option<T> {
+    fn into_scm(self) -> ScmValue {
+        match self {
+            Some(val) => val.into_scm(),
+            None => ScmValue::Bool(false),
+        }
+    }
+}
+
+fn scm_list(items: impl DoubleEndedIterator<Item = ScmValue>) -> ScmValue {
+    items
+        .rev()
+        .fold(ScmValue::Nil, |list, item| ScmValue::cons(item, list))
+}
+
+// Proper lists and vectors, converted to lists
+impl<T: FromScm> FromScm for Vec<T> {
+    const TYPE: &'static str = "List";
+
+    fn from_scm(val: &ScmValue) -> Option<Self> {
+        match val {
+            ScmValue::Vector(vec) => vec.borrow().iter().map(T::from_scm).collect(),
+            _ if scm_is_list(val) => scm_list_to_vec(val).iter().map(T::from_scm).collect(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: IntoScm> IntoScm for Vec<T> {
+    fn into_scm(self) -> ScmValue {
+        scm_list(self.into_iter().map(IntoScm::into_scm))
+    }
+}
+
+// Hash tables, new ones compare keys by `equal?`
+impl<K: FromScm + Eq + Hash, V: FromScm> FromScm for HashMap<K, V> {
+    const TYPE: &'static str = "HashTable";
+
+    fn from_scm(val: &ScmValue) -> Option<Self> {
+        match val {
+            ScmValue::HashTable(table) => table
+                .borrow()
+                .entries()
+                .iter()
+                .map(|(key, val)| Some((K::from_scm(key)?, V::from_scm(val)?)))
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+impl<K: IntoScm, V: IntoScm> IntoScm for HashMap<K, V> {
+    fn into_scm(self) -> ScmValue {
+        let mut table = ScmHashTable::new(ScmHashKind::Equal);
+        for (key, val) in self {
+            table.insert(key.into_scm(), val.into_scm());
+        }
+        ScmValue::HashTable(Rc::new(RefCell::new(table)))
+    }
+}
+
+// Tuples are lists of the same length
+macro_rules! scm_convert_tuple {
+    ($($t:ident $val:ident),*) => {
+        impl<$($t: FromScm),*> FromScm for ($($t,)*) {
+            const TYPE: &'static str = "List";
+
+            fn from_scm(val: &ScmValue) -> Option<Self> {
+                if !scm_is_list(val) {
+                    return None;
+                }
+                match &scm_list_to_vec(val)[..] {
+                    [$($val),*] => Some(($($t::from_scm($val)?,)*)),
+                    _ => None,
+                }
+            }
+        }
+
+        impl<$($t: IntoScm),*> IntoScm for ($($t,)*) {
+            fn into_scm(self) -> ScmValue {
+                let ($($val,)*) = self;
+                scm_list([$($val.into_scm()),*].into_iter())
+            }
+        }
+    };
+}
+
+scm_convert_tuple!(A a);
+scm_convert_tuple!(A a, B b);
+scm_convert_tuple!(A a, B b, C c);
+scm_convert_tuple!(A a, B b, C c, D d);
+
+// Rust function callable from Scheme: its params implement `FromScm` and the
+// result implements `IntoScmResult`. `Args` is the tuple of param types.
+pub trait ScmFn<Args> {
+    const ARITY: usize;
+
+    // Arguments are already checked against the arity
+    fn call(&self, name: &str, args: &[ScmValue]) -> ScmResult;
+}
+
+macro_rules! scm_fn_impl {
+    ($($t:ident $val:ident),*) => {
+        impl<F, R, $($t),*> ScmFn<($($t,)*)> for F
+        where
+            F: Fn($($t),*) -> R,
+            R: IntoScmResult,
+            $($t: FromScm,)*
+        {
+            const ARITY: usize = <[&str]>::len(&[$(stringify!($t)),*]);
+
+            #[allow(unused_variables, unused_mut)]
+            fn call(&self, name: &str, args: &[ScmValue]) -> ScmResult {
+                let mut args = args.iter();
+                $(let $val = expect_arg::<$t>(name, args.next().unwrap())?;)*
+                self($($val),*).into_scm_result()
+            }
+        }
+    };
+}
+
+scm_fn_impl!();
+scm_fn_impl!(A a);
+scm_fn_impl!(A a, B b);
+scm_fn_impl!(A a, B b, C c);
+scm_fn_impl!(A a, B b, C c, D d);
+scm_fn_impl!(A a, B b, C c, D d, E e);
+scm_fn_impl!(A a, B b, C c, D d, E e, G g);
+
+impl ScmExecContext {
+    // Defines an ordinary Rust function as a native procedure. The arity
+    // comes from its params, and arguments of wrong types raise errors.
+    pub fn define_fn<Args, F: ScmFn<Args> + 'static>(&mut self, name: &str, doc: &str, func: F) {
+        let proc_name = String::from(name);
+        self.define_native(name, ScmArity::exact(F::ARITY), doc, move |_, args| {
+            func.call(&proc_name, args)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::test_utils::*, *};
+
+    fn round_trip<T: FromScm + IntoScm + Clone + PartialEq + std::fmt::Debug>(val: T) {
+        assert_eq!(T::from_scm(&val.clone().into_scm()), Some(val));
+    }
+
+    #[test]
+    fn conversions() {
+        round_trip(-5i64);
+        round_trip(200u8);
+        round_trip(2.5f64);
+        round_trip('λ');
+        round_trip(String::from("str"));
+        round_trip(vec![Some(1i32), None]);
+        round_trip((1i64, String::from("a"), vec![true]));
+        round_trip(HashMap::from([(String::from("k"), vec![1u32, 2])]));
+
+        assert_eq!(u8::from_scm(&300i64.into_scm()), None);
+        let big = u64::MAX.into_scm();
+        assert!(scm_equal(
+            &big,
+            &ScmValue::Number(TypedNum::Float(u64::MAX as f64))
+        ));
+        assert_eq!(u64::from_scm(&big), None);
+        round_trip(i64::MAX as u64);
+        assert_eq!(f64::from_scm(&3i64.into_scm()), Some(3.0));
+        assert_eq!(<(i64, i64)>::from_scm(&vec![1i64].into_scm()), None);
+    }
+
+    #[test]
+    fn typed_functions() {
+        fn repeat(cnt: i64, s: String) -> Vec<String> {
+            (0..cnt).map(|_| s.clone()).collect()
+        }
+        let mut ctx = ScmExecContext::new();
+        ctx.define_fn("repeat", "List of cnt copies of the string", repeat);
+        ctx.define_fn("halve", "Half of the number", |num: f64| num / 2.0);
+        ctx.define_fn(
+            "checked-div",
+            "Integer division",
+            |a: i64, b: i64| match a.checked_div(b) {
+                Some(res) => Ok(res),
+                None => Err(scm_error!("Division by zero")),
+            },
+        );
+        let code = "
+            (list (repeat 2 \"ab\")
+                  (halve 3)
+                  (checked-div 7 2)
+                  (guard (err (#t (error-object-message err))) (checked-div 1 0))
+                  (guard (err (#t (error-object-message err))) (repeat \"2\" \"ab\"))
+                  (guard (err (#t (error-object-message err))) (repeat 2)))";
+        let expected = "'((\"ab\" \"ab\") 1.5 3 \"Division by zero\"
+                          \"REPEAT requires argument of type Integer\"
+                          \"REPEAT requires exactly 2 arguments\")";
+        assert!(scm_equal(&run_in(&mut ctx, code), &run(expected)));
+    }
+}
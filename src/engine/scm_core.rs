@@ -1,193 +1,433 @@
 use crate::{
-    engine::{scm_builtins::*, scm_is_true},
+    engine::{
+        scm_builtins::*,
+        scm_error::{ScmError, ScmErrorObject, ScmResult},
+        scm_foreign::ScmForeign,
+        scm_hash::ScmHashTable,
+        scm_library::{ScmEnvironment, ScmLibrary},
+        scm_limits::{track_alloc, ScmInterruptHandle, ScmLimits, ScmUsage},
+        scm_native::ScmNative,
+        scm_port::{ScmPort, ScmPortParams},
+        scm_print::scm_write,
+        scm_record::{ScmRecord, ScmRecordProc, ScmRecordType},
+        scm_sandbox::{ScmCapability, ScmFsAccess},
+        vm::{ScmContinuation, ScmRunId},
+    },
+    symbol::Symbol,
     typed_num::TypedNum,
-    VariablesSet,
+    GlobalSlots,
 };
 use core::fmt;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem,
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+    rc::Rc,
+};
 
 #[derive(Clone)]
 pub enum ScmValue {
     Number(TypedNum),
     Bool(bool),
     Char(char),
-    String(String),
-    Symbol(String),
-    DotPair(Box<ScmValue>, Box<ScmValue>),
+    String(Rc<RefCell<String>>),
+    Symbol(Symbol),
+    DotPair(Rc<RefCell<ScmPair>>),
+    Vector(Rc<RefCell<ScmVector>>),
     Nil,
     Procedure(ScmCallable),
+    Error(Rc<ScmErrorObject>),
+    Record(Rc<ScmRecord>),
+    RecordType(Rc<ScmRecordType>),
+    HashTable(Rc<RefCell<ScmHashTable>>),
+    Port(Rc<ScmPort>),
+    Eof, // End of file object, returned by reading procedures
+    Environment(Rc<ScmEnvironment>),
+    Foreign(Rc<ScmForeign>), // Object of the host program
 }
 
+// Pairs and other compound values are shared, so they have identity (for eq?)
+// and can be mutated in place.
 #[derive(Clone)]
-pub enum ScmCallable {
-    Builtin(fn(ctx: &mut ScmExecContext, args: &[ScmValue]) -> ScmValue),
-    CustomProc(ScmProcedure),
+pub struct ScmPair {
+    pub car: ScmValue,
+    pub cdr: ScmValue,
 }
 
+// Items of a vector, usable as a Vec
+#[derive(Clone, Default)]
+pub struct ScmVector(pub Vec<ScmValue>);
+
 #[derive(Clone)]
-pub struct ScmProcedure {
-    pub params: Vec<String>,
-    pub instructions: Vec<ScmProcUnit>,
+pub enum ScmCallable {
+    Builtin(fn(ctx: &mut ScmExecContext, args: &[ScmValue]) -> ScmResult),
+    Native(Rc<ScmNative>), // Closure registered by the host program
+    CustomProc(Rc<ScmProcedure>),
+    Primitive(ScmPrimitive),
+    Continuation(Rc<ScmContinuation>),
+    Parameter(Rc<ScmParameter>),
+    Record(Rc<ScmRecordProc>), // Constructor, predicate, accessor or modifier
 }
 
-// Used only as element of procedure's stack
-#[derive(Debug, Clone)]
-pub enum ScmProcUnit {
-    Val(ScmValue),
-    Variable(String),
-    ProcCall(String, usize), // Name and args cnt
-    Lambda { args: Vec<String>, units_cnt: usize },
-    TrueBranch(usize),  // Skip size
-    FalseBranch(usize), // Skip size
-    Assign(String),     // Define and assign are same here
+// Procedures that manipulate the control state of the VM, so they are
+// implemented by the VM itself instead of being builtins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScmPrimitive {
+    Apply,
+    CallCC, // Call with re-entrant continuation
+    CallEC, // Call with one-shot escape continuation
+    DynamicWind,
+    Parameterize,          // Used by the compiler for `parameterize` forms
+    Prelude(&'static str), // Procedure written in Scheme, compiled on first call
+    Raise,
+    RaiseContinuable,
+    WithExceptionHandler,
 }
 
-pub struct ScmExecContext {
-    pub variables: VariablesSet<ScmValue>,
+// Parameter object. Its value can be rebound by `parameterize`, otherwise
+// calling it returns the initial value.
+pub struct ScmParameter {
+    pub value: ScmValue,
+    pub converter: Option<ScmCallable>,
 }
 
-fn find_arg(name: &str, args: &[ScmValue], args_names: &[String]) -> Option<ScmValue> {
-    assert!(args.len() == args_names.len());
-    let mut res = None;
-    for i in 0..args_names.len() {
-        if *name == args_names[i] {
-            res = Some(args[i].clone());
-            break;
-        }
-    }
-    res
+// Entry of `dynamic-wind`, which is active while its thunk is running
+pub struct ScmWinder {
+    pub before: ScmCallable,
+    pub after: ScmCallable,
+    pub depth: usize,
+    pub outer: ScmDynamicState, // State of the `dynamic-wind` call, the thunks run in it
 }
 
-fn find_var(
-    name: &str,
-    args: &[ScmValue],
-    args_names: &[String],
-    ctx: &mut ScmExecContext,
-) -> Option<ScmValue> {
-    find_arg(name, args, args_names).or_else(|| ctx.variables.find_var(name))
+// Value given to the parameter by `parameterize`
+pub struct ScmParamBinding {
+    pub param: Rc<ScmParameter>,
+    pub value: ScmValue,
+    pub parent: Option<Rc<ScmParamBinding>>,
 }
 
-fn exec_custom_proc(
-    ctx: &mut ScmExecContext,
-    proc: &ScmProcedure,
-    call_args: &[ScmValue],
-) -> ScmValue {
-    let mut stack = Vec::<ScmValue>::new();
-    let mut iter = proc.instructions.iter().rev();
+// Exception handler installed by `with-exception-handler`
+pub struct ScmHandler {
+    pub handler: ScmCallable,
+    pub parent: Option<Rc<ScmHandler>>,
+}
 
-    while let Some(proc_unit) = iter.next() {
-        match proc_unit {
-            ScmProcUnit::Val(v) => {
-                stack.push(v.clone());
-            }
+// Active winders, parameter bindings and exception handlers. All of them are
+// persistent lists, so continuations capture the dynamic state by cloning
+// a few pointers.
+#[derive(Clone, Default)]
+pub struct ScmDynamicState {
+    pub winders: Option<Rc<ScmWinder>>,
+    pub params: Option<Rc<ScmParamBinding>>,
+    pub handlers: Option<Rc<ScmHandler>>,
+}
 
-            ScmProcUnit::Variable(name) => {
-                let var = find_var(name, call_args, &proc.params, ctx);
-                assert!(var.is_some(), "Unknown variable: {}", name);
-                stack.push(var.unwrap());
-            }
+// Closure: code shared by all instances of a lambda plus the environment
+// the instance was created in
+#[derive(Clone)]
+pub struct ScmProcedure {
+    pub template: Rc<ScmProcTemplate>,
+    pub env: Option<Rc<ScmEnv>>,
+}
 
-            ScmProcUnit::ProcCall(proc_name, args_cnt) => {
-                let mut args = Vec::<ScmValue>::new();
-                for _ in 0..*args_cnt {
-                    args.push(stack.pop().unwrap())
-                }
+// Compiled lambda. Created once by the compiler, closures only reference it.
+pub struct ScmProcTemplate {
+    pub name: Option<Symbol>,
+    pub params_cnt: usize,
+    pub has_rest: bool, // Extra arguments are passed as a list in the last param
+    pub frame_size: usize, // Params and internal definitions
+    pub code: Vec<ScmOp>,
+    pub consts: Vec<ScmValue>,
+    pub templates: Vec<Rc<ScmProcTemplate>>,
+}
 
-                let var = find_var(proc_name, call_args, &proc.params, ctx);
-                assert!(var.is_some(), "Unknown procedure: {}", proc_name);
+// Frame of procedure arguments and internal definitions. Frames are chained
+// to the frame of the enclosing procedure, so closures see variables of outer
+// procedures.
+pub struct ScmEnv {
+    pub vals: RefCell<Vec<ScmValue>>,
+    pub parent: Option<Rc<ScmEnv>>,
+}
 
-                if let ScmValue::Procedure(proc) = var.unwrap() {
-                    let res = match proc {
-                        ScmCallable::Builtin(func) => (func)(ctx, &args),
-                        ScmCallable::CustomProc(proc) => {
-                            exec_callable(ctx, &ScmCallable::CustomProc(proc), &args)
-                        }
-                    };
+// Bytecode instruction. Every operand fits into 32 bits, so instructions
+// stay 8 bytes long.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScmOp {
+    Const(u32),           // Push constant from the pool
+    LocalRef(u16, u16),   // Push local variable by (depth, index)
+    LocalSet(u16, u16),   // Replace stack top with nil, assign it to local variable
+    GlobalRef(Symbol),    // Push global variable
+    GlobalSet(Symbol),    // Replace stack top with nil, assign it to existing global variable
+    GlobalDefine(Symbol), // Replace stack top with nil, define global variable with it
+    Closure(u32),         // Create procedure from template and push it
+    Jump(u32),            // Jump forward to the absolute position
+    JumpIfFalse(u32),     // Pop a value and jump forward if it is false
+    Call(u32),            // Call procedure with args cnt arguments on the stack top
+    TailCall(u32),        // Same, but reuses the current frame
+    Return,
+    Pop,
+}
 
-                    stack.push(res);
-                } else {
-                    panic!("Variable cannot be called: {}", proc_name);
-                }
-            }
+pub struct ScmExecContext {
+    pub globals: GlobalSlots<ScmValue>,
+    pub dynamic_state: ScmDynamicState,
+    pub ports: ScmPortParams,
+    // File being loaded, relative paths of `load` are resolved against it
+    pub loading: Option<PathBuf>,
+    // Libraries defined by `define-library`, by names like "foo bar"
+    pub libraries: HashMap<String, ScmLibrary>,
+    // Directories searched for files of libraries that are not defined yet
+    pub library_path: Vec<PathBuf>,
+    pub limits: ScmLimits,
+    pub(crate) usage: ScmUsage,
+    // Runs of the VM in progress, innermost last
+    pub(crate) vm_runs: Vec<ScmRunId>,
+    // Groups of builtins the context was created with
+    capabilities: Vec<ScmCapability>,
+    // Files Scheme code may access, unrestricted unless sandboxed
+    pub fs_access: ScmFsAccess,
+    // Called on interrupts instead of stopping the evaluation, which
+    // continues if it returns
+    pub interrupt_handler: Option<ScmCallable>,
+}
 
-            ScmProcUnit::Lambda { args, units_cnt } => {
-                let mut data = Vec::<ScmProcUnit>::new();
-                for _ in 0..*units_cnt {
-                    let unit = iter.next().unwrap();
-                    let val = match unit {
-                        ScmProcUnit::Variable(name) => {
-                            if args.iter().any(|it| name == it) {
-                                unit.clone()
-                            } else {
-                                let var = find_var(name, call_args, &proc.params, ctx);
-                                match var {
-                                    None => unit.clone(),
-                                    Some(_) => ScmProcUnit::Val(var.unwrap()),
-                                }
-                            }
-                        }
-
-                        _ => unit.clone(),
-                    };
-                    data.push(val);
-                }
+fn is_compound(val: &ScmValue) -> bool {
+    matches!(
+        val,
+        ScmValue::DotPair(_)
+            | ScmValue::Vector(_)
+            | ScmValue::Record(_)
+            | ScmValue::HashTable(_)
+            | ScmValue::Procedure(_)
+    )
+}
 
-                data.reverse();
-                stack.push(ScmValue::Procedure(ScmCallable::CustomProc(ScmProcedure {
-                    params: args.clone(),
-                    instructions: data,
-                })));
-            }
+thread_local! {
+    // Values left to drop by the outermost `drop_later`
+    static DROP_QUEUE: RefCell<Option<Vec<ScmValue>>> = const { RefCell::new(None) };
+}
 
-            ScmProcUnit::TrueBranch(skip_cnt) => {
-                let cond = stack.pop().unwrap();
-                if !scm_is_true(&cond) {
-                    for _ in 0..*skip_cnt {
-                        iter.next();
-                    }
+// Dropping nested data would otherwise drop its parts recursively, one Rust
+// frame per level of nesting. Compound values give their parts to this
+// function instead, which queues them while an outer call drops values in a
+// loop.
+pub(crate) fn drop_later(val: ScmValue) {
+    if !is_compound(&val) {
+        return;
+    }
+    let mut val = Some(val);
+    let outermost = DROP_QUEUE
+        .try_with(|queue| {
+            let mut queue = queue.borrow_mut();
+            match queue.as_mut() {
+                Some(queue) => {
+                    queue.push(val.take().unwrap());
+                    false
+                }
+                None => {
+                    *queue = Some(Vec::new());
+                    true
                 }
             }
+        })
+        .unwrap_or(false);
+    if !outermost {
+        return;
+    }
+    drop(val);
+    while let Some(val) = DROP_QUEUE.with(|queue| queue.borrow_mut().as_mut().unwrap().pop()) {
+        drop(val);
+    }
+    DROP_QUEUE.with(|queue| *queue.borrow_mut() = None);
+}
 
-            ScmProcUnit::FalseBranch(skip_cnt) => {
-                iter.next();
-                for _ in 0..*skip_cnt {
-                    iter.next();
+// The cdr chain of a list is unlinked in a loop, up to the first pair shared
+// with other values, so long lists don't go through the queue
+impl Drop for ScmPair {
+    fn drop(&mut self) {
+        drop_later(mem::replace(&mut self.car, ScmValue::Nil));
+        let mut next = mem::replace(&mut self.cdr, ScmValue::Nil);
+        loop {
+            next = match next {
+                ScmValue::DotPair(pair) if Rc::strong_count(&pair) == 1 => {
+                    mem::replace(&mut pair.borrow_mut().cdr, ScmValue::Nil)
                 }
-            }
+                _ => break,
+            };
+        }
+        drop_later(next);
+    }
+}
 
-            ScmProcUnit::Assign(name) => {
-                let val = stack.pop().unwrap();
-                ctx.variables.add_or_assign_var(name, val);
-            }
+impl Drop for ScmVector {
+    fn drop(&mut self) {
+        self.0.drain(..).for_each(drop_later);
+    }
+}
+
+// Most frames hold no compound values, those are dropped as they are
+impl Drop for ScmEnv {
+    fn drop(&mut self) {
+        let vals = self.vals.get_mut();
+        if vals.iter().any(is_compound) {
+            vals.drain(..).for_each(drop_later);
+        }
+    }
+}
+
+impl Deref for ScmVector {
+    type Target = Vec<ScmValue>;
+
+    fn deref(&self) -> &Vec<ScmValue> {
+        &self.0
+    }
+}
+
+impl DerefMut for ScmVector {
+    fn deref_mut(&mut self) -> &mut Vec<ScmValue> {
+        &mut self.0
+    }
+}
+
+impl ScmEnv {
+    pub fn lookup(&self, depth: usize, index: usize) -> ScmValue {
+        let mut env = self;
+        for _ in 0..depth {
+            env = env.parent.as_ref().unwrap();
+        }
+        env.vals.borrow()[index].clone()
+    }
+
+    pub fn assign(&self, depth: usize, index: usize, val: ScmValue) {
+        let mut env = self;
+        for _ in 0..depth {
+            env = env.parent.as_ref().unwrap();
         }
+        env.vals.borrow_mut()[index] = val;
+    }
+}
+
+impl ScmValue {
+    pub fn cons(car: ScmValue, cdr: ScmValue) -> ScmValue {
+        track_alloc(mem::size_of::<ScmPair>());
+        ScmValue::DotPair(Rc::new(RefCell::new(ScmPair { car, cdr })))
+    }
+
+    pub fn new_string(s: &str) -> ScmValue {
+        ScmValue::from_string(String::from(s))
     }
 
-    stack.pop().unwrap()
+    // Takes the string without copying it
+    pub fn from_string(s: String) -> ScmValue {
+        track_alloc(mem::size_of::<String>() + s.len());
+        ScmValue::String(Rc::new(RefCell::new(s)))
+    }
+
+    pub fn new_vector(vec: Vec<ScmValue>) -> ScmValue {
+        track_alloc(mem::size_of::<Vec<ScmValue>>() + vec.len() * mem::size_of::<ScmValue>());
+        ScmValue::Vector(Rc::new(RefCell::new(ScmVector(vec))))
+    }
 }
 
-pub fn exec_callable(
-    ctx: &mut ScmExecContext,
-    proc: &ScmCallable,
-    call_args: &[ScmValue],
-) -> ScmValue {
-    match proc {
-        ScmCallable::Builtin(func) => (func)(ctx, call_args),
-        ScmCallable::CustomProc(proc) => exec_custom_proc(ctx, proc, call_args),
+impl ScmDynamicState {
+    pub fn param_value(&self, param: &Rc<ScmParameter>) -> ScmValue {
+        let mut binding = &self.params;
+        while let Some(cur) = binding {
+            if Rc::ptr_eq(&cur.param, param) {
+                return cur.value.clone();
+            }
+            binding = &cur.parent;
+        }
+        param.value.clone()
     }
 }
 
 impl ScmExecContext {
     pub fn new() -> ScmExecContext {
+        Self::with_capabilities(&ScmCapability::ALL, ScmFsAccess::unrestricted())
+    }
+
+    // Context with only the given groups of builtins and no access to files,
+    // directories are allowed by `fs_access.allow_dir`
+    pub fn sandboxed(capabilities: &[ScmCapability]) -> ScmExecContext {
+        Self::with_capabilities(capabilities, ScmFsAccess::deny_all())
+    }
+
+    fn with_capabilities(capabilities: &[ScmCapability], fs_access: ScmFsAccess) -> ScmExecContext {
         let mut ctx = Self {
-            variables: VariablesSet::new(),
+            globals: GlobalSlots::new(),
+            dynamic_state: ScmDynamicState::default(),
+            ports: ScmPortParams::new(),
+            loading: None,
+            libraries: HashMap::new(),
+            library_path: Vec::new(),
+            limits: ScmLimits::default(),
+            usage: ScmUsage::default(),
+            vm_runs: Vec::new(),
+            capabilities: capabilities.to_vec(),
+            fs_access,
+            interrupt_handler: None,
         };
         for builtin in BUILTINS_LIST.iter() {
-            ctx.add_or_assign_var(builtin.0, builtin.1.clone());
+            if ctx.allows_builtin(builtin.0) {
+                ctx.add_or_assign_var(builtin.0, builtin.1.clone());
+            }
+        }
+        for (name, param) in ctx.ports.globals() {
+            if ctx.allows_builtin(name) {
+                ctx.add_or_assign_var(name, param);
+            }
         }
         ctx
     }
 
+    pub fn capabilities(&self) -> &[ScmCapability] {
+        &self.capabilities
+    }
+
+    pub fn has_capability(&self, capability: ScmCapability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    // Whether the builtin or port parameter belongs to a group of the context
+    pub fn allows_builtin(&self, name: &str) -> bool {
+        self.has_capability(ScmCapability::of_builtin(name))
+    }
+
     pub fn add_or_assign_var(&mut self, name: &str, val: ScmValue) {
-        self.variables.add_or_assign_var(name, val);
+        self.globals.add_or_assign_var(Symbol::intern(name), val);
+    }
+
+    // Handle for interrupting evaluations of the context from other threads
+    pub fn interrupt_handle(&self) -> ScmInterruptHandle {
+        self.usage.interrupt.clone()
+    }
+
+    // For native procedures about to allocate a lot, fails if the allocation
+    // would exceed the allocation budget of the evaluation
+    pub fn reserve_alloc(&self, bytes: usize) -> Result<(), ScmError> {
+        self.usage.reserve(&self.limits, bytes)
+    }
+
+    // Counts bytes allocated by the VM itself, only while the allocations are
+    // limited, so unlimited evaluations don't pay for it
+    #[inline]
+    pub(crate) fn track_alloc(&self, bytes: usize) {
+        if self.limits.max_alloc.is_some() {
+            track_alloc(bytes);
+        }
+    }
+
+    // For native procedures running long loops, fails if the evaluation is
+    // interrupted or out of time
+    pub fn poll_limits(&self) -> Result<(), ScmError> {
+        self.usage.poll()
+    }
+
+    pub fn add_library_path(&mut self, dir: impl Into<PathBuf>) {
+        self.library_path.push(dir.into());
     }
 }
 
@@ -197,26 +437,99 @@ impl Default for ScmExecContext {
     }
 }
 
-impl fmt::Display for ScmProcedure {
+impl fmt::Display for ScmProcTemplate {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for instr in self.instructions.iter() {
-            writeln!(f, "{:?}", instr)?;
+        match self.name {
+            Some(name) => writeln!(f, "{}:", name)?,
+            None => writeln!(f, "<lambda>:")?,
+        }
+        for (pos, op) in self.code.iter().enumerate() {
+            match op {
+                ScmOp::Const(idx) => {
+                    writeln!(f, "{:4} {:?} ; {:?}", pos, op, self.consts[*idx as usize])?
+                }
+                _ => writeln!(f, "{:4} {:?}", pos, op)?,
+            }
+        }
+        for template in self.templates.iter() {
+            write!(f, "{}", template)?;
         }
         Result::Ok(())
     }
 }
 
+impl fmt::Display for ScmProcedure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
 impl fmt::Debug for ScmValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ScmValue::Number(val) => write!(f, "ScmValue::Number({})", val),
             ScmValue::Bool(val) => write!(f, "ScmValue::Bool({})", val),
             ScmValue::Char(val) => write!(f, "ScmValue::Char({})", val),
-            ScmValue::String(val) => write!(f, "ScmValue::String({})", val),
+            ScmValue::String(val) => write!(f, "ScmValue::String({})", val.borrow()),
             ScmValue::Symbol(val) => write!(f, "ScmValue::Symbol({})", val),
-            ScmValue::DotPair(car, cdr) => write!(f, "({:?} . {:?})", car, cdr),
+            ScmValue::DotPair(_) | ScmValue::Vector(_) => write!(f, "{}", scm_write(self)),
             ScmValue::Nil => write!(f, "nil"),
             ScmValue::Procedure(_) => write!(f, "<proc>"),
+            ScmValue::Error(err) => write!(f, "ScmValue::Error({})", err),
+            ScmValue::Record(record) => write!(f, "{:?}", record),
+            ScmValue::RecordType(rtype) => write!(f, "#<record-type {}>", rtype.display_name()),
+            ScmValue::HashTable(table) => write!(f, "#<hash-table {}>", table.borrow().len()),
+            ScmValue::Port(port) => write!(f, "{:?}", port),
+            ScmValue::Foreign(foreign) => write!(f, "{:?}", foreign),
+            ScmValue::Eof => write!(f, "#<eof>"),
+            ScmValue::Environment(_) => write!(f, "#<environment>"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn long_lists() {
+        // Dropping a long list must not recurse once per element
+        let code = "(define l (iota 1000000)) (set! l #f) (length (iota 1000000))";
+        assert!(scm_equal(&run(code), &int(1000000)));
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // Data nested through cars, vectors and records is read, compared,
+        // printed and dropped without recursion, so a small stack is enough.
+        // Code nested too deeply is rejected by the compiler.
+        let code = "
+            (define-record-type <box> (box x) box? (x unbox))
+            (define (nest n wrap)
+              (let loop ((i 0) (x '()))
+                (if (= i n) x (loop (+ i 1) (wrap x)))))
+            (define a (nest 200000 list))
+            (define port (open-output-string))
+            (write a port)
+            (define res
+              (list (equal? a (nest 200000 list))
+                    (string-length (get-output-string port))
+                    (equal? (nest 200000 vector) (nest 200000 vector))
+                    (string-length (call-with-output-string
+                                     (lambda (port) (display (nest 200000 box) port))))
+                    (pair? (read (open-input-string
+                                   (string-append (make-string 100000 #\\()
+                                                  (make-string 100000 #\\))))))
+                    (guard (e (#t (error-object-message e)))
+                      (eval (nest 100000 (lambda (x) (list 'car x)))
+                            (interaction-environment)))))
+            (set! a #f)
+            res";
+        let thread = std::thread::Builder::new().stack_size(2 << 20);
+        let res = thread.spawn(move || scm_write(&run(code)).to_string());
+        assert_eq!(
+            res.unwrap().join().unwrap(),
+            "(#t 400002 #t 2000002 #t \"Expression is nested too deeply\")"
+        );
+    }
+}
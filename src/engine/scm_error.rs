@@ -0,0 +1,92 @@
+use crate::engine::{
+    scm_core::ScmValue, scm_limits::ScmLimit, scm_print::scm_write, vm::ScmContinuation,
+};
+use core::fmt;
+use std::rc::Rc;
+
+// Kind of the error object, checked by `file-error?` and `read-error?`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScmErrorKind {
+    Error,
+    File,
+    Read,
+}
+
+// Error object created by `error` and by the engine itself
+pub struct ScmErrorObject {
+    pub kind: ScmErrorKind,
+    pub message: String,
+    pub irritants: Vec<ScmValue>,
+}
+
+#[derive(Debug)]
+pub enum ScmError {
+    // Exception raised by `raise` or by a failed operation and not handled
+    // by Scheme code
+    Raised(ScmValue),
+    // Escape continuation invoked in a run of the VM nested into a builtin,
+    // which unwinds the nested runs until the frame it returns from is found
+    Escape(Rc<ScmContinuation>, ScmValue),
+    // Evaluation aborted by a resource limit of the context
+    Limit(ScmLimit),
+    // Evaluation stopped by `ScmInterruptHandle::interrupt`, when no
+    // interrupt handler is installed
+    Interrupted,
+}
+
+pub type ScmResult = Result<ScmValue, ScmError>;
+
+impl ScmError {
+    pub fn new(kind: ScmErrorKind, message: String, irritants: Vec<ScmValue>) -> ScmError {
+        ScmError::Raised(ScmValue::Error(Rc::new(ScmErrorObject {
+            kind,
+            message,
+            irritants,
+        })))
+    }
+}
+
+// Error object with a formatted message and without irritants
+macro_rules! scm_error {
+    ($($arg:tt)*) => {
+        $crate::engine::scm_error::ScmError::new(
+            $crate::engine::scm_error::ScmErrorKind::Error,
+            format!($($arg)*),
+            Vec::new(),
+        )
+    };
+}
+
+// Returns the error from the current function if the condition is false
+macro_rules! scm_ensure {
+    ($cond:expr, $($arg:tt)*) => {
+        if !$cond {
+            return Err($crate::engine::scm_error::scm_error!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use scm_ensure;
+pub(crate) use scm_error;
+
+impl fmt::Display for ScmErrorObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for irritant in self.irritants.iter() {
+            write!(f, " {}", scm_write(irritant))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ScmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScmError::Raised(ScmValue::Error(err)) => write!(f, "Error: {}", err),
+            ScmError::Raised(obj) => write!(f, "Uncaught exception: {}", scm_write(obj)),
+            ScmError::Escape(..) => write!(f, "Escape continuation called outside of its extent"),
+            ScmError::Limit(limit) => write!(f, "Evaluation aborted: {}", limit),
+            ScmError::Interrupted => write!(f, "Evaluation interrupted"),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::engine::{scm_convert::FromScm, scm_core::ScmValue, scm_error::*};
+use core::fmt;
+use std::{any::Any, cell::RefCell, rc::Rc};
+
+type ScmDropHook = Box<dyn FnOnce(&dyn Any)>;
+
+// Object of the host program passed through Scheme code untouched. Scheme
+// sees only its type name, native procedures get the object back by
+// downcasting.
+pub struct ScmForeign {
+    pub type_name: String,
+    pub value: Rc<dyn Any>,
+    on_drop: RefCell<Option<ScmDropHook>>,
+}
+
+impl ScmForeign {
+    pub fn new(type_name: &str, value: Rc<dyn Any>) -> ScmForeign {
+        ScmForeign {
+            type_name: String::from(type_name),
+            value,
+            on_drop: RefCell::new(None),
+        }
+    }
+
+    // The hook gets the object when the last Scheme reference to it is
+    // dropped, even if the host still holds the object itself
+    pub fn with_drop_hook(self, hook: impl FnOnce(&dyn Any) + 'static) -> ScmForeign {
+        self.on_drop.replace(Some(Box::new(hook)));
+        self
+    }
+
+    pub fn downcast<T: Any>(&self) -> Option<Rc<T>> {
+        self.value.clone().downcast::<T>().ok()
+    }
+}
+
+impl Drop for ScmForeign {
+    fn drop(&mut self) {
+        if let Some(hook) = self.on_drop.take() {
+            hook(&*self.value);
+        }
+    }
+}
+
+impl fmt::Debug for ScmForeign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<foreign {}>", self.type_name)
+    }
+}
+
+impl ScmValue {
+    pub fn foreign<T: Any>(type_name: &str, value: T) -> ScmValue {
+        ScmValue::Foreign(Rc::new(ScmForeign::new(type_name, Rc::new(value))))
+    }
+
+    // Host object of the type T, None for other values
+    pub fn downcast_foreign<T: Any>(&self) -> Option<Rc<T>> {
+        match self {
+            ScmValue::Foreign(foreign) => foreign.downcast(),
+            _ => None,
+        }
+    }
+}
+
+// Argument of a native procedure holding a host object of the type T
+pub fn expect_foreign<T: Any>(name: &str, val: &ScmValue) -> Result<Rc<T>, ScmError> {
+    match val.downcast_foreign() {
+        Some(obj) => Ok(obj),
+        None => Err(scm_error!(
+            "{} requires argument of type {}",
+            name.to_uppercase(),
+            std::any::type_name::<T>()
+        )),
+    }
+}
+
+impl<T: Any> FromScm for Rc<T> {
+    const TYPE: &'static str = "Foreign";
+
+    fn from_scm(val: &ScmValue) -> Option<Self> {
+        val.downcast_foreign()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn foreign_objects() {
+        struct Document {
+            title: String,
+        }
+        let dropped = std::rc::Rc::new(std::cell::Cell::new(false));
+        let flag = dropped.clone();
+        let mut ctx = ScmExecContext::new();
+        ctx.define_native(
+            "open-doc",
+            ScmArity::exact(0),
+            "New document",
+            move |_, _| {
+                let doc = Document {
+                    title: String::from("notes"),
+                };
+                let flag = flag.clone();
+                let foreign = ScmForeign::new("Document", std::rc::Rc::new(doc))
+                    .with_drop_hook(move |_| flag.set(true));
+                Ok(ScmValue::Foreign(std::rc::Rc::new(foreign)))
+            },
+        );
+        ctx.define_fn(
+            "doc-title",
+            "Title of the document",
+            |doc: std::rc::Rc<Document>| doc.title.clone(),
+        );
+        let code = "
+            (define doc (open-doc))
+            (list (doc-title doc)
+                  (call-with-output-string (lambda (port) (display doc port)))
+                  (eqv? doc doc)
+                  (guard (err (#t (error-object-message err))) (doc-title 1)))";
+        let expected =
+            "'(\"notes\" \"#<foreign Document>\" #t \"DOC-TITLE requires argument of type Foreign\")";
+        assert!(scm_equal(&run_in(&mut ctx, code), &run(expected)));
+        assert!(ScmValue::foreign("Number", 5i64)
+            .downcast_foreign::<i64>()
+            .is_some());
+        assert!(!dropped.get());
+        run_in(&mut ctx, "(set! doc #f)");
+        assert!(dropped.get());
+    }
+}
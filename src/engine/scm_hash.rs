@@ -0,0 +1,272 @@
+use crate::engine::{
+    scm_core::{drop_later, ScmValue},
+    scm_error::*,
+    scm_limits::track_alloc,
+    scm_utils::{scm_eq, scm_equal, scm_eqv},
+    typed_num::TypedNum,
+};
+use std::{
+    collections::HashMap,
+    hash::{BuildHasherDefault, DefaultHasher, Hash, Hasher},
+    mem, ptr,
+    rc::Rc,
+};
+
+// Equality predicate of the hash table. Hashes of keys are consistent with
+// it: keys equal by the predicate have equal hashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScmHashKind {
+    Eq,
+    Eqv,
+    Equal,
+    String, // Keys are strings compared by contents
+}
+
+// Compound values hashed by `equal?` contribute at most this many nodes, so
+// hashing of long or cyclic structures stops
+const EQUAL_HASH_BUDGET: usize = 64;
+
+// Entries are grouped by the hash of the key. The hasher has fixed keys, so
+// the order of entries doesn't change between runs.
+pub struct ScmHashTable {
+    pub kind: ScmHashKind,
+    buckets: HashMap<u64, Vec<(ScmValue, ScmValue)>, BuildHasherDefault<DefaultHasher>>,
+    len: usize,
+}
+
+impl Drop for ScmHashTable {
+    fn drop(&mut self) {
+        for (_, entries) in self.buckets.drain() {
+            for (key, val) in entries {
+                drop_later(key);
+                drop_later(val);
+            }
+        }
+    }
+}
+
+fn hash_eqv(val: &ScmValue, state: &mut DefaultHasher) {
+    mem::discriminant(val).hash(state);
+    match val {
+        ScmValue::Number(TypedNum::Integer(num)) => num.hash(state),
+        ScmValue::Number(TypedNum::Float(num)) => num.to_bits().hash(state),
+        ScmValue::Bool(val) => val.hash(state),
+        ScmValue::Char(val) => val.hash(state),
+        ScmValue::Symbol(sym) => sym.id().hash(state),
+        ScmValue::String(s) => ptr::hash(Rc::as_ptr(s), state),
+        ScmValue::DotPair(pair) => ptr::hash(Rc::as_ptr(pair), state),
+        ScmValue::Vector(vec) => ptr::hash(Rc::as_ptr(vec), state),
+        ScmValue::Error(err) => ptr::hash(Rc::as_ptr(err), state),
+        ScmValue::Record(record) => ptr::hash(Rc::as_ptr(record), state),
+        ScmValue::RecordType(rtype) => ptr::hash(Rc::as_ptr(rtype), state),
+        ScmValue::HashTable(table) => ptr::hash(Rc::as_ptr(table), state),
+        ScmValue::Port(port) => ptr::hash(Rc::as_ptr(port), state),
+        ScmValue::Foreign(foreign) => ptr::hash(Rc::as_ptr(foreign), state),
+        ScmValue::Environment(env) => ptr::hash(Rc::as_ptr(env), state),
+        // Procedures of different kinds are compared differently, all of
+        // them share one hash
+        ScmValue::Nil | ScmValue::Eof | ScmValue::Procedure(_) => (),
+    }
+}
+
+fn hash_equal(val: &ScmValue, state: &mut DefaultHasher, budget: &mut usize) {
+    if *budget == 0 {
+        return;
+    }
+    *budget -= 1;
+
+    match val {
+        ScmValue::String(s) => {
+            mem::discriminant(val).hash(state);
+            s.borrow().hash(state);
+        }
+        ScmValue::DotPair(pair) => {
+            mem::discriminant(val).hash(state);
+            let pair = pair.borrow();
+            hash_equal(&pair.car, state, budget);
+            hash_equal(&pair.cdr, state, budget);
+        }
+        ScmValue::Vector(vec) => {
+            mem::discriminant(val).hash(state);
+            let vec = vec.borrow();
+            vec.len().hash(state);
+            for item in vec.iter() {
+                hash_equal(item, state, budget);
+            }
+        }
+        _ => hash_eqv(val, state),
+    }
+}
+
+impl ScmHashKind {
+    pub fn hash(self, key: &ScmValue) -> u64 {
+        let mut state = DefaultHasher::new();
+        let mut budget = EQUAL_HASH_BUDGET;
+        match (self, key) {
+            (ScmHashKind::Eq | ScmHashKind::Eqv, _) => hash_eqv(key, &mut state),
+            (ScmHashKind::Equal, _) => hash_equal(key, &mut state, &mut budget),
+            (ScmHashKind::String, ScmValue::String(s)) => s.borrow().hash(&mut state),
+            (ScmHashKind::String, _) => (),
+        }
+        state.finish()
+    }
+
+    pub fn equal(self, k1: &ScmValue, k2: &ScmValue) -> bool {
+        match self {
+            ScmHashKind::Eq => scm_eq(k1, k2),
+            ScmHashKind::Eqv => scm_eqv(k1, k2),
+            ScmHashKind::Equal => scm_equal(k1, k2),
+            ScmHashKind::String => match (k1, k2) {
+                (ScmValue::String(s1), ScmValue::String(s2)) => *s1.borrow() == *s2.borrow(),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl ScmHashTable {
+    pub fn new(kind: ScmHashKind) -> Self {
+        Self {
+            kind,
+            buckets: HashMap::default(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn check_key(&self, key: &ScmValue) -> Result<(), ScmError> {
+        scm_ensure!(
+            self.kind != ScmHashKind::String || matches!(key, ScmValue::String(_)),
+            "Keys of string hash table must be strings"
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, key: &ScmValue) -> Option<ScmValue> {
+        let bucket = self.buckets.get(&self.kind.hash(key))?;
+        bucket
+            .iter()
+            .find(|(k, _)| self.kind.equal(k, key))
+            .map(|(_, v)| v.clone())
+    }
+
+    // Approximate memory taken by an entry, counted when it's added
+    pub const ENTRY_SIZE: usize =
+        mem::size_of::<(u64, Vec<(ScmValue, ScmValue)>)>() + mem::size_of::<(ScmValue, ScmValue)>();
+
+    pub fn insert(&mut self, key: ScmValue, val: ScmValue) {
+        let kind = self.kind;
+        let bucket = self.buckets.entry(kind.hash(&key)).or_default();
+        match bucket.iter_mut().find(|(k, _)| kind.equal(k, &key)) {
+            Some(entry) => entry.1 = val,
+            None => {
+                track_alloc(Self::ENTRY_SIZE);
+                bucket.push((key, val));
+                self.len += 1;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &ScmValue) -> bool {
+        let kind = self.kind;
+        let hash = kind.hash(key);
+        let Some(bucket) = self.buckets.get_mut(&hash) else {
+            return false;
+        };
+        let Some(pos) = bucket.iter().position(|(k, _)| kind.equal(k, key)) else {
+            return false;
+        };
+        bucket.swap_remove(pos);
+        if bucket.is_empty() {
+            self.buckets.remove(&hash);
+        }
+        self.len -= 1;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+        self.len = 0;
+    }
+
+    // Copy of the entries, so the table can be modified while they are
+    // processed
+    pub fn entries(&self) -> Vec<(ScmValue, ScmValue)> {
+        self.buckets
+            .values()
+            .flat_map(|bucket| bucket.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::test_utils::*, *};
+
+    fn list(items: &[ScmValue]) -> ScmValue {
+        items.iter().rev().fold(ScmValue::Nil, |list, item| {
+            ScmValue::cons(item.clone(), list)
+        })
+    }
+
+    #[test]
+    fn consistent_hashing() {
+        let int = |val| ScmValue::Number(TypedNum::Integer(val));
+        let key1 = list(&[int(1), ScmValue::new_string("a")]);
+        let key2 = list(&[int(1), ScmValue::new_string("a")]);
+
+        let mut table = ScmHashTable::new(ScmHashKind::Equal);
+        table.insert(key1.clone(), int(10));
+        table.insert(key2.clone(), int(20));
+        assert_eq!(table.len(), 1);
+        assert!(scm_eqv(&table.get(&key1).unwrap(), &int(20)));
+
+        let mut table = ScmHashTable::new(ScmHashKind::Eqv);
+        table.insert(key1.clone(), int(10));
+        assert!(table.get(&key2).is_none());
+        table.insert(ScmValue::Number(TypedNum::Float(0.0)), int(1));
+        assert!(table
+            .get(&ScmValue::Number(TypedNum::Float(-0.0)))
+            .is_none());
+        table.insert(ScmValue::Number(TypedNum::Float(f64::NAN)), int(2));
+        assert!(table
+            .get(&ScmValue::Number(TypedNum::Float(f64::NAN)))
+            .is_some());
+        assert!(table.get(&int(0)).is_none());
+        assert!(table.remove(&key1));
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn hash_tables() {
+        let code = "
+            (define h (make-hash-table))
+            (hash-table-set! h '(1 2) 'a)
+            (hash-table-set! h \"key\" 1)
+            (hash-table-update! h \"key\" (lambda (x) (+ x 1)))
+            (hash-table-update!/default h 'missing (lambda (x) (+ x 1)) 10)
+            (define sum 0)
+            (hash-table-walk h (lambda (k v) (if (number? v) (set! sum (+ sum v)))))
+            (define e (make-hash-table eq?))
+            (hash-table-set! e (list 1) 'x)
+            (define s (make-hash-table string=?))
+            (hash-table-set! s \"a\" 1)
+            (hash-table-delete! s \"a\")
+            (list (hash-table-ref h (list 1 2))
+                  (hash-table-ref/default h \"key\" #f)
+                  sum
+                  (hash-table-ref e (list 1) (lambda () 'none))
+                  (hash-table-size s)
+                  (guard (err (#t (error-object-message err))) (hash-table-set! s 'b 1)))";
+        let expected = "'(a 2 13 none 0 \"Keys of string hash table must be strings\")";
+        assert!(scm_equal(&run(code), &run(expected)));
+    }
+}
@@ -0,0 +1,507 @@
+use crate::{
+    engine::{
+        scm_builtins::{builtin_value, BUILTINS_LIST},
+        scm_core::{ScmExecContext, ScmValue},
+        scm_error::*,
+        scm_print::scm_display,
+        scm_utils::{scm_is_list, scm_list_to_vec},
+        symbol::{symbol_size, Symbol},
+        vm::exec_callable,
+    },
+    frontend::{compile_library, load_file},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    rc::Rc,
+};
+
+// Standard libraries and the builtins they export. (scheme base) exports
+// all builtins not listed here.
+const LIBRARIES: &[(&str, &[&str])] = &[
+    (
+        "scheme char",
+        &[
+            "char-ci=?",
+            "char-ci<?",
+            "char-ci>?",
+            "char-ci<=?",
+            "char-ci>=?",
+            "char-alphabetic?",
+            "char-numeric?",
+            "char-whitespace?",
+            "char-upper-case?",
+            "char-lower-case?",
+            "char-upcase",
+            "char-downcase",
+            "char-foldcase",
+            "digit-value",
+            "string-ci=?",
+            "string-ci<?",
+            "string-ci>?",
+            "string-ci<=?",
+            "string-ci>=?",
+            "string-upcase",
+            "string-downcase",
+            "string-foldcase",
+        ],
+    ),
+    (
+        "scheme cxr",
+        &[
+            "caaar", "caadr", "cadar", "caddr", "cdaar", "cdadr", "cddar", "cdddr", "caaaar",
+            "caaadr", "caadar", "caaddr", "cadaar", "cadadr", "caddar", "cadddr", "cdaaar",
+            "cdaadr", "cdadar", "cdaddr", "cddaar", "cddadr", "cdddar", "cddddr",
+        ],
+    ),
+    ("scheme write", &["display", "write"]),
+    ("scheme read", &["read"]),
+    (
+        "scheme file",
+        &[
+            "open-input-file",
+            "open-output-file",
+            "call-with-output-file",
+            "with-output-to-file",
+        ],
+    ),
+    ("scheme eval", &["eval", "environment"]),
+    ("scheme load", &["load"]),
+    ("scheme repl", &["interaction-environment"]),
+    (
+        "scheme r5rs",
+        &["scheme-report-environment", "null-environment"],
+    ),
+    (
+        "srfi 1",
+        &[
+            "filter",
+            "remove",
+            "delete",
+            "fold-left",
+            "fold-right",
+            "reduce",
+            "last",
+            "iota",
+            "list-sort",
+        ],
+    ),
+    (
+        "srfi 69",
+        &[
+            "make-hash-table",
+            "hash-table?",
+            "hash-table-ref",
+            "hash-table-ref/default",
+            "hash-table-set!",
+            "hash-table-delete!",
+            "hash-table-contains?",
+            "hash-table-update!",
+            "hash-table-update!/default",
+            "hash-table-size",
+            "hash-table-keys",
+            "hash-table-values",
+            "hash-table->alist",
+            "hash-table-walk",
+            "hash-table-clear!",
+        ],
+    ),
+];
+
+// Environment specifier of `eval`
+pub enum ScmEnvironment {
+    // Globals of the context, definitions change them
+    Interaction,
+    // Imported bindings, which can't be changed by the evaluated code
+    Bindings(Rc<HashMap<Symbol, ScmBinding>>),
+}
+
+// Imported binding. Variables of libraries are imported as their global
+// slots, so changes made by the library are seen by the importers.
+#[derive(Clone)]
+pub enum ScmBinding {
+    Value(ScmValue),
+    Global(Symbol),
+}
+
+pub type ScmBindings = Vec<(Symbol, ScmBinding)>;
+
+// Library registered by `define-library`. Its body runs on the first import.
+pub enum ScmLibrary {
+    Declared(Rc<ScmLibraryDecl>),
+    Loading, // Body is running, so importing the library again is a cycle
+    Loaded(ScmBindings),
+}
+
+pub struct ScmLibraryDecl {
+    exports: Vec<(Symbol, Symbol)>, // Internal and external names
+    imports: Vec<ScmValue>,
+    body: Vec<ScmValue>,
+    source: Option<PathBuf>, // File of the declaration, for `include`
+}
+
+// Library name like (scheme base) as a string: "scheme base"
+pub fn library_name(spec: &ScmValue) -> Result<String, ScmError> {
+    let parts = match spec {
+        ScmValue::DotPair(_) if scm_is_list(spec) => scm_list_to_vec(spec),
+        _ => return Err(invalid_import(spec)),
+    };
+    let mut res = Vec::new();
+    for part in parts.iter() {
+        match part {
+            ScmValue::Symbol(_) | ScmValue::Number(_) => res.push(scm_display(part).to_string()),
+            _ => return Err(invalid_import(spec)),
+        }
+    }
+    Ok(res.join(" "))
+}
+
+fn invalid_import(spec: &ScmValue) -> ScmError {
+    library_error("Invalid import set", spec)
+}
+
+fn library_error(message: &str, irritant: &ScmValue) -> ScmError {
+    ScmError::new(
+        ScmErrorKind::Error,
+        String::from(message),
+        vec![irritant.clone()],
+    )
+}
+
+fn standard_library(ctx: &ScmExecContext, name: &str) -> Option<ScmBindings> {
+    let binding =
+        |name: &str, val: &ScmValue| (Symbol::intern(name), ScmBinding::Value(val.clone()));
+    if name == "scheme base" {
+        let listed: HashSet<&str> = LIBRARIES
+            .iter()
+            .flat_map(|(_, names)| names.iter().copied())
+            .collect();
+        let mut res: ScmBindings = BUILTINS_LIST
+            .iter()
+            .filter(|(name, _)| !listed.contains(name) && ctx.allows_builtin(name))
+            .map(|(name, val)| binding(name, val))
+            .collect();
+        res.extend(
+            ctx.ports
+                .globals()
+                .iter()
+                .filter(|(name, _)| ctx.allows_builtin(name))
+                .map(|(name, val)| binding(name, val)),
+        );
+        return Some(res);
+    }
+    // Builtins the context doesn't allow are left out, so `environment`
+    // can't bypass a sandbox
+    let (_, names) = LIBRARIES.iter().find(|(library, _)| *library == name)?;
+    names
+        .iter()
+        .filter(|name| ctx.allows_builtin(name))
+        .map(|name| Some(binding(name, builtin_value(name)?)))
+        .collect()
+}
+
+// All bindings of the standard libraries, as in (scheme-report-environment 5)
+pub fn standard_bindings(ctx: &ScmExecContext) -> ScmBindings {
+    let mut res = standard_library(ctx, "scheme base").unwrap_or_default();
+    for (name, _) in LIBRARIES.iter() {
+        res.extend(standard_library(ctx, name).unwrap_or_default());
+    }
+    res
+}
+
+fn expect_identifiers(spec: &ScmValue, items: &[ScmValue]) -> Result<Vec<Symbol>, ScmError> {
+    items
+        .iter()
+        .map(|item| match item {
+            ScmValue::Symbol(sym) => Ok(*sym),
+            _ => Err(invalid_import(spec)),
+        })
+        .collect()
+}
+
+fn find_binding(bindings: &ScmBindings, name: Symbol) -> Result<usize, ScmError> {
+    match bindings.iter().position(|(sym, _)| *sym == name) {
+        Some(idx) => Ok(idx),
+        None => Err(ScmError::new(
+            ScmErrorKind::Error,
+            String::from("Identifier is not exported by the import set"),
+            vec![ScmValue::Symbol(name)],
+        )),
+    }
+}
+
+// Bindings of the import set: a library name, (only set id ...),
+// (except set id ...), (prefix set prefix) or (rename set (from to) ...)
+pub fn import_set(ctx: &mut ScmExecContext, spec: &ScmValue) -> Result<ScmBindings, ScmError> {
+    let items = match spec {
+        ScmValue::DotPair(_) if scm_is_list(spec) => scm_list_to_vec(spec),
+        _ => return Err(invalid_import(spec)),
+    };
+    let modifier = match (&items[0], items.get(1)) {
+        (ScmValue::Symbol(sym), Some(ScmValue::DotPair(_))) => sym.as_str(),
+        _ => "",
+    };
+
+    match modifier {
+        "only" => {
+            let bindings = import_set(ctx, &items[1])?;
+            expect_identifiers(spec, &items[2..])?
+                .into_iter()
+                .map(|name| Ok(bindings[find_binding(&bindings, name)?].clone()))
+                .collect()
+        }
+        "except" => {
+            let mut bindings = import_set(ctx, &items[1])?;
+            for name in expect_identifiers(spec, &items[2..])? {
+                bindings.remove(find_binding(&bindings, name)?);
+            }
+            Ok(bindings)
+        }
+        "prefix" => {
+            let [prefix] = expect_identifiers(spec, &items[2..])?[..] else {
+                return Err(invalid_import(spec));
+            };
+            let mut bindings = import_set(ctx, &items[1])?;
+            for (name, _) in bindings.iter_mut() {
+                let prefixed = format!("{}{}", prefix, name);
+                if Symbol::lookup(&prefixed).is_none() {
+                    ctx.reserve_alloc(symbol_size(&prefixed))?;
+                }
+                *name = Symbol::intern(&prefixed);
+            }
+            Ok(bindings)
+        }
+        "rename" => {
+            let mut bindings = import_set(ctx, &items[1])?;
+            for rename in items[2..].iter() {
+                let rename = match rename {
+                    ScmValue::DotPair(_) if scm_is_list(rename) => scm_list_to_vec(rename),
+                    _ => return Err(invalid_import(spec)),
+                };
+                let [from, to] = expect_identifiers(spec, &rename)?[..] else {
+                    return Err(invalid_import(spec));
+                };
+                let idx = find_binding(&bindings, from)?;
+                bindings[idx].0 = to;
+            }
+            Ok(bindings)
+        }
+        _ => library(ctx, spec),
+    }
+}
+
+// Exported bindings of the library, running its body if it's not loaded yet.
+// Unknown libraries are looked up in the library path as name/parts.sld.
+fn library(ctx: &mut ScmExecContext, spec: &ScmValue) -> Result<ScmBindings, ScmError> {
+    let name = library_name(spec)?;
+    if let Some(bindings) = standard_library(ctx, &name) {
+        return Ok(bindings);
+    }
+    if !ctx.libraries.contains_key(&name) {
+        load_library_file(ctx, &name)?;
+    }
+    let decl = match ctx.libraries.get(&name) {
+        Some(ScmLibrary::Declared(decl)) => decl.clone(),
+        Some(ScmLibrary::Loaded(bindings)) => return Ok(bindings.clone()),
+        Some(ScmLibrary::Loading) => return Err(library_error("Circular library import", spec)),
+        None => return Err(library_error("Unknown library", spec)),
+    };
+
+    ctx.libraries.insert(name.clone(), ScmLibrary::Loading);
+    let res = instantiate(ctx, &decl);
+    let library = match &res {
+        Ok(bindings) => ScmLibrary::Loaded(bindings.clone()),
+        Err(_) => ScmLibrary::Declared(decl),
+    };
+    ctx.libraries.insert(name, library);
+    res
+}
+
+fn load_library_file(ctx: &mut ScmExecContext, name: &str) -> Result<(), ScmError> {
+    let mut parts: Vec<&str> = name.split(' ').collect();
+    let file = format!("{}.sld", parts.pop().unwrap_or_default());
+    for dir in ctx.library_path.clone() {
+        let path = parts
+            .iter()
+            .fold(dir, |path, part| path.join(part))
+            .join(&file);
+        if path.is_file() {
+            load_file(ctx, &path)?;
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn instantiate(ctx: &mut ScmExecContext, decl: &ScmLibraryDecl) -> Result<ScmBindings, ScmError> {
+    let mut imports = HashMap::new();
+    for spec in decl.imports.iter() {
+        imports.extend(import_set(ctx, spec)?);
+    }
+    let imports = Rc::new(imports);
+    let (forms, definitions) = compile_library(
+        &decl.body,
+        imports.clone(),
+        decl.source.as_deref(),
+        &ctx.fs_access,
+    )?;
+
+    let prev = std::mem::replace(&mut ctx.loading, decl.source.clone());
+    let mut res = Ok(ScmValue::Nil);
+    for form in forms.iter() {
+        res = exec_callable(ctx, form, &[]);
+        if res.is_err() {
+            break;
+        }
+    }
+    ctx.loading = prev;
+    res?;
+
+    decl.exports
+        .iter()
+        .map(|(internal, external)| {
+            let binding = match definitions.get(internal) {
+                Some(global) if ctx.globals.get(*global).is_some() => {
+                    Some(ScmBinding::Global(*global))
+                }
+                Some(_) => None,
+                None => imports.get(internal).cloned(),
+            };
+            match binding {
+                Some(binding) => Ok((*external, binding)),
+                None => Err(library_error(
+                    "Exported identifier is not defined",
+                    &ScmValue::Symbol(*internal),
+                )),
+            }
+        })
+        .collect()
+}
+
+// (define-library name declaration ...), registers the library
+pub fn scm_define_library(ctx: &mut ScmExecContext, args: &[ScmValue]) -> ScmResult {
+    let items = scm_list_to_vec(&args[0]);
+    scm_ensure!(items.len() >= 2, "Define-library requires a library name");
+    let name = library_name(&items[1])?;
+    let mut decl = ScmLibraryDecl {
+        exports: Vec::new(),
+        imports: Vec::new(),
+        body: Vec::new(),
+        source: ctx.loading.clone(),
+    };
+
+    for declaration in items[2..].iter() {
+        let invalid = || library_error("Invalid library declaration", declaration);
+        let parts = match declaration {
+            ScmValue::DotPair(_) if scm_is_list(declaration) => scm_list_to_vec(declaration),
+            _ => return Err(invalid()),
+        };
+        let ScmValue::Symbol(kind) = parts[0] else {
+            return Err(invalid());
+        };
+        match kind.as_str() {
+            "export" => {
+                for spec in parts[1..].iter() {
+                    decl.exports.push(export_spec(spec).ok_or_else(invalid)?);
+                }
+            }
+            "import" => decl.imports.extend(parts[1..].iter().cloned()),
+            "begin" => decl.body.extend(parts[1..].iter().cloned()),
+            "include" => decl.body.push(declaration.clone()),
+            _ => return Err(invalid()),
+        }
+    }
+
+    ctx.libraries
+        .insert(name, ScmLibrary::Declared(Rc::new(decl)));
+    Ok(ScmValue::Nil)
+}
+
+// Identifier or (rename internal external)
+fn export_spec(spec: &ScmValue) -> Option<(Symbol, Symbol)> {
+    match spec {
+        ScmValue::Symbol(sym) => Some((*sym, *sym)),
+        ScmValue::DotPair(_) if scm_is_list(spec) => match &scm_list_to_vec(spec)[..] {
+            [ScmValue::Symbol(rename), ScmValue::Symbol(internal), ScmValue::Symbol(external)]
+                if rename.as_str() == "rename" =>
+            {
+                Some((*internal, *external))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// (import set ...) on the top level defines the imported bindings as globals
+pub fn scm_import(ctx: &mut ScmExecContext, args: &[ScmValue]) -> ScmResult {
+    let items = scm_list_to_vec(&args[0]);
+    for spec in items[1..].iter() {
+        for (name, binding) in import_set(ctx, spec)? {
+            match binding {
+                ScmBinding::Value(val) => ctx.globals.add_or_assign_var(name, val),
+                ScmBinding::Global(global) => {
+                    ctx.globals.alias(name, global);
+                }
+            }
+        }
+    }
+    Ok(ScmValue::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn libraries_export_builtins() {
+        for (library, names) in LIBRARIES.iter() {
+            for name in names.iter() {
+                assert!(builtin_value(name).is_some(), "{} in {}", name, library);
+            }
+        }
+    }
+
+    #[test]
+    fn libraries() {
+        let dir = std::env::temp_dir().join(format!("scm_libraries_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("util")).unwrap();
+        std::fs::write(
+            dir.join("util/strings.sld"),
+            "(define-library (util strings)
+               (export (rename helper shout))
+               (import (scheme base))
+               (begin (define (helper s) (string-append s \"!\"))))",
+        )
+        .unwrap();
+        let code = "
+            (define-library (counter)
+              (export next (rename count current))
+              (import (only (scheme base) +))
+              (begin
+                (define count 0)
+                (define (helper) (set! count (+ count 1)) count)
+                (define (next) (helper))))
+            (define-library (other)
+              (export helper)
+              (import (prefix (counter) counter:) (scheme base))
+              (begin (define (helper) (list 'other (counter:next)))))
+            (define (helper) 'main)
+            (import (counter) (except (other) helper) (rename (other) (helper other-helper)))
+            (import (prefix (util strings) s:))
+            (list (next)
+                  (other-helper)
+                  (helper)
+                  current
+                  (s:shout \"hi\")
+                  (guard (err (#t (error-object-message err))) (import (missing)))
+                  (guard (err (#t (error-object-irritants err))) (import (only (counter) count))))";
+        let mut ctx = ScmExecContext::new();
+        ctx.add_library_path(&dir);
+        let res = run_in(&mut ctx, code);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let expected = "'(1 (other 2) main 2 \"hi!\" \"Unknown library\" (count))";
+        assert!(scm_equal(&res, &run(expected)));
+    }
+}
@@ -0,0 +1,484 @@
+use crate::engine::scm_error::{ScmError, ScmResult};
+use core::fmt;
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+// Allocations and the deadline are checked once per this many instructions
+const CHECK_INTERVAL: u64 = 1024;
+
+// Builtins looping over data tick once per item (a list element, a char or
+// a byte of a string), the interrupt flag and the deadline are checked once
+// per this many ticks
+const POLL_INTERVAL: u64 = 4096;
+
+// Nested runs of the VM (builtins calling procedures) use the host stack, so
+// their number is limited in every mode, which fails as the depth limit
+const MAX_NESTED_RUNS: usize = 128;
+
+thread_local! {
+    // Approximate number of bytes allocated for Scheme values so far
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn track_alloc(bytes: usize) {
+    ALLOCATED.with(|allocated| allocated.set(allocated.get().wrapping_add(bytes)));
+}
+
+fn allocated() -> usize {
+    ALLOCATED.with(Cell::get)
+}
+
+// Limits of a single evaluation: a call of `exec_callable` from the host,
+// including everything it calls. None means no limit. `max_alloc` is a budget
+// for all bytes allocated during the evaluation, memory freed meanwhile is
+// not given back to it.
+#[derive(Debug, Clone, Default)]
+pub struct ScmLimits {
+    pub fuel: Option<u64>,         // Executed instructions
+    pub max_depth: Option<usize>,  // Frames of procedures being called
+    pub max_alloc: Option<usize>,  // Bytes allocated for pairs, strings, vectors, closures
+    pub timeout: Option<Duration>, // Wall-clock time
+}
+
+// Limit that aborted the evaluation. It isn't an exception, so Scheme code
+// can't handle it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScmLimit {
+    Fuel,
+    Depth,
+    Alloc,
+    Timeout,
+}
+
+// Interrupt requested by the handle
+const NO_INTERRUPT: u8 = 0;
+const SOFT_INTERRUPT: u8 = 1;
+const HARD_INTERRUPT: u8 = 2;
+
+// Stops the evaluation of the context from another thread. It's checked
+// together with the limits, so the evaluation stops after a few instructions.
+// Builtins looping over data check it as well. An interrupt requested while
+// nothing runs stops the next evaluation.
+#[derive(Debug, Clone, Default)]
+pub struct ScmInterruptHandle {
+    requested: Arc<AtomicU8>,
+}
+
+impl ScmInterruptHandle {
+    // Stops the evaluation, or calls the interrupt handler of the Scheme
+    // code if it has installed one
+    pub fn interrupt(&self) {
+        self.requested.fetch_max(SOFT_INTERRUPT, Ordering::Relaxed);
+    }
+
+    // Stops the evaluation without calling the interrupt handler, for code
+    // whose handler doesn't let it stop
+    pub fn interrupt_hard(&self) {
+        self.requested.store(HARD_INTERRUPT, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> u8 {
+        self.requested.swap(NO_INTERRUPT, Ordering::Relaxed)
+    }
+}
+
+// Resources used by the current evaluation
+#[derive(Default)]
+pub struct ScmUsage {
+    runs: usize,             // Nested runs of `exec_callable`
+    pub(crate) depth: usize, // Frames of the VM runs suspended by builtins
+    steps: u64,              // Instructions executed up to the last check
+    countdown: u64,          // Instructions left before the next check
+    interval: u64,           // Instructions between the last check and the next one
+    alloc_base: usize,
+    deadline: Option<Instant>,
+    ticks: Cell<u64>,
+    pub(crate) interrupt: ScmInterruptHandle,
+    forced: Cell<bool>, // Stopped by a hard interrupt
+}
+
+impl ScmUsage {
+    // Starts the evaluation if it's the outermost run. Fails if the runs are
+    // nested too deep for the host stack.
+    pub(crate) fn enter(&mut self, limits: &ScmLimits) -> Result<(), ScmError> {
+        if self.runs == 0 {
+            self.depth = 0;
+            self.steps = 0;
+            self.countdown = 0;
+            self.interval = 0;
+            self.alloc_base = allocated();
+            self.deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+            self.forced.set(false);
+        }
+        if self.runs >= MAX_NESTED_RUNS {
+            return Err(ScmError::Limit(ScmLimit::Depth));
+        }
+        self.runs += 1;
+        Ok(())
+    }
+
+    // Whether the current run was started by the host
+    pub(crate) fn is_outermost(&self) -> bool {
+        self.runs == 1
+    }
+
+    // Ends the run. An interrupt the outermost run didn't get to check is
+    // reported rather than left to stop the next evaluation.
+    pub(crate) fn leave(&mut self, res: ScmResult) -> ScmResult {
+        self.runs -= 1;
+        if self.runs == 0 && self.interrupt.take() != NO_INTERRUPT {
+            return Err(ScmError::Interrupted);
+        }
+        res
+    }
+
+    // Whether a hard interrupt has stopped the evaluation, which then
+    // doesn't call the interrupt handler
+    pub(crate) fn is_forced(&self) -> bool {
+        self.forced.get()
+    }
+
+    // Fails if an interrupt was requested. After a hard interrupt every
+    // check fails until the evaluation ends.
+    fn check_interrupt(&self) -> Result<(), ScmError> {
+        let requested = self.interrupt.take();
+        if requested == HARD_INTERRUPT {
+            self.forced.set(true);
+        }
+        match requested != NO_INTERRUPT || self.forced.get() {
+            true => Err(ScmError::Interrupted),
+            false => Ok(()),
+        }
+    }
+
+    // Called before every instruction, so the limits are checked only when
+    // the countdown runs out
+    #[inline]
+    pub(crate) fn step(&mut self, limits: &ScmLimits) -> Result<(), ScmError> {
+        match self.countdown.checked_sub(1) {
+            Some(countdown) => {
+                self.countdown = countdown;
+                Ok(())
+            }
+            None => self.check(limits),
+        }
+    }
+
+    #[cold]
+    fn check(&mut self, limits: &ScmLimits) -> Result<(), ScmError> {
+        self.steps += self.interval + 1;
+        self.interval = CHECK_INTERVAL;
+        if let Some(fuel) = limits.fuel {
+            self.interval = self.interval.min(fuel.saturating_sub(self.steps));
+        }
+        self.countdown = self.interval;
+        self.check_interrupt()?;
+        if limits.fuel.is_some_and(|fuel| self.steps > fuel) {
+            return Err(ScmError::Limit(ScmLimit::Fuel));
+        }
+        self.reserve(limits, 0)?;
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(ScmError::Limit(ScmLimit::Timeout));
+        }
+        Ok(())
+    }
+
+    // For builtins looping over data, which run no instructions. Checks the
+    // interrupt flag and the deadline, outside of evaluations nothing.
+    pub(crate) fn poll(&self) -> Result<(), ScmError> {
+        if self.runs == 0 {
+            return Ok(());
+        }
+        self.check_interrupt()?;
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(ScmError::Limit(ScmLimit::Timeout));
+        }
+        Ok(())
+    }
+
+    // Counts an item of data processed by a builtin, polls once per
+    // `POLL_INTERVAL` of them
+    #[inline]
+    pub(crate) fn tick(&self) -> Result<(), ScmError> {
+        self.tick_n(1)
+    }
+
+    // Same for `cnt` items processed at once
+    #[inline]
+    pub(crate) fn tick_n(&self, cnt: usize) -> Result<(), ScmError> {
+        let prev = self.ticks.get();
+        let ticks = prev.wrapping_add(cnt as u64);
+        self.ticks.set(ticks);
+        match ticks / POLL_INTERVAL == prev / POLL_INTERVAL {
+            true => Ok(()),
+            false => self.poll(),
+        }
+    }
+
+    pub(crate) fn check_depth(&self, limits: &ScmLimits, frames: usize) -> Result<(), ScmError> {
+        match limits.max_depth {
+            Some(max) if self.depth + frames > max => Err(ScmError::Limit(ScmLimit::Depth)),
+            _ => Ok(()),
+        }
+    }
+
+    // Checks the allocation budget before allocating `bytes` more
+    pub fn reserve(&self, limits: &ScmLimits, bytes: usize) -> Result<(), ScmError> {
+        let used = allocated().wrapping_sub(self.alloc_base);
+        match limits.max_alloc {
+            Some(max) if used.saturating_add(bytes) > max => Err(ScmError::Limit(ScmLimit::Alloc)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ScmLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScmLimit::Fuel => write!(f, "instruction limit exceeded"),
+            ScmLimit::Depth => write!(f, "call depth limit exceeded"),
+            ScmLimit::Alloc => write!(f, "allocation limit exceeded"),
+            ScmLimit::Timeout => write!(f, "time limit exceeded"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn limits() {
+        let mut interp = Interpreter::new();
+        let code = "
+            (define (spin) (spin))
+            (define (deep n) (+ 1 (deep n)))
+            (define (grow acc) (grow (cons 1 acc)))
+            (define (nest) (map (lambda (x) (nest)) '(1)))";
+        interp.eval_str(code).unwrap();
+        let aborted = |interp: &mut Interpreter, code: &str| match interp.eval_str(code) {
+            Err(ScmError::Limit(limit)) => Some(limit),
+            _ => None,
+        };
+
+        interp.context().limits.fuel = Some(10_000);
+        assert_eq!(aborted(&mut interp, "(spin)"), Some(ScmLimit::Fuel));
+        // Limits can't be caught by Scheme code
+        let code = "(guard (err (#t 'caught)) (spin))";
+        assert_eq!(aborted(&mut interp, code), Some(ScmLimit::Fuel));
+        interp.context().limits.fuel = None;
+
+        interp.context().limits.max_depth = Some(100);
+        assert_eq!(aborted(&mut interp, "(deep 1)"), Some(ScmLimit::Depth));
+        // Builtins calling procedures nest runs on the host stack, which are
+        // limited however big the depth limit is
+        interp.context().limits.max_depth = Some(1_000_000);
+        assert_eq!(aborted(&mut interp, "(nest)"), Some(ScmLimit::Depth));
+        interp.context().limits.max_depth = None;
+
+        interp.context().limits.max_alloc = Some(100_000);
+        assert_eq!(aborted(&mut interp, "(grow '())"), Some(ScmLimit::Alloc));
+        assert_eq!(
+            aborted(&mut interp, "(make-string 1000000)"),
+            Some(ScmLimit::Alloc)
+        );
+        assert_eq!(aborted(&mut interp, "(iota 100000)"), Some(ScmLimit::Alloc));
+        // Builtins check the budget before allocating, output of string ports
+        // and new hash table entries count too
+        let code = "
+            (let loop ((s \"a\") (n 0))
+              (if (< n 27) (loop (string-append s s) (+ n 1)) (string-length s)))";
+        assert_eq!(aborted(&mut interp, code), Some(ScmLimit::Alloc));
+        let code = "
+            (define (tree n) (if (= n 0) 'leaf (let ((t (tree (- n 1)))) (list t t))))
+            (write (tree 40) (open-output-string))";
+        assert_eq!(aborted(&mut interp, code), Some(ScmLimit::Alloc));
+        let code = "
+            (define h (make-hash-table))
+            (let loop ((i 0)) (hash-table-set! h i i) (loop (+ i 1)))";
+        assert_eq!(aborted(&mut interp, code), Some(ScmLimit::Alloc));
+        // Continuations copy the frames when captured and when resumed, which
+        // exhausts the budget long before the fuel
+        interp.context().limits.max_alloc = Some(1_000_000);
+        interp.context().limits.fuel = Some(50_000);
+        let code = "
+            (define (deep n thunk) (if (= n 0) (thunk) (car (list (deep (- n 1) thunk)))))
+            (deep 1000 (lambda () (let loop () (call/cc (lambda (k) k)) (loop))))";
+        assert_eq!(aborted(&mut interp, code), Some(ScmLimit::Alloc));
+        let code = "(let ((k (deep 1000 (lambda () (call/cc (lambda (k) k)))))) (k k))";
+        assert_eq!(aborted(&mut interp, code), Some(ScmLimit::Alloc));
+        interp.context().limits.fuel = None;
+        // Symbols are never freed, so creating them counts too
+        interp.context().limits.max_alloc = None;
+        interp
+            .eval_str("(define s (make-string 200000 #\\a))")
+            .unwrap();
+        interp.context().limits.max_alloc = Some(100_000);
+        assert_eq!(
+            aborted(&mut interp, "(string->symbol s)"),
+            Some(ScmLimit::Alloc)
+        );
+        let code = "(read (open-input-string (make-string 200000 #\\b)))";
+        assert_eq!(aborted(&mut interp, code), Some(ScmLimit::Alloc));
+        let code = "
+            (define prefix (string->symbol (make-string 1000 #\\p)))
+            (environment (list 'prefix '(scheme base) prefix))";
+        assert_eq!(aborted(&mut interp, code), Some(ScmLimit::Alloc));
+        interp.context().limits.max_alloc = None;
+        // Without a limit, sizes that can't be allocated raise an error
+        for code in [
+            "(make-string 9223372036854775807)",
+            "(iota 9223372036854775807)",
+        ] {
+            let code = format!("(guard (err (#t 'failed)) {})", code);
+            let res = interp.eval_str(&code).unwrap();
+            assert!(scm_equal(&res, &sym("failed")));
+        }
+
+        interp.context().limits.timeout = Some(std::time::Duration::from_millis(20));
+        assert_eq!(aborted(&mut interp, "(spin)"), Some(ScmLimit::Timeout));
+        // Builtins looping over data check the deadline themselves
+        let code = "(write (tree 40) (open-output-string))";
+        assert_eq!(aborted(&mut interp, code), Some(ScmLimit::Timeout));
+        interp.context().limits.timeout = None;
+        let code = "(define l (iota 1000000)) (define s (make-string 20000000 #\\a))";
+        interp.eval_str(code).unwrap();
+        interp.context().limits.timeout = Some(std::time::Duration::from_millis(1));
+        assert_eq!(aborted(&mut interp, "(reverse l)"), Some(ScmLimit::Timeout));
+        assert_eq!(
+            aborted(&mut interp, "(string-upcase s)"),
+            Some(ScmLimit::Timeout)
+        );
+
+        // Every evaluation gets the whole budget again
+        assert!(scm_equal(&interp.eval_str("(+ 1 2)").unwrap(), &int(3)));
+    }
+
+    #[test]
+    fn nested_builtin_calls() {
+        // Even without limits, builtins calling procedures nest only as deep
+        // as the stack of a test thread allows. `member` calls the compare
+        // procedure from a nested run.
+        let thread = std::thread::Builder::new().stack_size(2 << 20);
+        let res = thread.spawn(|| {
+            let mut interp = Interpreter::new();
+            let code = "
+                (define (nest n)
+                  (let ((res 0))
+                    (if (< 0 n) (member n '(1) (lambda (a b) (set! res (+ 1 (nest (- a 1)))))))
+                    res))
+                (nest 100)";
+            let res = interp.eval_str(code).unwrap();
+            let code = "
+                (define (f n) (member n '(1) (lambda (a b) (f (+ n 1)))))
+                (f 0)";
+            let err = interp.eval_str(code).unwrap_err();
+            scm_equal(&res, &int(100)) && matches!(err, ScmError::Limit(ScmLimit::Depth))
+        });
+        assert!(res.unwrap().join().unwrap());
+    }
+
+    #[test]
+    fn interrupts() {
+        fn send_sync<T: Send + Sync>(_: &T) {}
+        let mut interp = Interpreter::new();
+        let handle = interp.context().interrupt_handle();
+        send_sync(&handle);
+        let interrupt_with = |interrupt: fn(&ScmInterruptHandle)| {
+            let handle = handle.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                interrupt(&handle);
+            })
+        };
+        let interrupt_later = || interrupt_with(ScmInterruptHandle::interrupt);
+
+        // An interrupt requested while nothing runs isn't lost, it stops the
+        // next evaluation
+        handle.interrupt();
+        assert!(matches!(
+            interp.eval_str("(+ 1 2)"),
+            Err(ScmError::Interrupted)
+        ));
+        assert!(scm_equal(&interp.eval_str("(+ 1 2)").unwrap(), &int(3)));
+
+        interp.eval_str("(define (spin) (spin))").unwrap();
+        let thread = interrupt_later();
+        assert!(matches!(
+            interp.eval_str("(guard (err (#t 'caught)) (spin))"),
+            Err(ScmError::Interrupted)
+        ));
+        thread.join().unwrap();
+
+        // The handler can stop the loop by an exception or let it go on
+        let code = "
+            (set-interrupt-handler! (lambda () (raise 'stopped)))
+            (guard (err ((eq? err 'stopped) err)) (spin))";
+        let thread = interrupt_later();
+        let res = interp.eval_str(code).unwrap();
+        assert!(scm_equal(&res, &sym("stopped")));
+        thread.join().unwrap();
+
+        let code = "
+            (define done #f)
+            (set-interrupt-handler! (lambda () (set! done #t)))
+            (define (wait n) (if done n (wait (+ n 1))))
+            (< 0 (wait 0))";
+        let thread = interrupt_later();
+        assert!(scm_equal(
+            &interp.eval_str(code).unwrap(),
+            &ScmValue::Bool(true)
+        ));
+        thread.join().unwrap();
+
+        // A builtin stopped by the interrupt can't go on after the handler
+        let code = "
+            (define (tree n) (if (= n 0) 'leaf (let ((t (tree (- n 1)))) (list t t))))
+            (guard (err (#t 'failed)) (write (tree 40) (open-output-string)))";
+        let thread = interrupt_later();
+        let res = interp.eval_str(code).unwrap();
+        assert!(scm_equal(&res, &sym("failed")));
+        thread.join().unwrap();
+
+        // Builtins check the interrupt while they run. The timeout only stops
+        // the test if they don't.
+        interp.eval_str("(define l (iota 1000000))").unwrap();
+        interp.context().limits.timeout = Some(std::time::Duration::from_secs(10));
+        let code = "
+            (set-interrupt-handler! (lambda () #t))
+            (guard (err ((error-object? err) (error-object-message err)))
+              (let loop () (reverse l) (loop)))";
+        let thread = interrupt_later();
+        let res = interp.eval_str(code).unwrap();
+        assert!(scm_equal(
+            &res,
+            &ScmValue::new_string("Builtin interrupted")
+        ));
+        thread.join().unwrap();
+
+        // A hard interrupt stops code that doesn't let itself be interrupted,
+        // without calling the handler
+        let code = "
+            (define called #f)
+            (set-interrupt-handler! (lambda () (set! called #t)))
+            (define (forever) (guard (err (#t (forever))) (let loop () (reverse l) (loop))))
+            (forever)";
+        let thread = interrupt_with(ScmInterruptHandle::interrupt_hard);
+        assert!(matches!(interp.eval_str(code), Err(ScmError::Interrupted)));
+        thread.join().unwrap();
+        let res = interp.eval_str("called").unwrap();
+        assert!(scm_equal(&res, &ScmValue::Bool(false)));
+    }
+}
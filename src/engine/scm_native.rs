@@ -0,0 +1,182 @@
+use crate::engine::{
+    scm_core::{ScmCallable, ScmExecContext, ScmValue},
+    scm_error::*,
+    symbol::Symbol,
+};
+use core::fmt;
+use std::rc::Rc;
+
+pub type ScmNativeFn = dyn Fn(&mut ScmExecContext, &[ScmValue]) -> ScmResult;
+
+// Procedure registered by the host program. Unlike builtins it's a closure,
+// so it can keep state (handles, counters, configuration).
+pub struct ScmNative {
+    pub name: String,
+    pub arity: ScmArity,
+    pub doc: String,
+    pub func: Rc<ScmNativeFn>,
+}
+
+// Number of arguments accepted by a procedure, `max` is None for any number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScmArity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl ScmArity {
+    pub fn exact(cnt: usize) -> ScmArity {
+        ScmArity {
+            min: cnt,
+            max: Some(cnt),
+        }
+    }
+
+    pub fn at_least(cnt: usize) -> ScmArity {
+        ScmArity {
+            min: cnt,
+            max: None,
+        }
+    }
+
+    pub fn range(min: usize, max: usize) -> ScmArity {
+        ScmArity {
+            min,
+            max: Some(max),
+        }
+    }
+
+    pub fn accepts(&self, cnt: usize) -> bool {
+        cnt >= self.min && self.max.is_none_or(|max| cnt <= max)
+    }
+
+    pub fn check(&self, name: &str, cnt: usize) -> Result<(), ScmError> {
+        scm_ensure!(
+            self.accepts(cnt),
+            "{} requires {}",
+            name.to_uppercase(),
+            self
+        );
+        Ok(())
+    }
+}
+
+impl fmt::Display for ScmArity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |cnt| if cnt == 1 { "argument" } else { "arguments" };
+        match self.max {
+            Some(max) if max == self.min => write!(f, "exactly {} {}", max, plural(max)),
+            Some(max) => write!(f, "{} to {} arguments", self.min, max),
+            None => write!(f, "at least {} {}", self.min, plural(self.min)),
+        }
+    }
+}
+
+impl ScmNative {
+    pub fn call(&self, ctx: &mut ScmExecContext, args: &[ScmValue]) -> ScmResult {
+        self.arity.check(&self.name, args.len())?;
+        (self.func)(ctx, args)
+    }
+}
+
+impl fmt::Debug for ScmNative {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<procedure {}>", self.name)
+    }
+}
+
+impl ScmExecContext {
+    // Defines the procedure as a global, replacing the previous value.
+    // Boxed closures are accepted too, shared ones go to `define_native_rc`.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: ScmArity,
+        doc: &str,
+        func: impl Fn(&mut ScmExecContext, &[ScmValue]) -> ScmResult + 'static,
+    ) {
+        self.define_native_rc(name, arity, doc, Rc::new(func));
+    }
+
+    pub fn define_native_rc(
+        &mut self,
+        name: &str,
+        arity: ScmArity,
+        doc: &str,
+        func: Rc<ScmNativeFn>,
+    ) {
+        let native = ScmNative {
+            name: String::from(name),
+            arity,
+            doc: String::from(doc),
+            func,
+        };
+        let proc = ScmValue::Procedure(ScmCallable::Native(Rc::new(native)));
+        self.add_or_assign_var(name, proc);
+    }
+
+    // Native procedure bound to the global, if any
+    pub fn native(&self, name: &str) -> Option<Rc<ScmNative>> {
+        match self.globals.get(Symbol::lookup(name)?) {
+            Some(ScmValue::Procedure(ScmCallable::Native(native))) => Some(native.clone()),
+            _ => None,
+        }
+    }
+
+    // Unbinds the global if it holds a native procedure. Procedures already
+    // taken by Scheme code stay callable.
+    pub fn remove_native(&mut self, name: &str) -> Option<Rc<ScmNative>> {
+        let native = self.native(name)?;
+        self.globals.remove(Symbol::lookup(name)?);
+        Some(native)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn native_procedures() {
+        let mut ctx = ScmExecContext::new();
+        let counter = std::rc::Rc::new(std::cell::Cell::new(0));
+        let state = counter.clone();
+        ctx.define_native(
+            "next!",
+            ScmArity::range(0, 1),
+            "Increments the counter",
+            move |_, args| {
+                let step = args.first().and_then(scm_get_integer).unwrap_or(1);
+                state.set(state.get() + step);
+                Ok(int(state.get()))
+            },
+        );
+        let boxed: Box<ScmNativeFn> = Box::new(|_, args| Ok(args[0].clone()));
+        ctx.define_native(
+            "identity",
+            ScmArity::exact(1),
+            "Returns the argument",
+            boxed,
+        );
+        let code = "
+            (list (next!)
+                  (next! 10)
+                  (map identity '(1 2))
+                  (guard (err (#t (error-object-message err))) (next! 1 2)))";
+        let expected = "'(1 11 (1 2) \"NEXT! requires 0 to 1 arguments\")";
+        assert!(scm_equal(&run_in(&mut ctx, code), &run(expected)));
+        assert_eq!(counter.get(), 11);
+        assert_eq!(ctx.native("next!").unwrap().doc, "Increments the counter");
+
+        let shared: std::rc::Rc<ScmNativeFn> = std::rc::Rc::new(|_, _| Ok(int(0)));
+        ctx.define_native_rc("next!", ScmArity::exact(0), "Always zero", shared);
+        assert!(scm_equal(&run_in(&mut ctx, "(next!)"), &int(0)));
+        assert!(ctx.remove_native("identity").is_some());
+        assert!(ctx.remove_native("car").is_none());
+        let code = "(guard (err (#t (error-object-message err))) (identity 1))";
+        assert!(scm_equal(
+            &run_in(&mut ctx, code),
+            &run("\"Unbound variable\"")
+        ));
+    }
+}
@@ -0,0 +1,351 @@
+use crate::engine::{
+    scm_core::{ScmCallable, ScmExecContext, ScmParameter, ScmValue},
+    scm_error::*,
+    scm_limits::track_alloc,
+};
+use core::fmt;
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    rc::Rc,
+};
+
+// Textual port. Closing a port releases the file, but the port object stays
+// valid and remembers its direction.
+pub struct ScmPort {
+    pub name: String,
+    pub input: bool,
+    state: RefCell<ScmPortState>,
+}
+
+enum ScmPortState {
+    Input(ScmInput),
+    Output(Box<dyn Write>),
+    OutputString(String), // Accumulated output of the string port
+    Closed,
+}
+
+// Input is read by lines, characters are then taken from the current line
+struct ScmInput {
+    reader: Box<dyn BufRead>,
+    line: String,
+    pos: usize,
+    interactive: bool, // Reading may block, so only the buffered line is ready
+}
+
+// Parameters holding the current ports. They are rebound by `parameterize`
+// and by `with-output-to-file`.
+pub struct ScmPortParams {
+    pub input: Rc<ScmParameter>,
+    pub output: Rc<ScmParameter>,
+    pub error: Rc<ScmParameter>,
+}
+
+pub fn io_error(err: io::Error, path: &str) -> ScmError {
+    ScmError::new(ScmErrorKind::File, format!("{}: {}", path, err), Vec::new())
+}
+
+impl ScmInput {
+    // Makes sure there is an unread char in the line, false at the end of input
+    fn fill(&mut self, name: &str) -> Result<bool, ScmError> {
+        if self.pos < self.line.len() {
+            return Ok(true);
+        }
+        self.line.clear();
+        self.pos = 0;
+        let cnt = self
+            .reader
+            .read_line(&mut self.line)
+            .map_err(|err| io_error(err, name))?;
+        Ok(cnt > 0)
+    }
+
+    fn peek(&mut self, name: &str) -> Result<Option<char>, ScmError> {
+        Ok(match self.fill(name)? {
+            true => self.line[self.pos..].chars().next(),
+            false => None,
+        })
+    }
+
+    fn next(&mut self, name: &str) -> Result<Option<char>, ScmError> {
+        let c = self.peek(name)?;
+        if let Some(c) = c {
+            self.pos += c.len_utf8();
+        }
+        Ok(c)
+    }
+}
+
+impl ScmPort {
+    fn new(name: &str, state: ScmPortState) -> ScmPort {
+        ScmPort {
+            name: String::from(name),
+            input: matches!(state, ScmPortState::Input(_)),
+            state: RefCell::new(state),
+        }
+    }
+
+    fn new_input(name: &str, reader: Box<dyn BufRead>, interactive: bool) -> ScmPort {
+        ScmPort::new(
+            name,
+            ScmPortState::Input(ScmInput {
+                reader,
+                line: String::new(),
+                pos: 0,
+                interactive,
+            }),
+        )
+    }
+
+    pub fn stdin() -> ScmPort {
+        ScmPort::new_input("stdin", Box::new(BufReader::new(io::stdin())), true)
+    }
+
+    pub fn stdout() -> ScmPort {
+        ScmPort::new("stdout", ScmPortState::Output(Box::new(io::stdout())))
+    }
+
+    pub fn stderr() -> ScmPort {
+        ScmPort::new("stderr", ScmPortState::Output(Box::new(io::stderr())))
+    }
+
+    pub fn input_string(s: &str) -> ScmPort {
+        let port = ScmPort::new_input("string", Box::new(io::empty()), false);
+        if let ScmPortState::Input(input) = &mut *port.state.borrow_mut() {
+            input.line = String::from(s);
+        }
+        port
+    }
+
+    pub fn output_string() -> ScmPort {
+        ScmPort::new("string", ScmPortState::OutputString(String::new()))
+    }
+
+    pub fn open_input_file(path: &Path) -> Result<ScmPort, ScmError> {
+        let name = path.display().to_string();
+        let file = File::open(path).map_err(|err| io_error(err, &name))?;
+        Ok(ScmPort::new_input(
+            &name,
+            Box::new(BufReader::new(file)),
+            false,
+        ))
+    }
+
+    pub fn open_output_file(path: &Path) -> Result<ScmPort, ScmError> {
+        let name = path.display().to_string();
+        let file = File::create(path).map_err(|err| io_error(err, &name))?;
+        Ok(ScmPort::new(
+            &name,
+            ScmPortState::Output(Box::new(BufWriter::new(file))),
+        ))
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state.borrow(), ScmPortState::Closed)
+    }
+
+    // Output is flushed when the port is closed
+    pub fn close(&self) -> Result<(), ScmError> {
+        let state = self.state.replace(ScmPortState::Closed);
+        if let ScmPortState::Output(mut writer) = state {
+            writer.flush().map_err(|err| io_error(err, &self.name))?;
+        }
+        Ok(())
+    }
+
+    fn with_input<T>(
+        &self,
+        func: impl FnOnce(&mut ScmInput) -> Result<T, ScmError>,
+    ) -> Result<T, ScmError> {
+        match &mut *self.state.borrow_mut() {
+            ScmPortState::Input(input) => func(input),
+            ScmPortState::Output(_) | ScmPortState::OutputString(_) => {
+                Err(scm_error!("Port {} is not an input port", self.name))
+            }
+            ScmPortState::Closed => Err(scm_error!("Port {} is closed", self.name)),
+        }
+    }
+
+    pub fn read_char(&self) -> Result<Option<char>, ScmError> {
+        self.with_input(|input| input.next(&self.name))
+    }
+
+    pub fn peek_char(&self) -> Result<Option<char>, ScmError> {
+        self.with_input(|input| input.peek(&self.name))
+    }
+
+    // Line without the terminating newline, None at the end of input
+    pub fn read_line(&self) -> Result<Option<String>, ScmError> {
+        self.with_input(|input| {
+            if !input.fill(&self.name)? {
+                return Ok(None);
+            }
+            // String ports keep all their lines in the buffer
+            let rest = &input.line[input.pos..];
+            let end = rest.find('\n').map_or(rest.len(), |end| end + 1);
+            let line = String::from(rest[..end].trim_end_matches(['\n', '\r']));
+            input.pos += end;
+            Ok(Some(line))
+        })
+    }
+
+    // At most `cnt` chars, None if the input ended before any of them
+    pub fn read_string(&self, cnt: usize) -> Result<Option<String>, ScmError> {
+        self.with_input(|input| {
+            let mut res = String::new();
+            for _ in 0..cnt {
+                match input.next(&self.name)? {
+                    Some(c) => res.push(c),
+                    None if res.is_empty() && cnt > 0 => return Ok(None),
+                    None => break,
+                }
+            }
+            Ok(Some(res))
+        })
+    }
+
+    pub fn char_ready(&self) -> Result<bool, ScmError> {
+        self.with_input(|input| Ok(!input.interactive || input.pos < input.line.len()))
+    }
+
+    pub fn write_str(&self, s: &str) -> Result<(), ScmError> {
+        match &mut *self.state.borrow_mut() {
+            ScmPortState::Output(writer) => writer
+                .write_all(s.as_bytes())
+                .map_err(|err| io_error(err, &self.name)),
+            ScmPortState::OutputString(output) => {
+                output.push_str(s);
+                Ok(())
+            }
+            ScmPortState::Input(_) => Err(scm_error!("Port {} is not an output port", self.name)),
+            ScmPortState::Closed => Err(scm_error!("Port {} is closed", self.name)),
+        }
+    }
+
+    // Output of Scheme code. String ports keep it in memory, so it counts
+    // against the allocation budget of the evaluation.
+    pub fn write_tracked(&self, ctx: &ScmExecContext, s: &str) -> Result<(), ScmError> {
+        if matches!(*self.state.borrow(), ScmPortState::OutputString(_)) {
+            ctx.reserve_alloc(s.len())?;
+            track_alloc(s.len());
+        }
+        self.write_str(s)
+    }
+
+    // Output accumulated by the string port so far
+    pub fn output(&self) -> Result<String, ScmError> {
+        match &*self.state.borrow() {
+            ScmPortState::OutputString(output) => Ok(output.clone()),
+            ScmPortState::Closed => Err(scm_error!("Port {} is closed", self.name)),
+            _ => Err(scm_error!("Port {} is not a string output port", self.name)),
+        }
+    }
+
+    pub fn flush(&self) -> Result<(), ScmError> {
+        match &mut *self.state.borrow_mut() {
+            ScmPortState::Output(writer) => writer.flush().map_err(|err| io_error(err, &self.name)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ScmPortParams {
+    pub fn new() -> ScmPortParams {
+        let param = |port: ScmPort| {
+            Rc::new(ScmParameter {
+                value: ScmValue::Port(Rc::new(port)),
+                converter: None,
+            })
+        };
+        ScmPortParams {
+            input: param(ScmPort::stdin()),
+            output: param(ScmPort::stdout()),
+            error: param(ScmPort::stderr()),
+        }
+    }
+
+    // Named parameter procedures, defined as globals of the context
+    pub fn globals(&self) -> [(&'static str, ScmValue); 3] {
+        let proc =
+            |param: &Rc<ScmParameter>| ScmValue::Procedure(ScmCallable::Parameter(param.clone()));
+        [
+            ("current-input-port", proc(&self.input)),
+            ("current-output-port", proc(&self.output)),
+            ("current-error-port", proc(&self.error)),
+        ]
+    }
+}
+
+impl Default for ScmPortParams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ScmPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.input {
+            true => write!(f, "#<input-port {}>", self.name),
+            false => write!(f, "#<output-port {}>", self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn ports() {
+        let path = std::env::temp_dir().join(format!("scm_ports_{}.txt", std::process::id()));
+        let code = format!(
+            "
+            (define path {:?})
+            (with-output-to-file path
+              (lambda ()
+                (display \"héllo\")
+                (newline)
+                (write (list 1 \"a\\\"b\" #\\space 'sym))))
+            (define port (open-input-file path))
+            (define first (list (peek-char port) (read-char port) (read-line port)))
+            (define rest (read-string 100 port))
+            (close-port port)
+            (call-with-output-file path (lambda (port) (write-string \"abcdef\" port 1 3)))
+            (define in (open-input-file path))
+            (list first
+                  rest
+                  (read-line in)
+                  (eof-object? (read-char in))
+                  (guard (err ((file-error? err) 'missing)) (open-input-file \"/nonexistent/file\"))
+                  (guard (err (#t 'closed)) (read-char port)))",
+            path.display().to_string()
+        );
+        let res = run(&code);
+        std::fs::remove_file(&path).unwrap();
+        let expected = "'((#\\h #\\h \"éllo\") \"(1 \\\"a\\\\\\\"b\\\" #\\\\space sym)\" \"bc\" #t missing closed)";
+        assert!(scm_equal(&res, &run(expected)));
+    }
+
+    #[test]
+    fn string_ports() {
+        let code = "
+            (define (show . items)
+              (with-output-to-string (lambda () (for-each display items))))
+            (define in (open-input-string \"ab\\ncd\"))
+            (define out (open-output-string))
+            (write 'x out)
+            (write-char #\\λ out)
+            (list (show \"n=\" 42 #\\! '(1 \"s\"))
+                  (call-with-output-string (lambda (port) (write \"q\" port)))
+                  (get-output-string out)
+                  (read-line in)
+                  (read-char in)
+                  (read-string 5 in)
+                  (eof-object? (peek-char in))
+                  (parameterize ((current-output-port out)) (display 1) (get-output-string out)))";
+        let expected = "'(\"n=42!(1 s)\" \"\\\"q\\\"\" \"xλ\" \"ab\" #\\c \"d\" #t \"xλ1\")";
+        assert!(scm_equal(&run(code), &run(expected)));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{
+    engine::{
+        scm_builtins::{BUILTINS_LIST, PRELUDE_HELPERS},
+        scm_core::*,
+        scm_error::*,
+        scm_library::ScmBinding,
+        scm_sandbox::ScmFsAccess,
+        symbol::Symbol,
+    },
+    frontend::{compile_datum, Parser},
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+// Builtins which call procedures are written in Scheme, so they run in the
+// VM of the caller and continuations captured by the procedures they call
+// can be resumed after they return. The code sees only the builtins and the
+// `%` helpers, so redefining a global doesn't change it.
+const PRELUDE: &[(&str, &str)] = &[
+    (
+        "map",
+        "(lambda (proc first . rest)
+           (%expect-lists \"MAP\" proc (cons first rest))
+           (if (null? rest)
+               (let loop ((l first) (res '()))
+                 (if (pair? l)
+                     (loop (cdr l) (cons (proc (car l)) res))
+                     (reverse res)))
+               (let loop ((lists (cons first rest)) (res '()))
+                 (let ((items (%heads lists)))
+                   (if items
+                       (loop (%tails lists) (cons (apply proc items) res))
+                       (reverse res))))))",
+    ),
+    (
+        "for-each",
+        "(lambda (proc first . rest)
+           (%expect-lists \"FOR-EACH\" proc (cons first rest))
+           (if (null? rest)
+               (let loop ((l first))
+                 (if (pair? l)
+                     (begin (proc (car l)) (loop (cdr l)))
+                     '()))
+               (let loop ((lists (cons first rest)))
+                 (let ((items (%heads lists)))
+                   (if items
+                       (begin (apply proc items) (loop (%tails lists)))
+                       '())))))",
+    ),
+    (
+        "filter",
+        "(lambda (pred l)
+           (%expect-lists \"FILTER\" pred (list l))
+           (let loop ((l l) (res '()))
+             (if (pair? l)
+                 (loop (cdr l) (if (pred (car l)) (cons (car l) res) res))
+                 (reverse res))))",
+    ),
+    (
+        "remove",
+        "(lambda (pred l)
+           (%expect-lists \"REMOVE\" pred (list l))
+           (let loop ((l l) (res '()))
+             (if (pair? l)
+                 (loop (cdr l) (if (pred (car l)) res (cons (car l) res)))
+                 (reverse res))))",
+    ),
+    (
+        "fold-left",
+        "(lambda (proc init first . rest)
+           (%expect-lists \"FOLD-LEFT\" proc (cons first rest))
+           (let loop ((lists (cons first rest)) (acc init))
+             (let ((items (%heads lists)))
+               (if items
+                   (loop (%tails lists) (apply proc acc items))
+                   acc))))",
+    ),
+    // Items with the same index are collected from the last ones
+    (
+        "fold-right",
+        "(lambda (proc init first . rest)
+           (%expect-lists \"FOLD-RIGHT\" proc (cons first rest))
+           (let collect ((lists (cons first rest)) (rows '()))
+             (let ((items (%heads lists)))
+               (if items
+                   (collect (%tails lists) (cons items rows))
+                   (let loop ((rows rows) (acc init))
+                     (if (pair? rows)
+                         (loop (cdr rows) (apply proc (append (car rows) (list acc))))
+                         acc))))))",
+    ),
+    (
+        "reduce",
+        "(lambda (proc ridentity l)
+           (%expect-lists \"REDUCE\" proc (list l))
+           (if (pair? l)
+               (let loop ((l (cdr l)) (acc (car l)))
+                 (if (pair? l)
+                     (loop (cdr l) (proc (car l) acc))
+                     acc))
+               ridentity))",
+    ),
+    // Bottom-up merge sort: adjacent runs are merged until one is left. The
+    // right item goes first only if it is strictly less, so equal items keep
+    // their order.
+    (
+        "list-sort",
+        "(lambda (less l)
+           (define (merge a b res)
+             (if (pair? a)
+                 (if (pair? b)
+                     (if (less (car b) (car a))
+                         (merge a (cdr b) (cons (car b) res))
+                         (merge (cdr a) b (cons (car a) res)))
+                     (append (reverse res) a))
+                 (append (reverse res) b)))
+           (define (merge-pairs runs res)
+             (if (pair? runs)
+                 (if (pair? (cdr runs))
+                     (merge-pairs (cddr runs) (cons (merge (car runs) (cadr runs) '()) res))
+                     (reverse (cons (car runs) res)))
+                 (reverse res)))
+           (define (sort runs)
+             (if (pair? (cdr runs))
+                 (sort (merge-pairs runs '()))
+                 (car runs)))
+           (%expect-lists \"LIST-SORT\" less (list l))
+           (if (pair? l) (sort (map list l)) '()))",
+    ),
+    (
+        "eval",
+        "(lambda (expr . env)
+           ((apply %compile expr env)))",
+    ),
+    (
+        "load",
+        "(lambda (path)
+           (let ((forms (%load-forms path)) (prev #f))
+             (dynamic-wind
+               (lambda () (set! prev (%set-loading! (car forms))))
+               (lambda ()
+                 (let loop ((thunks (cdr forms)) (res '()))
+                   (if (pair? thunks)
+                       (loop (cdr thunks) ((car thunks)))
+                       res)))
+               (lambda () (%set-loading! prev)))))",
+    ),
+];
+
+thread_local! {
+    // Procedures compiled so far, the code doesn't depend on the context
+    static COMPILED: RefCell<HashMap<&'static str, ScmCallable>> = RefCell::new(HashMap::new());
+}
+
+// Procedure of the prelude with the name, compiled on the first call
+pub fn prelude_procedure(name: &'static str) -> Result<ScmCallable, ScmError> {
+    if let Some(proc) = COMPILED.with(|compiled| compiled.borrow().get(name).cloned()) {
+        return Ok(proc);
+    }
+    let proc = compile_prelude(name)?;
+    COMPILED.with(|compiled| compiled.borrow_mut().insert(name, proc.clone()));
+    Ok(proc)
+}
+
+fn compile_prelude(name: &str) -> Result<ScmCallable, ScmError> {
+    let Some((_, code)) = PRELUDE.iter().find(|(proc, _)| *proc == name) else {
+        return Err(scm_error!("Unknown prelude procedure {}", name));
+    };
+    let bindings: HashMap<Symbol, ScmBinding> = BUILTINS_LIST
+        .iter()
+        .chain(PRELUDE_HELPERS.iter())
+        .map(|(name, val)| (Symbol::intern(name), ScmBinding::Value(val.clone())))
+        .collect();
+    let Some(datum) = Parser::new(code).read()? else {
+        return Err(scm_error!("Prelude procedure {} is empty", name));
+    };
+    // The lambda is compiled into a thunk creating the closure. It has no
+    // free variables, so the closure is made without running the thunk.
+    let thunk = compile_datum(&datum, Some(Rc::new(bindings)), &ScmFsAccess::deny_all())?;
+    match thunk {
+        ScmCallable::CustomProc(thunk) => Ok(ScmCallable::CustomProc(Rc::new(ScmProcedure {
+            template: thunk.template.templates[0].clone(),
+            env: None,
+        }))),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn continuations() {
+        // Generator suspended inside for-each, which is resumed after
+        // for-each has been left
+        let code = "
+            (define (make-generator items)
+              (define return #f)
+              (define (resume)
+                (for-each
+                  (lambda (item)
+                    (call/cc
+                      (lambda (k)
+                        (set! resume (lambda () (k #f)))
+                        (return item))))
+                  items)
+                (return 'done))
+              (lambda ()
+                (call/cc
+                  (lambda (r)
+                    (set! return r)
+                    (resume)))))
+            (define next (make-generator '(1 2 3)))
+            (list (next) (next) (next) (next))";
+        assert_eq!(scm_write(&run(code)).to_string(), "(1 2 3 done)");
+
+        // Re-entering map after it has returned builds a new result
+        let code = "
+            (let ()
+              (define k #f)
+              (define n 0)
+              (define res
+                (map (lambda (x) (call/cc (lambda (c) (if (= x 2) (set! k c)) x)))
+                     '(1 2 3)))
+              (set! n (+ n 1))
+              (if (< n 2) (k 10) (list res n)))";
+        assert_eq!(scm_write(&run(code)).to_string(), "((1 10 3) 2)");
+
+        // The evaluated code sees the globals
+        let code = "
+            (define k #f)
+            (let ((res (eval '(+ 1 (call/cc (lambda (c) (set! k c) 1))))))
+              (if (< res 5) (k (+ res 1)) res))";
+        assert!(scm_equal(&run(code), &int(6)));
+    }
+
+    #[test]
+    fn redefined_globals() {
+        // The prelude doesn't see globals of the context
+        let code = "
+            (define (reverse l) 'redefined)
+            (define (car l) 'redefined)
+            (list (map (lambda (x) (+ x x)) '(1 2 3))
+                  (list-sort < '(3 1 2))
+                  (fold-right list '() '(1 2) '(3 4)))";
+        assert_eq!(
+            scm_write(&run(code)).to_string(),
+            "((2 4 6) (1 2 3) (1 3 (2 4 ())))"
+        );
+    }
+}
@@ -0,0 +1,378 @@
+use crate::engine::{
+    scm_core::{ScmCallable, ScmValue},
+    scm_error::*,
+    symbol::Symbol,
+    typed_num::TypedNum,
+};
+use core::fmt;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+// External representation of the value, as printed by `display` (strings and
+// chars as they are) or `write` (readable back by the parser). Pairs, vectors
+// and records the value reaches again through themselves get datum labels:
+// #0=(1 . #0#).
+pub struct ScmPrinter<'a> {
+    val: &'a ScmValue,
+    write: bool,
+    labels: Option<ScmLabels>,
+}
+
+pub fn scm_display(val: &ScmValue) -> ScmPrinter<'_> {
+    ScmPrinter {
+        val,
+        write: false,
+        labels: None,
+    }
+}
+
+pub fn scm_write(val: &ScmValue) -> ScmPrinter<'_> {
+    ScmPrinter {
+        val,
+        write: true,
+        labels: None,
+    }
+}
+
+impl ScmPrinter<'_> {
+    // Printer with the datum labels found up front, calling `tick` for each
+    // node of the value, so the search can be stopped by the limits
+    pub(crate) fn with_labels(
+        self,
+        tick: impl FnMut() -> Result<(), ScmError>,
+    ) -> Result<Self, ScmError> {
+        let labels = ScmLabels::find(self.val, tick)?;
+        Ok(Self {
+            labels: Some(labels),
+            ..self
+        })
+    }
+}
+
+// Parts of the value left to print, kept on the heap, so deeply nested data
+// doesn't use the Rust stack
+enum Task {
+    Value(ScmValue),
+    Text(&'static str),
+    Tail(ScmValue), // Rest of a list after one of its items
+    Field(Symbol),  // Name of the record field printed next
+}
+
+// Nodes on cycles and the labels of those printed already
+#[derive(Clone, Default)]
+struct ScmLabels {
+    labels: HashMap<usize, Option<usize>>,
+    next: usize,
+}
+
+fn node_id(val: &ScmValue) -> Option<usize> {
+    match val {
+        ScmValue::DotPair(pair) => Some(Rc::as_ptr(pair) as *const u8 as usize),
+        ScmValue::Vector(vec) => Some(Rc::as_ptr(vec) as *const u8 as usize),
+        ScmValue::Record(record) => Some(Rc::as_ptr(record) as *const u8 as usize),
+        _ => None,
+    }
+}
+
+fn node_items(val: &ScmValue) -> Vec<ScmValue> {
+    match val {
+        ScmValue::DotPair(pair) => {
+            let pair = pair.borrow();
+            vec![pair.car.clone(), pair.cdr.clone()]
+        }
+        ScmValue::Vector(vec) => vec.borrow().to_vec(),
+        ScmValue::Record(record) => record.fields.borrow().clone(),
+        _ => Vec::new(),
+    }
+}
+
+impl ScmLabels {
+    // Depth-first search keeping the path on the heap, so that long lists
+    // don't use the Rust stack. A node met again while it is on the path is
+    // on a cycle; nodes shared without a cycle are printed as they are.
+    fn find(
+        val: &ScmValue,
+        mut tick: impl FnMut() -> Result<(), ScmError>,
+    ) -> Result<ScmLabels, ScmError> {
+        let mut on_path = HashMap::new();
+        let mut cyclic = HashSet::new();
+        let mut path = Vec::new();
+        let mut next = Some(val.clone());
+        loop {
+            if let Some(id) = next.as_ref().and_then(node_id) {
+                match on_path.get(&id) {
+                    Some(true) => {
+                        cyclic.insert(id);
+                    }
+                    Some(false) => (),
+                    None => {
+                        on_path.insert(id, true);
+                        let items = node_items(next.as_ref().unwrap());
+                        path.push((id, items.into_iter()));
+                    }
+                }
+            }
+            let Some((id, items)) = path.last_mut() else {
+                break;
+            };
+            tick()?;
+            next = items.next();
+            if next.is_none() {
+                on_path.insert(*id, false);
+                path.pop();
+            }
+        }
+        Ok(ScmLabels {
+            labels: cyclic.into_iter().map(|id| (id, None)).collect(),
+            next: 0,
+        })
+    }
+}
+
+pub fn format_number(num: TypedNum, radix: u32) -> Result<String, ScmError> {
+    match num {
+        TypedNum::Integer(val) => {
+            let mut digits = Vec::new();
+            let mut rest = val.unsigned_abs();
+            loop {
+                digits.push(char::from_digit((rest % radix as u64) as u32, radix).unwrap());
+                rest /= radix as u64;
+                if rest == 0 {
+                    break;
+                }
+            }
+            if val < 0 {
+                digits.push('-');
+            }
+            Ok(digits.iter().rev().collect())
+        }
+        TypedNum::Float(val) => {
+            scm_ensure!(
+                radix == 10,
+                "Inexact numbers can be formatted only in radix 10"
+            );
+            Ok(match val {
+                f64::INFINITY => String::from("+inf.0"),
+                f64::NEG_INFINITY => String::from("-inf.0"),
+                _ if val.is_nan() => String::from("+nan.0"),
+                _ => format!("{:?}", val),
+            })
+        }
+    }
+}
+
+// Text between the delimiters, with escapes the reader understands
+fn write_quoted(f: &mut fmt::Formatter<'_>, s: &str, delimiter: char) -> fmt::Result {
+    write!(f, "{}", delimiter)?;
+    for c in s.chars() {
+        match c {
+            c if c == delimiter => write!(f, "\\{}", c)?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            c if c.is_control() => write!(f, "\\x{:x};", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "{}", delimiter)
+}
+
+// Symbols the reader wouldn't read back as written, like ones with spaces
+// or ones that look like numbers, are written as |hello world|
+fn needs_bars(name: &str) -> bool {
+    name.is_empty()
+        || name == "."
+        || name.starts_with('#')
+        || TypedNum::parse(name, 10).is_some()
+        || name
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "()\"';|[]{}\\".contains(c))
+}
+
+fn char_name(c: char) -> Option<&'static str> {
+    Some(match c {
+        ' ' => "space",
+        '\n' => "newline",
+        '\t' => "tab",
+        '\r' => "return",
+        '\0' => "null",
+        '\u{7}' => "alarm",
+        '\u{8}' => "backspace",
+        '\u{1b}' => "escape",
+        '\u{7f}' => "delete",
+        _ => return None,
+    })
+}
+
+impl ScmPrinter<'_> {
+    // Values without parts to print
+    fn fmt_atom(&self, val: &ScmValue, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match val {
+            ScmValue::Number(num) => {
+                let num = format_number(*num, 10).map_err(|_| fmt::Error)?;
+                write!(f, "{}", num)
+            }
+            ScmValue::Bool(true) => write!(f, "#t"),
+            ScmValue::Bool(false) => write!(f, "#f"),
+            ScmValue::Char(c) if self.write => match char_name(*c) {
+                Some(name) => write!(f, "#\\{}", name),
+                None if c.is_control() => write!(f, "#\\x{:x}", *c as u32),
+                None => write!(f, "#\\{}", c),
+            },
+            ScmValue::Char(c) => write!(f, "{}", c),
+            ScmValue::String(s) if self.write => write_quoted(f, &s.borrow(), '"'),
+            ScmValue::String(s) => write!(f, "{}", s.borrow()),
+            ScmValue::Symbol(sym) if self.write && needs_bars(sym.as_str()) => {
+                write_quoted(f, sym.as_str(), '|')
+            }
+            ScmValue::Symbol(sym) => write!(f, "{}", sym),
+            ScmValue::Nil => write!(f, "()"),
+            ScmValue::Procedure(ScmCallable::CustomProc(proc)) => match proc.template.name {
+                Some(name) => write!(f, "#<procedure {}>", name),
+                None => write!(f, "#<procedure>"),
+            },
+            ScmValue::Procedure(ScmCallable::Native(native)) => write!(f, "{:?}", native),
+            ScmValue::Procedure(_) => write!(f, "#<procedure>"),
+            ScmValue::Error(err) => write!(f, "#<error {}>", err),
+            _ => write!(f, "{:?}", val),
+        }
+    }
+}
+
+impl fmt::Display for ScmPrinter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut labels = match &self.labels {
+            Some(labels) => labels.clone(),
+            None => ScmLabels::find(self.val, || Ok(())).map_err(|_| fmt::Error)?,
+        };
+        let is_labeled = |labels: &ScmLabels, val: &ScmValue| {
+            node_id(val).is_some_and(|id| labels.labels.contains_key(&id))
+        };
+        let mut tasks = vec![Task::Value(self.val.clone())];
+        while let Some(task) = tasks.pop() {
+            let val = match task {
+                Task::Value(val) => val,
+                Task::Text(text) => {
+                    write!(f, "{}", text)?;
+                    continue;
+                }
+                Task::Field(name) => {
+                    write!(f, " {}: ", name)?;
+                    continue;
+                }
+                Task::Tail(rest) => match rest {
+                    ScmValue::Nil => {
+                        write!(f, ")")?;
+                        continue;
+                    }
+                    // The rest of the list is printed by its label
+                    ScmValue::DotPair(ref pair) if !is_labeled(&labels, &rest) => {
+                        let pair = pair.borrow();
+                        write!(f, " ")?;
+                        tasks.push(Task::Tail(pair.cdr.clone()));
+                        tasks.push(Task::Value(pair.car.clone()));
+                        continue;
+                    }
+                    rest => {
+                        write!(f, " . ")?;
+                        tasks.push(Task::Text(")"));
+                        rest
+                    }
+                },
+            };
+
+            if let Some(id) = node_id(&val) {
+                let next = labels.next;
+                match labels.labels.get_mut(&id) {
+                    Some(Some(label)) => {
+                        write!(f, "#{}#", label)?;
+                        continue;
+                    }
+                    Some(label) => {
+                        *label = Some(next);
+                        labels.next += 1;
+                        write!(f, "#{}=", next)?;
+                    }
+                    None => (),
+                }
+            }
+
+            match &val {
+                ScmValue::DotPair(pair) => {
+                    let pair = pair.borrow();
+                    write!(f, "(")?;
+                    tasks.push(Task::Tail(pair.cdr.clone()));
+                    tasks.push(Task::Value(pair.car.clone()));
+                }
+                ScmValue::Vector(vec) => {
+                    write!(f, "#(")?;
+                    tasks.push(Task::Text(")"));
+                    for (i, item) in vec.borrow().iter().enumerate().rev() {
+                        tasks.push(Task::Value(item.clone()));
+                        if i > 0 {
+                            tasks.push(Task::Text(" "));
+                        }
+                    }
+                }
+                ScmValue::Record(record) => {
+                    write!(f, "#<{}", record.rtype.display_name())?;
+                    tasks.push(Task::Text(">"));
+                    let fields = record.fields.borrow();
+                    for (name, val) in record.rtype.fields.iter().zip(fields.iter()).rev() {
+                        tasks.push(Task::Value(val.clone()));
+                        tasks.push(Task::Field(*name));
+                    }
+                }
+                _ => self.fmt_atom(&val, f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn circular_data() {
+        let code = "
+            (define l (list 1 2 3))
+            (set-cdr! (cddr l) l)
+            (define v (vector 1 (list 2)))
+            (vector-set! v 0 v)
+            (set-car! (vector-ref v 1) v)
+            (define x (list 1))
+            (list l v (list x x))";
+        let val = run(code);
+        let expected = "(#0=(1 2 3 . #0#) #1=#(#1# (#1#)) ((1) (1)))";
+        assert_eq!(scm_write(&val).to_string(), expected);
+        assert_eq!(scm_display(&val).to_string(), expected);
+        assert_eq!(
+            scm_write(&run("(define l (list \"a\")) (set-cdr! l l) l")).to_string(),
+            "#0=(\"a\" . #0#)"
+        );
+    }
+
+    #[test]
+    fn symbols() {
+        // Symbols which need escapes are written between bars and read back
+        let code = "
+            (list (string->symbol \"hello world\")
+                  (string->symbol \"a|b\\\\c\")
+                  (string->symbol \"12\")
+                  (string->symbol \"\")
+                  'plain)";
+        let val = run(code);
+        let expected = "(|hello world| |a\\|b\\\\c| |12| || plain)";
+        assert_eq!(scm_write(&val).to_string(), expected);
+        assert_eq!(
+            scm_display(&val).to_string(),
+            "(hello world a|b\\c 12  plain)"
+        );
+        assert!(scm_equal(&run(&format!("'{}", expected)), &val));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::engine::{
+    scm_core::{drop_later, ScmCallable, ScmExecContext, ScmValue},
+    scm_error::*,
+    scm_print::scm_write,
+    scm_utils::*,
+    symbol::Symbol,
+    typed_num::TypedNum,
+};
+use core::fmt;
+use std::{cell::RefCell, rc::Rc};
+
+// Type created every time a `define-record-type` definition is evaluated
+pub struct ScmRecordType {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+pub struct ScmRecord {
+    pub rtype: Rc<ScmRecordType>,
+    pub fields: RefCell<Vec<ScmValue>>,
+}
+
+impl Drop for ScmRecord {
+    fn drop(&mut self) {
+        self.fields.get_mut().drain(..).for_each(drop_later);
+    }
+}
+
+// Procedures defined by `define-record-type`
+pub enum ScmRecordProc {
+    Constructor {
+        rtype: Rc<ScmRecordType>,
+        fields: Vec<usize>, // Fields initialized by the arguments
+    },
+    Predicate(Rc<ScmRecordType>),
+    Accessor {
+        rtype: Rc<ScmRecordType>,
+        name: Symbol,
+        field: usize,
+    },
+    Modifier {
+        rtype: Rc<ScmRecordType>,
+        name: Symbol,
+        field: usize,
+    },
+}
+
+impl ScmRecordType {
+    // Name without the conventional angle brackets: <point> is point
+    pub fn display_name(&self) -> &'static str {
+        let name = self.name.as_str();
+        name.strip_prefix('<')
+            .and_then(|name| name.strip_suffix('>'))
+            .unwrap_or(name)
+    }
+
+    pub fn field_index(&self, field: Symbol) -> Option<usize> {
+        self.fields.iter().position(|name| *name == field)
+    }
+}
+
+fn expect_record(
+    proc_name: Symbol,
+    rtype: &Rc<ScmRecordType>,
+    val: &ScmValue,
+) -> Result<Rc<ScmRecord>, ScmError> {
+    match val {
+        ScmValue::Record(record) if Rc::ptr_eq(&record.rtype, rtype) => Ok(record.clone()),
+        _ => Err(ScmError::new(
+            ScmErrorKind::Error,
+            format!(
+                "{} requires argument of record type {}",
+                proc_name,
+                rtype.display_name()
+            ),
+            vec![val.clone()],
+        )),
+    }
+}
+
+impl ScmRecordProc {
+    pub fn call(&self, args: &[ScmValue]) -> ScmResult {
+        match self {
+            ScmRecordProc::Constructor { rtype, fields } => {
+                scm_ensure!(
+                    args.len() == fields.len(),
+                    "Constructor of {} requires exactly {} arguments",
+                    rtype.display_name(),
+                    fields.len()
+                );
+                let mut vals = vec![ScmValue::Nil; rtype.fields.len()];
+                for (field, arg) in fields.iter().zip(args.iter()) {
+                    vals[*field] = arg.clone();
+                }
+                Ok(ScmValue::Record(Rc::new(ScmRecord {
+                    rtype: rtype.clone(),
+                    fields: RefCell::new(vals),
+                })))
+            }
+
+            ScmRecordProc::Predicate(rtype) => {
+                scm_ensure!(
+                    args.len() == 1,
+                    "Predicate of {} requires exactly 1 argument",
+                    rtype.display_name()
+                );
+                Ok(ScmValue::Bool(matches!(
+                    &args[0],
+                    ScmValue::Record(record) if Rc::ptr_eq(&record.rtype, rtype)
+                )))
+            }
+
+            ScmRecordProc::Accessor { rtype, name, field } => {
+                scm_ensure!(args.len() == 1, "{} requires exactly 1 argument", name);
+                let record = expect_record(*name, rtype, &args[0])?;
+                let val = record.fields.borrow()[*field].clone();
+                Ok(val)
+            }
+
+            ScmRecordProc::Modifier { rtype, name, field } => {
+                scm_ensure!(args.len() == 2, "{} requires exactly 2 arguments", name);
+                let record = expect_record(*name, rtype, &args[0])?;
+                record.fields.borrow_mut()[*field] = args[1].clone();
+                Ok(ScmValue::Nil)
+            }
+        }
+    }
+}
+
+// `define-record-type` expands to calls of these builtins, so that every
+// evaluation of the definition creates a new type.
+// (make-record-type 'name '(field ...))
+pub fn scm_make_record_type(_ctx: &mut ScmExecContext, args: &[ScmValue]) -> ScmResult {
+    let [ScmValue::Symbol(name), fields] = args else {
+        return Err(scm_error!("Make-record-type requires name and fields"));
+    };
+    let fields = scm_list_to_vec(fields)
+        .iter()
+        .map(|field| match field {
+            ScmValue::Symbol(field) => Ok(*field),
+            _ => Err(scm_error!("Record field name must be a symbol")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ScmValue::RecordType(Rc::new(ScmRecordType {
+        name: *name,
+        fields,
+    })))
+}
+
+// (record-procedure kind type name field ...), where kind is constructor,
+// predicate, accessor or modifier and fields are indexes
+pub fn scm_record_procedure(_ctx: &mut ScmExecContext, args: &[ScmValue]) -> ScmResult {
+    let [ScmValue::Symbol(kind), rtype, ScmValue::Symbol(name), fields @ ..] = args else {
+        return Err(scm_error!("Record-procedure requires kind, type and name"));
+    };
+    let ScmValue::RecordType(rtype) = rtype else {
+        return Err(ScmError::new(
+            ScmErrorKind::Error,
+            format!("{} requires a record type", name),
+            vec![rtype.clone()],
+        ));
+    };
+    let fields = fields
+        .iter()
+        .map(|field| match field {
+            ScmValue::Number(TypedNum::Integer(index))
+                if (*index as usize) < rtype.fields.len() =>
+            {
+                Ok(*index as usize)
+            }
+            _ => Err(scm_error!("Invalid record field index")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (rtype, name) = (rtype.clone(), *name);
+    let proc = match (kind.as_str(), fields.first()) {
+        ("constructor", _) => ScmRecordProc::Constructor { rtype, fields },
+        ("predicate", _) => ScmRecordProc::Predicate(rtype),
+        ("accessor", Some(&field)) => ScmRecordProc::Accessor { rtype, name, field },
+        ("modifier", Some(&field)) => ScmRecordProc::Modifier { rtype, name, field },
+        _ => return Err(scm_error!("Invalid record procedure: {}", kind)),
+    };
+    Ok(ScmValue::Procedure(ScmCallable::Record(Rc::new(proc))))
+}
+
+impl fmt::Debug for ScmRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<{}", self.rtype.display_name())?;
+        for (name, val) in self.rtype.fields.iter().zip(self.fields.borrow().iter()) {
+            write!(f, " {}: {}", name, scm_write(val))?;
+        }
+        write!(f, ">")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn records() {
+        let code = "
+            (define-record-type <point>
+              (make-point x y)
+              point?
+              (x point-x set-point-x!)
+              (y point-y))
+            (define p (make-point 1 2))
+            (set-point-x! p 10)
+            (list (point? p) (point? 5) (point-x p) (point-y p)
+                  (guard (e (#t (error-object-message e))) (point-x 'a)))";
+        let expected = "'(#t #f 10 2 \"point-x requires argument of record type point\")";
+        assert!(scm_equal(&run(code), &run(expected)));
+
+        let code = "
+            (define (make-counter)
+              (define-record-type counter (new-counter n) counter? (n count set-count!))
+              (new-counter 0))
+            (make-counter)";
+        assert_eq!(scm_write(&run(code)).to_string(), "#<counter n: 0>");
+
+        // Every evaluation of the definition creates a new type
+        let code = "
+            (define (make-type)
+              (define-record-type point (make-point x) point? (x point-x))
+              (cons make-point point?))
+            (define a (make-type))
+            (define b (make-type))
+            (list ((cdr a) ((car a) 1)) ((cdr b) ((car a) 1)))";
+        assert!(scm_equal(&run(code), &run("'(#t #f)")));
+    }
+}
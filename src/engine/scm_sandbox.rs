@@ -0,0 +1,289 @@
+use crate::engine::{scm_error::*, scm_port::io_error};
+use core::fmt;
+use std::path::{Path, PathBuf};
+
+// Group of builtins a context can be given. Builtins of a group that is not
+// given are neither globals nor exported by the standard libraries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScmCapability {
+    Pure,    // Computation without side effects outside of Scheme values
+    String,  // Strings, chars, symbols and string ports
+    IoRead,  // Reading from ports and opening files for reading
+    IoWrite, // Writing to ports and opening files for writing
+    System,  // Loading code from files, handling interrupts
+}
+
+impl ScmCapability {
+    pub const ALL: [ScmCapability; 5] = [
+        ScmCapability::Pure,
+        ScmCapability::String,
+        ScmCapability::IoRead,
+        ScmCapability::IoWrite,
+        ScmCapability::System,
+    ];
+
+    // Builtins not listed here are pure
+    const GROUPS: &'static [(ScmCapability, &'static [&'static str])] = &[
+        (
+            ScmCapability::String,
+            &[
+                "string->symbol",
+                "symbol->string",
+                "symbol=?",
+                "make-string",
+                "string",
+                "string-length",
+                "string-ref",
+                "string-set!",
+                "string-fill!",
+                "substring",
+                "string-copy",
+                "string-append",
+                "string=?",
+                "string<?",
+                "string>?",
+                "string<=?",
+                "string>=?",
+                "string-ci=?",
+                "string-ci<?",
+                "string-ci>?",
+                "string-ci<=?",
+                "string-ci>=?",
+                "string-upcase",
+                "string-downcase",
+                "string-foldcase",
+                "string->list",
+                "list->string",
+                "string->number",
+                "number->string",
+                "string-index",
+                "string-search-forward",
+                "string-split",
+                "string-join",
+                "char=?",
+                "char<?",
+                "char>?",
+                "char<=?",
+                "char>=?",
+                "char-ci=?",
+                "char-ci<?",
+                "char-ci>?",
+                "char-ci<=?",
+                "char-ci>=?",
+                "char-alphabetic?",
+                "char-numeric?",
+                "char-whitespace?",
+                "char-upper-case?",
+                "char-lower-case?",
+                "char-upcase",
+                "char-downcase",
+                "char-foldcase",
+                "digit-value",
+                "char->integer",
+                "integer->char",
+                "open-input-string",
+                "open-output-string",
+                "get-output-string",
+                "call-with-output-string",
+                "with-output-to-string",
+            ],
+        ),
+        (
+            ScmCapability::IoRead,
+            &[
+                "read",
+                "read-char",
+                "peek-char",
+                "read-line",
+                "read-string",
+                "char-ready?",
+                "open-input-file",
+                "current-input-port",
+            ],
+        ),
+        (
+            ScmCapability::IoWrite,
+            &[
+                "display",
+                "write",
+                "newline",
+                "write-char",
+                "write-string",
+                "flush-output-port",
+                "open-output-file",
+                "call-with-output-file",
+                "with-output-to-file",
+                "current-output-port",
+                "current-error-port",
+            ],
+        ),
+        (ScmCapability::System, &["load", "set-interrupt-handler!"]),
+    ];
+
+    // Group of the builtin or port parameter
+    pub fn of_builtin(name: &str) -> ScmCapability {
+        Self::GROUPS
+            .iter()
+            .find(|(_, names)| names.contains(&name))
+            .map_or(ScmCapability::Pure, |(cap, _)| *cap)
+    }
+}
+
+impl fmt::Display for ScmCapability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScmCapability::Pure => write!(f, "pure"),
+            ScmCapability::String => write!(f, "string"),
+            ScmCapability::IoRead => write!(f, "io-read"),
+            ScmCapability::IoWrite => write!(f, "io-write"),
+            ScmCapability::System => write!(f, "system"),
+        }
+    }
+}
+
+// Files Scheme code may open, load or include. Files loaded by the host
+// program itself are not checked.
+#[derive(Debug, Clone, Default)]
+pub struct ScmFsAccess {
+    allowed: Option<Vec<PathBuf>>, // None means any file
+}
+
+impl ScmFsAccess {
+    pub fn unrestricted() -> ScmFsAccess {
+        ScmFsAccess { allowed: None }
+    }
+
+    pub fn deny_all() -> ScmFsAccess {
+        ScmFsAccess {
+            allowed: Some(Vec::new()),
+        }
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.allowed.is_some()
+    }
+
+    // Canonical paths of the allowed directories
+    pub fn allowed_dirs(&self) -> &[PathBuf] {
+        self.allowed.as_deref().unwrap_or_default()
+    }
+
+    // Allows the files under the directory, which must exist. Does nothing
+    // if access is unrestricted.
+    pub fn allow_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), ScmError> {
+        let dir = dir.as_ref();
+        let dir = dir
+            .canonicalize()
+            .map_err(|err| io_error(err, &dir.display().to_string()))?;
+        if let Some(allowed) = self.allowed.as_mut() {
+            allowed.push(dir);
+        }
+        Ok(())
+    }
+
+    // Symlinks and `..` are resolved first. A file that doesn't exist yet is
+    // checked by its directory, unless it's a dangling symlink, which could
+    // create a file anywhere.
+    pub fn check(&self, path: &Path) -> Result<(), ScmError> {
+        let Some(allowed) = &self.allowed else {
+            return Ok(());
+        };
+        let resolved = path.canonicalize().ok().or_else(|| {
+            let meta = path.symlink_metadata();
+            if meta.is_ok_and(|meta| meta.file_type().is_symlink()) {
+                return None;
+            }
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            Some(dir.canonicalize().ok()?.join(path.file_name()?))
+        });
+        match resolved {
+            Some(resolved) if allowed.iter().any(|dir| resolved.starts_with(dir)) => Ok(()),
+            _ => Err(ScmError::new(
+                ScmErrorKind::File,
+                format!("{}: access denied", path.display()),
+                Vec::new(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn fs_access() {
+        let dir = std::env::temp_dir().join("scm_fs_access");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let mut access = ScmFsAccess::deny_all();
+        assert!(access.check(&dir.join("sub/new.txt")).is_err());
+        access.allow_dir(dir.join("sub")).unwrap();
+        assert!(access.check(&dir.join("sub/new.txt")).is_ok());
+        assert!(access.check(&dir.join("sub/../other.txt")).is_err());
+        assert!(access.check(&dir.join("missing/new.txt")).is_err());
+        assert!(access.allow_dir(dir.join("missing")).is_err());
+        assert!(ScmFsAccess::unrestricted().check(Path::new("/")).is_ok());
+        assert_eq!(ScmCapability::of_builtin("load"), ScmCapability::System);
+        assert_eq!(ScmCapability::of_builtin("car"), ScmCapability::Pure);
+    }
+
+    #[test]
+    fn sandbox() {
+        let dir = std::env::temp_dir().join(format!("scm_sandbox_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(dir.join("data/in.scm"), "(define included 1)").unwrap();
+        std::fs::write(dir.join("secret.scm"), "(define leaked 1)").unwrap();
+
+        let mut ctx = ScmExecContext::sandboxed(&[ScmCapability::Pure, ScmCapability::IoRead]);
+        ctx.fs_access.allow_dir(dir.join("data")).unwrap();
+        assert!(ctx.has_capability(ScmCapability::IoRead));
+        assert!(!ctx.has_capability(ScmCapability::IoWrite));
+        let mut interp = Interpreter::with_context(ctx);
+        let failed = |interp: &mut Interpreter, code: &str| interp.eval_str(code).is_err();
+
+        assert!(scm_equal(&interp.eval_str("(+ 1 2)").unwrap(), &int(3)));
+        assert!(failed(&mut interp, "(display 1)"));
+        assert!(failed(&mut interp, "(string-append \"a\")"));
+        assert!(failed(&mut interp, "(load \"x.scm\")"));
+        // Standard libraries export only the allowed builtins
+        assert!(failed(
+            &mut interp,
+            "(eval '(display 1) (scheme-report-environment 5))"
+        ));
+        assert!(failed(
+            &mut interp,
+            "(import (only (scheme write) display))"
+        ));
+
+        let path = |name: &str| format!("{:?}", dir.join(name).display().to_string());
+        let code = format!("(read (open-input-file {}))", path("data/in.scm"));
+        assert!(!failed(&mut interp, &code));
+        let code = format!("(open-input-file {})", path("secret.scm"));
+        assert!(failed(&mut interp, &code));
+        let code = format!("(open-input-file {})", path("data/../secret.scm"));
+        assert!(failed(&mut interp, &code));
+        assert!(failed(
+            &mut interp,
+            &format!("(include {})", path("secret.scm"))
+        ));
+        interp
+            .eval_str(&format!("(include {})", path("data/in.scm")))
+            .unwrap();
+        assert!(interp.get_global("included").is_some());
+
+        // A dangling symlink would create its target outside the directory
+        #[cfg(unix)]
+        {
+            let link = dir.join("data/link.txt");
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(dir.join("escaped.txt"), &link).unwrap();
+            assert!(interp.context().fs_access.check(&link).is_err());
+            let code = format!("(open-input-file {})", path("data/link.txt"));
+            assert!(failed(&mut interp, &code));
+        }
+    }
+}
@@ -1,56 +1,60 @@
-use crate::*;
+use crate::{typed_num::TypedNum, *};
+use std::{collections::HashSet, rc::Rc};
 
 pub fn scm_is_list(val: &ScmValue) -> bool {
-    let mut cur = val;
-    if matches!(cur, ScmValue::Nil) {
-        return true;
-    }
-    loop {
-        if let ScmValue::DotPair(_, cdr) = cur {
-            if matches!(**cdr, ScmValue::Nil) {
-                return true;
-            }
-            cur = cdr;
-        } else {
-            return false;
-        }
-    }
+    scm_list_len(val).is_some()
 }
 
 pub fn scm_list_len(val: &ScmValue) -> Option<i64> {
-    let mut cur = val;
-    if matches!(cur, ScmValue::Nil) {
-        return Some(0);
-    }
-    let mut res = 1i64;
+    scm_list_len_polled(val, || Ok(())).unwrap_or(None)
+}
+
+// Length of the proper list, None for improper and circular lists. The
+// second cursor moves twice as fast and meets the first one in a cycle.
+// `tick` is called for every pair, so builtins can stop long loops when the
+// evaluation is interrupted or out of time.
+pub fn scm_list_len_polled(
+    val: &ScmValue,
+    mut tick: impl FnMut() -> Result<(), ScmError>,
+) -> Result<Option<i64>, ScmError> {
+    let cdr = |val: &ScmValue| match val {
+        ScmValue::DotPair(pair) => Some(pair.borrow().cdr.clone()),
+        _ => None,
+    };
+    let mut slow = val.clone();
+    let mut fast = val.clone();
+    let mut res = 0i64;
     loop {
-        if let ScmValue::DotPair(_, cdr) = cur {
-            if matches!(**cdr, ScmValue::Nil) {
-                return Some(res);
+        for _ in 0..2 {
+            match fast {
+                ScmValue::Nil => return Ok(Some(res)),
+                ScmValue::DotPair(_) => {
+                    res += 1;
+                    fast = cdr(&fast).unwrap();
+                    tick()?;
+                }
+                _ => return Ok(None),
             }
-            res += 1;
-            cur = cdr;
-        } else {
-            return None;
+        }
+        slow = cdr(&slow).unwrap();
+        if scm_eq(&slow, &fast) && matches!(fast, ScmValue::DotPair(_)) {
+            return Ok(None);
         }
     }
 }
 
 pub fn scm_list_to_vec(list: &ScmValue) -> Vec<ScmValue> {
-    if matches!(list, ScmValue::Nil) {
-        return Vec::new();
-    }
-
     let mut res = Vec::new();
-    let mut cur = list;
+    let mut cur = list.clone();
     loop {
         match cur {
             ScmValue::Nil => {
                 break;
             }
-            ScmValue::DotPair(car, cdr) => {
-                res.push(*car.clone());
-                cur = cdr;
+            ScmValue::DotPair(pair) => {
+                let pair = pair.borrow();
+                res.push(pair.car.clone());
+                cur = pair.cdr.clone();
             }
             _ => {
                 panic!("Not a list")
@@ -60,6 +64,12 @@ pub fn scm_list_to_vec(list: &ScmValue) -> Vec<ScmValue> {
     res
 }
 
+pub fn scm_vec_to_list(vec: Vec<ScmValue>) -> ScmValue {
+    vec.into_iter()
+        .rev()
+        .fold(ScmValue::Nil, |list, item| ScmValue::cons(item, list))
+}
+
 pub fn scm_is_true(val: &ScmValue) -> bool {
     match val {
         ScmValue::Bool(val) => *val,
@@ -84,3 +94,164 @@ pub fn scm_get_integer(val: &ScmValue) -> Option<i64> {
         _ => None,
     }
 }
+
+//
+// Equivalence predicates
+//
+
+fn scm_callable_eq(a: &ScmCallable, b: &ScmCallable) -> bool {
+    match (a, b) {
+        (ScmCallable::Builtin(f1), ScmCallable::Builtin(f2)) => std::ptr::fn_addr_eq(*f1, *f2),
+        (ScmCallable::Native(p1), ScmCallable::Native(p2)) => Rc::ptr_eq(p1, p2),
+        (ScmCallable::CustomProc(p1), ScmCallable::CustomProc(p2)) => Rc::ptr_eq(p1, p2),
+        (ScmCallable::Primitive(p1), ScmCallable::Primitive(p2)) => p1 == p2,
+        (ScmCallable::Continuation(k1), ScmCallable::Continuation(k2)) => Rc::ptr_eq(k1, k2),
+        (ScmCallable::Parameter(p1), ScmCallable::Parameter(p2)) => Rc::ptr_eq(p1, p2),
+        (ScmCallable::Record(p1), ScmCallable::Record(p2)) => Rc::ptr_eq(p1, p2),
+        _ => false,
+    }
+}
+
+pub fn scm_eq(a: &ScmValue, b: &ScmValue) -> bool {
+    scm_eqv(a, b)
+}
+
+pub fn scm_eqv(a: &ScmValue, b: &ScmValue) -> bool {
+    match (a, b) {
+        // Exactness matters: (eqv? 1 1.0) is #f. Floats are compared by
+        // bits, so 0.0 and -0.0 differ and NaN is eqv? to itself.
+        (ScmValue::Number(TypedNum::Integer(n1)), ScmValue::Number(TypedNum::Integer(n2))) => {
+            n1 == n2
+        }
+        (ScmValue::Number(TypedNum::Float(n1)), ScmValue::Number(TypedNum::Float(n2))) => {
+            n1.to_bits() == n2.to_bits()
+        }
+        (ScmValue::Bool(b1), ScmValue::Bool(b2)) => b1 == b2,
+        (ScmValue::Char(c1), ScmValue::Char(c2)) => c1 == c2,
+        (ScmValue::Symbol(s1), ScmValue::Symbol(s2)) => s1 == s2,
+        (ScmValue::String(s1), ScmValue::String(s2)) => Rc::ptr_eq(s1, s2),
+        (ScmValue::DotPair(p1), ScmValue::DotPair(p2)) => Rc::ptr_eq(p1, p2),
+        (ScmValue::Vector(v1), ScmValue::Vector(v2)) => Rc::ptr_eq(v1, v2),
+        (ScmValue::Nil, ScmValue::Nil) => true,
+        (ScmValue::Procedure(p1), ScmValue::Procedure(p2)) => scm_callable_eq(p1, p2),
+        (ScmValue::Error(e1), ScmValue::Error(e2)) => Rc::ptr_eq(e1, e2),
+        (ScmValue::Record(r1), ScmValue::Record(r2)) => Rc::ptr_eq(r1, r2),
+        (ScmValue::RecordType(t1), ScmValue::RecordType(t2)) => Rc::ptr_eq(t1, t2),
+        (ScmValue::HashTable(t1), ScmValue::HashTable(t2)) => Rc::ptr_eq(t1, t2),
+        (ScmValue::Port(p1), ScmValue::Port(p2)) => Rc::ptr_eq(p1, p2),
+        (ScmValue::Foreign(f1), ScmValue::Foreign(f2)) => Rc::ptr_eq(f1, f2),
+        (ScmValue::Eof, ScmValue::Eof) => true,
+        (ScmValue::Environment(e1), ScmValue::Environment(e2)) => Rc::ptr_eq(e1, e2),
+        _ => false,
+    }
+}
+
+// Compounds already being compared are assumed equal, so cyclic structures
+// are compared by bisimulation instead of looping forever. Parts left to
+// compare are kept on the heap, so deeply nested data doesn't use the Rust
+// stack.
+struct ScmEqual<T> {
+    visited: HashSet<(usize, usize)>,
+    tick: T,
+}
+
+impl<T: FnMut() -> Result<(), ScmError>> ScmEqual<T> {
+    fn equal(&mut self, a: &ScmValue, b: &ScmValue) -> Result<bool, ScmError> {
+        let mut pending = vec![(a.clone(), b.clone())];
+        while let Some((a, b)) = pending.pop() {
+            (self.tick)()?;
+            match (&a, &b) {
+                (ScmValue::String(s1), ScmValue::String(s2)) => {
+                    if *s1.borrow() != *s2.borrow() {
+                        return Ok(false);
+                    }
+                }
+                (ScmValue::DotPair(p1), ScmValue::DotPair(p2)) => {
+                    let key = (Rc::as_ptr(p1) as usize, Rc::as_ptr(p2) as usize);
+                    if Rc::ptr_eq(p1, p2) || !self.visited.insert(key) {
+                        continue;
+                    }
+                    let (p1, p2) = (p1.borrow(), p2.borrow());
+                    pending.push((p1.cdr.clone(), p2.cdr.clone()));
+                    pending.push((p1.car.clone(), p2.car.clone()));
+                }
+                (ScmValue::Vector(v1), ScmValue::Vector(v2)) => {
+                    let key = (Rc::as_ptr(v1) as usize, Rc::as_ptr(v2) as usize);
+                    if Rc::ptr_eq(v1, v2) || !self.visited.insert(key) {
+                        continue;
+                    }
+                    let (v1, v2) = (v1.borrow(), v2.borrow());
+                    if v1.len() != v2.len() {
+                        return Ok(false);
+                    }
+                    let items = v1.iter().cloned().zip(v2.iter().cloned());
+                    pending.extend(items.rev());
+                }
+                _ => {
+                    if !scm_eqv(&a, &b) {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+pub fn scm_equal(a: &ScmValue, b: &ScmValue) -> bool {
+    scm_equal_polled(a, b, || Ok(())).unwrap_or(false)
+}
+
+pub fn scm_equal_polled(
+    a: &ScmValue,
+    b: &ScmValue,
+    tick: impl FnMut() -> Result<(), ScmError>,
+) -> Result<bool, ScmError> {
+    let mut equal = ScmEqual {
+        visited: HashSet::new(),
+        tick,
+    };
+    equal.equal(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(val: i64) -> ScmValue {
+        ScmValue::Number(TypedNum::Integer(val))
+    }
+
+    #[test]
+    fn equivalence() {
+        let a = ScmValue::cons(int(1), ScmValue::cons(int(2), ScmValue::Nil));
+        let b = ScmValue::cons(int(1), ScmValue::cons(int(2), ScmValue::Nil));
+
+        assert!(scm_eq(&a, &a));
+        assert!(!scm_eqv(&a, &b));
+        assert!(scm_equal(&a, &b));
+        assert!(!scm_eqv(&int(1), &ScmValue::Number(TypedNum::Float(1.0))));
+        let float = |val| ScmValue::Number(TypedNum::Float(val));
+        assert!(!scm_eqv(&float(0.0), &float(-0.0)));
+        assert!(scm_eqv(&float(f64::NAN), &float(f64::NAN)));
+        assert!(scm_equal(
+            &ScmValue::new_string("abc"),
+            &ScmValue::new_string("abc")
+        ));
+    }
+
+    #[test]
+    fn equal_on_cycles() {
+        let make_cycle = || {
+            let list = ScmValue::cons(int(1), ScmValue::cons(int(2), ScmValue::Nil));
+            if let ScmValue::DotPair(first) = &list {
+                if let ScmValue::DotPair(second) = &first.borrow().cdr {
+                    second.borrow_mut().cdr = list.clone();
+                }
+            }
+            list
+        };
+
+        assert!(scm_equal(&make_cycle(), &make_cycle()));
+    }
+}
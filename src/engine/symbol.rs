@@ -0,0 +1,129 @@
+use crate::engine::scm_limits::track_alloc;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt, mem,
+    sync::{LazyLock, Mutex},
+};
+
+// Symbols are ids in the global symbol table, so comparing and cloning them
+// is cheap. Names are never freed, which lets `as_str` hand out `&'static str`,
+// so every new name counts as allocated, wherever the symbol comes from, and
+// code creating symbols from data checks the budget first.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+struct SymbolTable {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, Symbol>,
+    gensym_counter: u64,
+}
+
+static SYMBOLS: LazyLock<Mutex<SymbolTable>> = LazyLock::new(|| {
+    Mutex::new(SymbolTable {
+        names: Vec::new(),
+        ids: HashMap::new(),
+        gensym_counter: 0,
+    })
+});
+
+thread_local! {
+    // Names of the table copied by this thread, so `as_str` locks the table
+    // only for symbols it hasn't seen yet
+    static NAMES: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+// Bytes kept by the table for a new symbol with the name
+pub(crate) fn symbol_size(name: &str) -> usize {
+    name.len() + 4 * mem::size_of::<usize>()
+}
+
+impl SymbolTable {
+    fn push(&mut self, name: &str) -> Symbol {
+        track_alloc(symbol_size(name));
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name);
+        sym
+    }
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let mut table = SYMBOLS.lock().unwrap();
+        if let Some(sym) = table.ids.get(name) {
+            return *sym;
+        }
+        let sym = table.push(name);
+        let name = table.names[sym.0 as usize];
+        table.ids.insert(name, sym);
+        sym
+    }
+
+    // Interned symbol with the name, if there is one. Doesn't add the name
+    // to the table.
+    pub fn lookup(name: &str) -> Option<Symbol> {
+        SYMBOLS.lock().unwrap().ids.get(name).copied()
+    }
+
+    // Creates a symbol that is not reachable by name, so it never collides
+    // with a symbol from the source code.
+    pub fn uninterned(prefix: &str) -> Symbol {
+        let mut table = SYMBOLS.lock().unwrap();
+        table.gensym_counter += 1;
+        let name = format!("{}{}", prefix, table.gensym_counter);
+        table.push(&name)
+    }
+
+    pub fn is_interned(&self) -> bool {
+        let table = SYMBOLS.lock().unwrap();
+        table.ids.get(table.names[self.0 as usize]) == Some(self)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        NAMES.with(|names| {
+            let mut names = names.borrow_mut();
+            if let Some(name) = names.get(self.0 as usize) {
+                return *name;
+            }
+            let table = SYMBOLS.lock().unwrap();
+            let known = names.len();
+            names.extend_from_slice(&table.names[known..]);
+            names[self.0 as usize]
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        assert_eq!(Symbol::intern("foo"), Symbol::intern("foo"));
+        assert_ne!(Symbol::intern("foo"), Symbol::intern("bar"));
+        assert_eq!(Symbol::intern("foo").as_str(), "foo");
+        assert_eq!(Symbol::lookup("foo"), Some(Symbol::intern("foo")));
+        assert_eq!(Symbol::lookup("never interned"), None);
+
+        let sym = Symbol::uninterned("foo");
+        assert!(!sym.is_interned());
+        assert_ne!(sym, Symbol::intern(sym.as_str()));
+    }
+}
@@ -0,0 +1,23 @@
+// Helpers for tests running Scheme code
+use crate::{engine::*, frontend::Parser, typed_num::TypedNum};
+
+pub fn run(code: &str) -> ScmValue {
+    run_in(&mut ScmExecContext::new(), code)
+}
+
+// Evaluates the forms one by one and returns the result of the last one
+pub fn run_in(ctx: &mut ScmExecContext, code: &str) -> ScmValue {
+    let mut res = ScmValue::Nil;
+    for callable in Parser::new(code).parse().unwrap().iter() {
+        res = exec_callable(ctx, callable, &[]).unwrap();
+    }
+    res
+}
+
+pub fn int(val: i64) -> ScmValue {
+    ScmValue::Number(TypedNum::Integer(val))
+}
+
+pub fn sym(name: &str) -> ScmValue {
+    ScmValue::Symbol(Symbol::intern(name))
+}
@@ -27,6 +27,61 @@ impl TypedNum {
         }
     }
 
+    // Number syntax shared by the reader and `string->number`
+    pub fn parse(s: &str, radix: u32) -> Option<TypedNum> {
+        if let Ok(num) = i64::from_str_radix(s, radix) {
+            return Some(TypedNum::Integer(num));
+        }
+        match s {
+            "+inf.0" => return Some(TypedNum::Float(f64::INFINITY)),
+            "-inf.0" => return Some(TypedNum::Float(f64::NEG_INFINITY)),
+            "+nan.0" | "-nan.0" => return Some(TypedNum::Float(f64::NAN)),
+            _ => (),
+        }
+        // Rust also accepts words like "inf" and "NaN"
+        let is_decimal = s
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+        if radix != 10 || !is_decimal || !s.contains(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok().map(TypedNum::Float)
+    }
+
+    // Same, after the optional radix (#x, #b, #o, #d) and exactness (#e, #i)
+    // prefixes, in any order. Exact numbers are integers only, so #e1.5 is
+    // not a number.
+    pub fn parse_prefixed(mut s: &str, mut radix: u32) -> Option<TypedNum> {
+        let mut has_radix = false;
+        let mut exact = None;
+        while let Some(rest) = s.strip_prefix('#') {
+            let mut chars = rest.chars();
+            match chars.next()?.to_ascii_lowercase() {
+                c @ ('x' | 'b' | 'o' | 'd') if !has_radix => {
+                    has_radix = true;
+                    radix = match c {
+                        'x' => 16,
+                        'b' => 2,
+                        'o' => 8,
+                        _ => 10,
+                    };
+                }
+                c @ ('e' | 'i') if exact.is_none() => exact = Some(c == 'e'),
+                _ => return None,
+            }
+            s = chars.as_str();
+        }
+        let num = Self::parse(s, radix)?;
+        match (exact, num) {
+            (Some(true), Self::Float(val)) => {
+                let in_range = (-(2f64.powi(63))..2f64.powi(63)).contains(&val);
+                (in_range && val.fract() == 0.0).then_some(Self::Integer(val as i64))
+            }
+            (Some(false), Self::Integer(val)) => Some(Self::Float(val as f64)),
+            _ => Some(num),
+        }
+    }
+
     fn to_int_wn(self) -> Self {
         match self {
             Self::Integer(_) => self,
@@ -1,97 +1,156 @@
+use crate::engine::symbol::Symbol;
+
+const PAGE_SIZE: usize = 256;
+
+// Global variables of a context. Every defined name gets a slot in `values`,
+// found through pages of slot numbers indexed by the symbol id. So a global
+// reference resolved at compile time is still a few indexed loads at
+// runtime, while memory grows with the number of globals rather than with
+// the number of symbols ever created. Several names can share a slot, as
+// imported library variables do.
 #[derive(Clone)]
-pub struct NamedArgsList<T> {
-    args: Vec<(String, T)>,
+pub struct GlobalSlots<T> {
+    pages: Vec<Option<Box<[u32; PAGE_SIZE]>>>, // Slot numbers + 1, 0 for no slot
+    values: Vec<Option<T>>,
+    owners: Vec<Symbol>, // Names that defined the slots
 }
 
-impl<T: Clone> NamedArgsList<T> {
+impl<T: Clone> GlobalSlots<T> {
     pub fn new() -> Self {
-        Self { args: Vec::new() }
+        Self {
+            pages: Vec::new(),
+            values: Vec::new(),
+            owners: Vec::new(),
+        }
     }
 
-    pub fn get_ith_val(&self, ind: usize) -> T {
-        self.args[ind].1.clone()
+    fn slot(&self, name: Symbol) -> Option<usize> {
+        let page = self.pages.get(name.id() / PAGE_SIZE)?.as_ref()?;
+        match page[name.id() % PAGE_SIZE] {
+            0 => None,
+            slot => Some(slot as usize - 1),
+        }
     }
 
-    pub fn find_by_name(&self, name: &str) -> Option<T> {
-        for arg in self.args.iter() {
-            if arg.0 == *name {
-                return Some(arg.1.clone());
-            }
+    fn set_slot(&mut self, name: Symbol, slot: u32) {
+        let (page, index) = (name.id() / PAGE_SIZE, name.id() % PAGE_SIZE);
+        if page >= self.pages.len() {
+            self.pages.resize(page + 1, None);
         }
-        None
+        self.pages[page].get_or_insert_with(|| Box::new([0; PAGE_SIZE]))[index] = slot;
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<&T> {
+        self.values[self.slot(name)?].as_ref()
     }
 
-    pub fn len(&self) -> usize {
-        self.args.len()
+    // Defines the name in its own slot. A name sharing the slot of another
+    // one gets a new slot, the other name keeps its value.
+    pub fn add_or_assign_var(&mut self, name: Symbol, val: T) {
+        match self.slot(name) {
+            Some(slot) if self.owners[slot] == name => self.values[slot] = Some(val),
+            _ => {
+                self.values.push(Some(val));
+                self.owners.push(name);
+                self.set_slot(name, self.values.len() as u32);
+            }
+        }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    // Changes the value of a defined name, also seen by the names sharing
+    // its slot. Returns false if the name is not defined.
+    pub fn assign(&mut self, name: Symbol, val: T) -> bool {
+        match self.slot(name).map(|slot| &mut self.values[slot]) {
+            Some(Some(old)) => {
+                *old = val;
+                true
+            }
+            _ => false,
+        }
     }
 
-    pub fn iter(&self) -> std::slice::Iter<'_, (String, T)> {
-        self.args.iter()
+    // Makes the name share the slot of `target`. Returns false if the target
+    // is not defined.
+    pub fn alias(&mut self, name: Symbol, target: Symbol) -> bool {
+        match self.slot(target) {
+            Some(slot) if self.values[slot].is_some() => {
+                self.set_slot(name, slot as u32 + 1);
+                true
+            }
+            _ => false,
+        }
     }
 
-    pub fn copy_values(&self) -> Vec<T> {
-        let mut res = Vec::new();
-        for arg in self.args.iter() {
-            res.push(arg.1.clone());
+    // The slot of a removed name is not reused. Names sharing the slot of
+    // another one are only unbound.
+    pub fn remove(&mut self, name: Symbol) -> Option<T> {
+        let slot = self.slot(name)?;
+        self.set_slot(name, 0);
+        match self.owners[slot] == name {
+            true => self.values[slot].take(),
+            false => self.values[slot].clone(),
         }
-        res
     }
 }
 
-impl<T: Clone> Default for NamedArgsList<T> {
+impl<T: Clone> Default for GlobalSlots<T> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-#[derive(Clone)]
-pub struct VariablesSet<T> {
-    sets: Vec<NamedArgsList<T>>,
+// Lexical scopes known to the compiler, innermost last. Used to turn variable
+// names into (depth, index) addresses of the runtime environment frames.
+#[derive(Clone, Default)]
+pub struct LexicalScopes {
+    scopes: Vec<Vec<Symbol>>,
 }
 
-impl<T: Clone> VariablesSet<T> {
+impl LexicalScopes {
     pub fn new() -> Self {
-        let mut res = VariablesSet { sets: Vec::new() };
-        res.sets.push(NamedArgsList::<T>::new());
-        res
+        Self { scopes: Vec::new() }
     }
 
-    pub fn find_var(&self, name: &str) -> Option<T> {
-        for container in self.sets.iter().rev() {
-            let val = container.find_by_name(name);
-            if val.is_some() {
-                return val;
-            }
-        }
-        None
+    pub fn push(&mut self, names: Vec<Symbol>) {
+        self.scopes.push(names);
     }
 
-    pub fn add_or_assign_var(&mut self, name: &str, val: T) {
-        let cur_set: &mut NamedArgsList<T> = self.sets.last_mut().unwrap();
-        for mut var in cur_set.args.iter_mut() {
-            if var.0 == *name {
-                var.1 = val;
-                return;
+    pub fn pop(&mut self) {
+        self.scopes.pop();
+    }
+
+    pub fn resolve(&self, name: Symbol) -> Option<(usize, usize)> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(index) = scope.iter().position(|it| *it == name) {
+                return Some((depth, index));
             }
         }
-        cur_set.args.push((String::from(name), val));
+        None
     }
+}
 
-    pub fn add_set(&mut self, container: NamedArgsList<T>) {
-        self.sets.push(container);
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn pop_set(&mut self) {
-        self.sets.pop();
-    }
-}
+    #[test]
+    fn global_slots() {
+        let mut slots = GlobalSlots::new();
+        let (a, b) = (Symbol::uninterned("a"), Symbol::uninterned("b"));
+        slots.add_or_assign_var(a, 1);
+        slots.add_or_assign_var(b, 2);
+        slots.add_or_assign_var(a, 3);
+        assert_eq!(slots.get(a), Some(&3));
+        assert_eq!(slots.remove(b), Some(2));
+        assert_eq!(slots.get(b), None);
+        assert_eq!(slots.values.len(), 2);
 
-impl<T: Clone> Default for VariablesSet<T> {
-    fn default() -> Self {
-        Self::new()
+        let c = Symbol::uninterned("c");
+        assert!(slots.alias(c, a));
+        assert!(slots.assign(c, 4));
+        assert_eq!(slots.get(a), Some(&4));
+        slots.add_or_assign_var(c, 5);
+        assert_eq!((slots.get(a), slots.get(c)), (Some(&4), Some(&5)));
+        assert!(!slots.alias(c, b));
     }
 }
@@ -0,0 +1,1083 @@
+use crate::engine::{scm_core::*, scm_error::*, scm_prelude::prelude_procedure, scm_utils::*};
+use core::fmt;
+use std::{
+    cell::{Cell, RefCell},
+    mem,
+    rc::Rc,
+};
+
+// Special roles of a frame. Tail calls from the frame keep them.
+#[derive(Clone, Default)]
+struct ScmFrameMarks {
+    escape: Option<Rc<ScmContinuation>>, // Escape continuation returning from this frame
+    exit_state: Option<ScmDynamicState>, // Dynamic state to restore when this frame returns
+    exit_raise: Option<ScmValue>, // Non-continuable exception handled by this frame, which must not return
+}
+
+// Activation record of a custom procedure. Frames live on the heap (in the
+// `frames` vector of the VM), so deep recursion in Scheme code does not
+// consume the Rust stack and the whole control state can be copied into a
+// continuation.
+#[derive(Clone)]
+struct ScmFrame {
+    proc: Rc<ScmProcedure>,
+    env: Rc<ScmEnv>,
+    pc: usize,
+    stack_base: usize, // Values stack length at the moment of the call
+    marks: Option<Box<ScmFrameMarks>>, // Most frames have no marks
+}
+
+// Full continuations copy the frames and the values stack when captured and
+// again when resumed, the copies count against the allocation budget
+fn charge_copy(ctx: &ScmExecContext, frames: usize, stack: usize) -> Result<(), ScmError> {
+    let bytes = (frames + 1) * mem::size_of::<ScmFrame>() + stack * mem::size_of::<ScmValue>();
+    ctx.reserve_alloc(bytes)?;
+    ctx.track_alloc(bytes);
+    Ok(())
+}
+
+impl ScmFrame {
+    fn new(
+        ctx: &ScmExecContext,
+        proc: Rc<ScmProcedure>,
+        mut args: Vec<ScmValue>,
+        stack_base: usize,
+    ) -> Result<Self, ScmError> {
+        let template = &proc.template;
+
+        if template.has_rest {
+            scm_ensure!(
+                args.len() >= template.params_cnt - 1,
+                "Wrong number of arguments: expected at least {}, got {}",
+                template.params_cnt - 1,
+                args.len()
+            );
+            let rest = args
+                .drain(template.params_cnt - 1..)
+                .rev()
+                .fold(ScmValue::Nil, |list, arg| ScmValue::cons(arg, list));
+            args.push(rest);
+        } else {
+            scm_ensure!(
+                args.len() == template.params_cnt,
+                "Wrong number of arguments: expected {}, got {}",
+                template.params_cnt,
+                args.len()
+            );
+        }
+        args.resize(template.frame_size, ScmValue::Nil);
+        ctx.track_alloc(mem::size_of::<ScmEnv>() + args.len() * mem::size_of::<ScmValue>());
+
+        let env = Rc::new(ScmEnv {
+            vals: RefCell::new(args),
+            parent: proc.env.clone(),
+        });
+
+        Ok(Self {
+            proc,
+            env,
+            pc: 0,
+            stack_base,
+            marks: None,
+        })
+    }
+}
+
+// Re-entrant continuations hold a copy of the whole control state, which is
+// copied again every time the continuation is invoked. Escape continuations
+// only mark the frame they return from, so they are cheap to create, but can
+// be used only while that frame is alive.
+pub struct ScmContinuation {
+    state: ScmContinuationState,
+}
+
+enum ScmContinuationState {
+    Full {
+        run: ScmRunId, // Run the control state belongs to
+        frame: ScmFrame,
+        frames: Vec<ScmFrame>,
+        stack: Vec<ScmValue>,
+        dynamic_state: ScmDynamicState,
+    },
+    Escape {
+        dynamic_state: ScmDynamicState,
+    },
+}
+
+impl fmt::Debug for ScmContinuation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<continuation>")
+    }
+}
+
+// Run of the VM. Builtins calling procedures start nested runs, which end
+// when the builtin returns. Runs started by the host are outermost: the rest
+// of any of them is the rest of the evaluation.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ScmRunId {
+    id: usize,
+    outermost: bool,
+}
+
+thread_local! {
+    static NEXT_RUN_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+impl ScmRunId {
+    fn new(outermost: bool) -> ScmRunId {
+        let id = NEXT_RUN_ID.with(|next| next.replace(next.get() + 1));
+        ScmRunId { id, outermost }
+    }
+}
+
+// Run in progress that resumes the control state of `run`. The state of a
+// nested run that has ended can't be resumed, the builtin that started it
+// has returned already.
+fn resuming_run(ctx: &ScmExecContext, run: ScmRunId) -> Result<ScmRunId, ScmError> {
+    if ctx.vm_runs.contains(&run) {
+        return Ok(run);
+    }
+    match ctx.vm_runs.first() {
+        Some(outer) if run.outermost && outer.outermost => Ok(*outer),
+        _ => Err(scm_error!(
+            "Continuation captured in a call from a builtin can't be resumed after the builtin returned"
+        )),
+    }
+}
+
+fn winders_depth(winders: &Option<Rc<ScmWinder>>) -> usize {
+    winders.as_ref().map_or(0, |winder| winder.depth)
+}
+
+fn same_winders(w1: &Option<Rc<ScmWinder>>, w2: &Option<Rc<ScmWinder>>) -> bool {
+    match (w1, w2) {
+        (Some(w1), Some(w2)) => Rc::ptr_eq(w1, w2),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn common_winders(
+    mut w1: Option<Rc<ScmWinder>>,
+    mut w2: Option<Rc<ScmWinder>>,
+) -> Option<Rc<ScmWinder>> {
+    while winders_depth(&w1) > winders_depth(&w2) {
+        w1 = w1.unwrap().outer.winders.clone();
+    }
+    while winders_depth(&w2) > winders_depth(&w1) {
+        w2 = w2.unwrap().outer.winders.clone();
+    }
+    while !same_winders(&w1, &w2) {
+        w1 = w1.unwrap().outer.winders.clone();
+        w2 = w2.unwrap().outer.winders.clone();
+    }
+    w1
+}
+
+// Switches to the `target` dynamic state: runs `after` thunks of the winders
+// being left (innermost first), then `before` thunks of the winders being
+// entered (outermost first). Thunks run in the dynamic state of the
+// corresponding `dynamic-wind` call.
+fn do_wind(ctx: &mut ScmExecContext, target: ScmDynamicState) -> Result<(), ScmError> {
+    let common = common_winders(ctx.dynamic_state.winders.clone(), target.winders.clone());
+
+    while !same_winders(&ctx.dynamic_state.winders, &common) {
+        let winder = ctx.dynamic_state.winders.clone().unwrap();
+        ctx.dynamic_state = winder.outer.clone();
+        exec_callable(ctx, &winder.after, &[])?;
+    }
+
+    let mut entered = Vec::new();
+    let mut winders = target.winders.clone();
+    while !same_winders(&winders, &common) {
+        let winder = winders.unwrap();
+        winders = winder.outer.winders.clone();
+        entered.push(winder);
+    }
+    for winder in entered.into_iter().rev() {
+        ctx.dynamic_state = winder.outer.clone();
+        exec_callable(ctx, &winder.before, &[])?;
+    }
+
+    ctx.dynamic_state = target;
+    Ok(())
+}
+
+fn expect_callable(val: ScmValue) -> Result<ScmCallable, ScmError> {
+    match val {
+        ScmValue::Procedure(callable) => Ok(callable),
+        val => Err(scm_error!("Expected procedure, got {:?}", val)),
+    }
+}
+
+// Raised when the handler of a non-continuable exception returns
+fn handler_returned(obj: ScmValue) -> ScmError {
+    ScmError::new(
+        ScmErrorKind::Error,
+        String::from("Handler returned from non-continuable exception"),
+        vec![obj],
+    )
+}
+
+struct ScmVm {
+    run: ScmRunId,
+    stack: Vec<ScmValue>,
+    frames: Vec<ScmFrame>, // Callers of the current frame
+    frame: ScmFrame,
+}
+
+impl ScmVm {
+    // Pops the result of the current frame and passes it to the caller.
+    // Returns the result if there is no caller.
+    fn ret(&mut self, ctx: &mut ScmExecContext) -> Result<Option<ScmValue>, ScmError> {
+        let res = self.stack.pop().unwrap();
+        self.stack.truncate(self.frame.stack_base);
+
+        if let Some(marks) = self.frame.marks.take() {
+            if let Some(obj) = marks.exit_raise {
+                // The secondary exception is raised in the dynamic state of the
+                // handler, the frame which raised is left as is
+                self.frame = self.frames.pop().unwrap();
+                return Err(handler_returned(obj));
+            }
+            if let Some(state) = marks.exit_state {
+                do_wind(ctx, state)?;
+            }
+        }
+
+        match self.frames.pop() {
+            Some(caller) => {
+                self.frame = caller;
+                self.stack.push(res);
+                Ok(None)
+            }
+            None => Ok(Some(res)),
+        }
+    }
+
+    // Calls procedure placed in the stack below `args_cnt` arguments. Custom
+    // procedures get a new frame, results of other callables are pushed into
+    // the stack right away. Returns the result if the VM has finished.
+    fn call(
+        &mut self,
+        ctx: &mut ScmExecContext,
+        mut args_cnt: usize,
+        tail: bool,
+    ) -> Result<Option<ScmValue>, ScmError> {
+        loop {
+            let callee_idx = self.stack.len() - args_cnt - 1;
+
+            let callee = match &self.stack[callee_idx] {
+                ScmValue::Procedure(callee) => callee.clone(),
+                val => return Err(scm_error!("Value cannot be called: {:?}", val)),
+            };
+
+            match callee {
+                ScmCallable::Builtin(func) => {
+                    let res = self.nested(ctx, |ctx| (func)(ctx, &self.stack[callee_idx + 1..]))?;
+                    self.stack.truncate(callee_idx);
+                    self.stack.push(res);
+                    return Ok(None);
+                }
+
+                ScmCallable::Native(native) => {
+                    let res =
+                        self.nested(ctx, |ctx| native.call(ctx, &self.stack[callee_idx + 1..]))?;
+                    self.stack.truncate(callee_idx);
+                    self.stack.push(res);
+                    return Ok(None);
+                }
+
+                ScmCallable::CustomProc(proc) => {
+                    let args = self.stack.split_off(callee_idx + 1);
+                    self.stack.pop();
+
+                    if tail {
+                        let marks = mem::take(&mut self.frame.marks);
+                        self.stack.truncate(self.frame.stack_base);
+                        self.frame = ScmFrame::new(ctx, proc, args, self.frame.stack_base)?;
+                        self.frame.marks = marks;
+                    } else {
+                        ctx.usage.check_depth(&ctx.limits, self.frames.len() + 2)?;
+                        let callee = ScmFrame::new(ctx, proc, args, self.stack.len())?;
+                        self.frames.push(mem::replace(&mut self.frame, callee));
+                    }
+                    return Ok(None);
+                }
+
+                ScmCallable::Primitive(ScmPrimitive::Apply) => {
+                    // (apply f a b '(c d)) becomes (f a b c d)
+                    scm_ensure!(args_cnt >= 2, "Apply requires at least 2 arguments");
+                    let list = self.stack.pop().unwrap();
+                    let len = scm_list_len_polled(&list, || ctx.usage.tick())?;
+                    scm_ensure!(len.is_some(), "Last argument of apply must be a list");
+                    self.stack.remove(callee_idx);
+                    let spread = scm_list_to_vec(&list);
+                    args_cnt = args_cnt - 2 + spread.len();
+                    self.stack.extend(spread);
+                }
+
+                ScmCallable::Primitive(ScmPrimitive::Prelude(name)) => {
+                    // Called as the compiled procedure, so continuations
+                    // captured inside stay in this run
+                    self.stack[callee_idx] = ScmValue::Procedure(prelude_procedure(name)?);
+                }
+
+                ScmCallable::Primitive(ScmPrimitive::CallCC) => {
+                    scm_ensure!(args_cnt == 1, "Call/cc requires exactly 1 argument");
+                    charge_copy(ctx, self.frames.len(), callee_idx)?;
+                    let mut stack = self.stack.clone();
+                    stack.truncate(callee_idx);
+                    let cont = ScmContinuation {
+                        state: ScmContinuationState::Full {
+                            run: self.run,
+                            frame: self.frame.clone(),
+                            frames: self.frames.clone(),
+                            stack,
+                            dynamic_state: ctx.dynamic_state.clone(),
+                        },
+                    };
+                    self.stack[callee_idx] = self.stack.pop().unwrap();
+                    self.stack
+                        .push(ScmValue::Procedure(ScmCallable::Continuation(Rc::new(
+                            cont,
+                        ))));
+                }
+
+                ScmCallable::Primitive(ScmPrimitive::CallEC) => {
+                    scm_ensure!(args_cnt == 1, "Call/ec requires exactly 1 argument");
+                    let cont = Rc::new(ScmContinuation {
+                        state: ScmContinuationState::Escape {
+                            dynamic_state: ctx.dynamic_state.clone(),
+                        },
+                    });
+                    self.stack[callee_idx] = self.stack.pop().unwrap();
+                    self.stack
+                        .push(ScmValue::Procedure(ScmCallable::Continuation(cont.clone())));
+                    let marks = ScmFrameMarks {
+                        escape: Some(cont),
+                        ..Default::default()
+                    };
+                    return self.call_marked(ctx, 1, marks);
+                }
+
+                ScmCallable::Primitive(ScmPrimitive::DynamicWind) => {
+                    scm_ensure!(args_cnt == 3, "Dynamic-wind requires exactly 3 arguments");
+                    let after = expect_callable(self.stack.pop().unwrap())?;
+                    let thunk = self.stack.pop().unwrap();
+                    let before = expect_callable(self.stack.pop().unwrap())?;
+
+                    exec_callable(ctx, &before, &[])?;
+
+                    let saved = ctx.dynamic_state.clone();
+                    ctx.dynamic_state.winders = Some(Rc::new(ScmWinder {
+                        before,
+                        after,
+                        depth: winders_depth(&saved.winders) + 1,
+                        outer: saved.clone(),
+                    }));
+
+                    self.stack[callee_idx] = thunk;
+                    let marks = ScmFrameMarks {
+                        exit_state: Some(saved),
+                        ..Default::default()
+                    };
+                    return self.call_marked(ctx, 0, marks);
+                }
+
+                ScmCallable::Primitive(ScmPrimitive::Parameterize) => {
+                    // (%parameterize thunk param1 value1 param2 value2 ...)
+                    scm_ensure!(
+                        args_cnt % 2 == 1,
+                        "Parameterize requires a thunk and pairs of parameters and values"
+                    );
+                    let bindings = self.stack.split_off(callee_idx + 2);
+                    self.stack.remove(callee_idx);
+
+                    let saved = ctx.dynamic_state.clone();
+                    let mut params = saved.params.clone();
+                    for binding in bindings.chunks(2) {
+                        let param = match &binding[0] {
+                            ScmValue::Procedure(ScmCallable::Parameter(param)) => param.clone(),
+                            val => {
+                                return Err(scm_error!("Expected parameter object, got {:?}", val))
+                            }
+                        };
+                        let value = match &param.converter {
+                            Some(converter) => exec_callable(ctx, converter, &binding[1..])?,
+                            None => binding[1].clone(),
+                        };
+                        params = Some(Rc::new(ScmParamBinding {
+                            param,
+                            value,
+                            parent: params,
+                        }));
+                    }
+
+                    ctx.dynamic_state.params = params;
+                    let marks = ScmFrameMarks {
+                        exit_state: Some(saved),
+                        ..Default::default()
+                    };
+                    return self.call_marked(ctx, 0, marks);
+                }
+
+                ScmCallable::Primitive(ScmPrimitive::WithExceptionHandler) => {
+                    scm_ensure!(
+                        args_cnt == 2,
+                        "With-exception-handler requires exactly 2 arguments"
+                    );
+                    let thunk = self.stack.pop().unwrap();
+                    let handler = expect_callable(self.stack.pop().unwrap())?;
+
+                    let saved = ctx.dynamic_state.clone();
+                    ctx.dynamic_state.handlers = Some(Rc::new(ScmHandler {
+                        handler,
+                        parent: saved.handlers.clone(),
+                    }));
+
+                    self.stack[callee_idx] = thunk;
+                    let marks = ScmFrameMarks {
+                        exit_state: Some(saved),
+                        ..Default::default()
+                    };
+                    return self.call_marked(ctx, 0, marks);
+                }
+
+                ScmCallable::Primitive(
+                    primitive @ (ScmPrimitive::Raise | ScmPrimitive::RaiseContinuable),
+                ) => {
+                    scm_ensure!(args_cnt == 1, "Raise requires exactly 1 argument");
+                    let obj = self.stack.pop().unwrap();
+                    self.stack.truncate(callee_idx);
+                    return self.raise(ctx, obj, primitive == ScmPrimitive::RaiseContinuable);
+                }
+
+                ScmCallable::Record(proc) => {
+                    let res = proc.call(&self.stack[callee_idx + 1..])?;
+                    self.stack.truncate(callee_idx);
+                    self.stack.push(res);
+                    return Ok(None);
+                }
+
+                ScmCallable::Parameter(param) => {
+                    scm_ensure!(args_cnt == 0, "Parameter object accepts no arguments");
+                    self.stack[callee_idx] = ctx.dynamic_state.param_value(&param);
+                    return Ok(None);
+                }
+
+                ScmCallable::Continuation(cont) => {
+                    scm_ensure!(args_cnt <= 1, "Continuation accepts at most 1 value");
+                    let val = match args_cnt {
+                        0 => ScmValue::Nil,
+                        _ => self.stack.pop().unwrap(),
+                    };
+                    return self.resume(ctx, &cont, val);
+                }
+            }
+        }
+    }
+
+    // Runs the builtin, which may run the VM again. Frames of this run count
+    // in the depth of the nested runs. A builtin stopped by an interrupt
+    // can't go on, so it fails after the interrupt handler returns.
+    fn nested(
+        &self,
+        ctx: &mut ScmExecContext,
+        func: impl FnOnce(&mut ScmExecContext) -> ScmResult,
+    ) -> ScmResult {
+        let frames = self.frames.len() + 1;
+        ctx.usage.depth += frames;
+        let mut res = func(ctx);
+        if let (Err(ScmError::Interrupted), false) = (&res, ctx.usage.is_forced()) {
+            if let Some(handler) = ctx.interrupt_handler.clone() {
+                res = exec_callable(ctx, &handler, &[]).and(Err(scm_error!("Builtin interrupted")));
+            }
+        }
+        ctx.usage.depth -= frames;
+        res
+    }
+
+    // Failed check of the limits. Interrupts are passed to the interrupt
+    // handler, if there is one, and the evaluation goes on after it returns.
+    // Hard interrupts stop the evaluation anyway.
+    fn interrupted(&self, ctx: &mut ScmExecContext, err: ScmError) -> Result<(), ScmError> {
+        match (err, ctx.interrupt_handler.clone()) {
+            (ScmError::Interrupted, Some(handler)) if !ctx.usage.is_forced() => {
+                self.nested(ctx, |ctx| exec_callable(ctx, &handler, &[]))?;
+                Ok(())
+            }
+            (err, _) => Err(err),
+        }
+    }
+
+    // Calls procedure placed in the stack below `args_cnt` arguments in a new
+    // frame with the given marks (never a tail call). Results of builtins and
+    // other callables without frames are processed as if they were returned
+    // from such a frame.
+    fn call_marked(
+        &mut self,
+        ctx: &mut ScmExecContext,
+        args_cnt: usize,
+        marks: ScmFrameMarks,
+    ) -> Result<Option<ScmValue>, ScmError> {
+        let callee_idx = self.stack.len() - args_cnt - 1;
+
+        let callee = match &self.stack[callee_idx] {
+            ScmValue::Procedure(ScmCallable::CustomProc(proc)) => {
+                ctx.usage.check_depth(&ctx.limits, self.frames.len() + 2)?;
+                let proc = proc.clone();
+                let args = self.stack.split_off(callee_idx + 1);
+                self.stack.pop();
+
+                let mut callee = ScmFrame::new(ctx, proc, args, self.stack.len())?;
+                callee.marks = Some(Box::new(marks));
+                self.frames.push(mem::replace(&mut self.frame, callee));
+                return Ok(None);
+            }
+            // Continuations replace the dynamic state themselves
+            ScmValue::Procedure(ScmCallable::Continuation(_)) => {
+                return self.call(ctx, args_cnt, false);
+            }
+            ScmValue::Procedure(callee) => callee.clone(),
+            val => return Err(scm_error!("Value cannot be called: {:?}", val)),
+        };
+
+        let args = self.stack.split_off(callee_idx + 1);
+        self.stack.pop();
+        let res = self.nested(ctx, |ctx| exec_callable(ctx, &callee, &args))?;
+
+        if let Some(obj) = marks.exit_raise {
+            return Err(handler_returned(obj));
+        }
+        if let Some(state) = marks.exit_state {
+            do_wind(ctx, state)?;
+        }
+        self.stack.push(res);
+        Ok(None)
+    }
+
+    // Calls the current exception handler with the handlers outside of it
+    // installed. The result of the handler is returned from
+    // `raise-continuable`, while returning from a handler of `raise` is an
+    // error.
+    fn raise(
+        &mut self,
+        ctx: &mut ScmExecContext,
+        obj: ScmValue,
+        continuable: bool,
+    ) -> Result<Option<ScmValue>, ScmError> {
+        let handler = match &ctx.dynamic_state.handlers {
+            Some(handler) => handler.clone(),
+            None => return Err(ScmError::Raised(obj)),
+        };
+
+        let saved = ctx.dynamic_state.clone();
+        ctx.dynamic_state.handlers = handler.parent.clone();
+
+        self.stack
+            .push(ScmValue::Procedure(handler.handler.clone()));
+        self.stack.push(obj.clone());
+        let marks = match continuable {
+            true => ScmFrameMarks {
+                exit_state: Some(saved),
+                ..Default::default()
+            },
+            false => ScmFrameMarks {
+                exit_raise: Some(obj),
+                ..Default::default()
+            },
+        };
+        self.call_marked(ctx, 1, marks)
+    }
+
+    fn resume(
+        &mut self,
+        ctx: &mut ScmExecContext,
+        cont: &Rc<ScmContinuation>,
+        val: ScmValue,
+    ) -> Result<Option<ScmValue>, ScmError> {
+        match &cont.state {
+            ScmContinuationState::Full {
+                run,
+                frame,
+                frames,
+                stack,
+                dynamic_state,
+            } => {
+                // Other runs are left by returning the escape through the
+                // builtins that started them
+                if resuming_run(ctx, *run)? != self.run {
+                    return Err(ScmError::Escape(cont.clone(), val));
+                }
+                charge_copy(ctx, frames.len(), stack.len())?;
+                do_wind(ctx, dynamic_state.clone())?;
+                self.frame = frame.clone();
+                self.frames = frames.clone();
+                self.stack = stack.clone();
+                self.stack.push(val);
+                Ok(None)
+            }
+
+            ScmContinuationState::Escape { dynamic_state } => {
+                let is_target = |frame: &ScmFrame| {
+                    frame
+                        .marks
+                        .as_ref()
+                        .and_then(|marks| marks.escape.as_ref())
+                        .is_some_and(|escape| Rc::ptr_eq(escape, cont))
+                };
+
+                if !is_target(&self.frame) {
+                    // The frame may belong to a run this one is nested into
+                    let pos = match self.frames.iter().rposition(is_target) {
+                        Some(pos) => pos,
+                        None => return Err(ScmError::Escape(cont.clone(), val)),
+                    };
+                    self.frames.truncate(pos + 1);
+                    self.frame = self.frames.pop().unwrap();
+                }
+
+                do_wind(ctx, dynamic_state.clone())?;
+                self.stack.push(val);
+                self.ret(ctx)
+            }
+        }
+    }
+
+    // Executes instructions until the VM finishes or an error occurs
+    fn exec(&mut self, ctx: &mut ScmExecContext) -> ScmResult {
+        loop {
+            if let Err(err) = ctx.usage.step(&ctx.limits) {
+                self.interrupted(ctx, err)?;
+            }
+            let template = &self.frame.proc.template;
+            let op = template.code[self.frame.pc];
+            self.frame.pc += 1;
+
+            match op {
+                ScmOp::Const(idx) => {
+                    self.stack.push(template.consts[idx as usize].clone());
+                }
+
+                ScmOp::LocalRef(depth, index) => {
+                    let val = self.frame.env.lookup(depth as usize, index as usize);
+                    self.stack.push(val);
+                }
+
+                ScmOp::LocalSet(depth, index) => {
+                    let val = mem::replace(self.stack.last_mut().unwrap(), ScmValue::Nil);
+                    self.frame.env.assign(depth as usize, index as usize, val);
+                }
+
+                ScmOp::GlobalRef(name) => match ctx.globals.get(name) {
+                    Some(val) => self.stack.push(val.clone()),
+                    None => {
+                        return Err(ScmError::new(
+                            ScmErrorKind::Error,
+                            String::from("Unbound variable"),
+                            vec![ScmValue::Symbol(name)],
+                        ))
+                    }
+                },
+
+                ScmOp::GlobalSet(name) => {
+                    let val = mem::replace(self.stack.last_mut().unwrap(), ScmValue::Nil);
+                    if !ctx.globals.assign(name, val) {
+                        return Err(ScmError::new(
+                            ScmErrorKind::Error,
+                            String::from("Assignment to unbound variable"),
+                            vec![ScmValue::Symbol(name)],
+                        ));
+                    }
+                }
+
+                ScmOp::GlobalDefine(name) => {
+                    let val = mem::replace(self.stack.last_mut().unwrap(), ScmValue::Nil);
+                    ctx.globals.add_or_assign_var(name, val);
+                }
+
+                ScmOp::Closure(idx) => {
+                    ctx.track_alloc(mem::size_of::<ScmProcedure>());
+                    let proc = ScmProcedure {
+                        template: template.templates[idx as usize].clone(),
+                        env: Some(self.frame.env.clone()),
+                    };
+                    self.stack
+                        .push(ScmValue::Procedure(ScmCallable::CustomProc(Rc::new(proc))));
+                }
+
+                ScmOp::Jump(pos) => {
+                    self.frame.pc = pos as usize;
+                }
+
+                ScmOp::JumpIfFalse(pos) => {
+                    if !scm_is_true(&self.stack.pop().unwrap()) {
+                        self.frame.pc = pos as usize;
+                    }
+                }
+
+                // Result of a builtin in the tail position is returned by
+                // the following Return
+                ScmOp::Call(args_cnt) | ScmOp::TailCall(args_cnt) => {
+                    let tail = matches!(op, ScmOp::TailCall(_));
+                    if let Some(res) = self.call(ctx, args_cnt as usize, tail)? {
+                        return Ok(res);
+                    }
+                }
+
+                ScmOp::Return => {
+                    if let Some(res) = self.ret(ctx)? {
+                        return Ok(res);
+                    }
+                }
+
+                ScmOp::Pop => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    fn run(&mut self, ctx: &mut ScmExecContext) -> ScmResult {
+        loop {
+            let mut res = match self.exec(ctx) {
+                Ok(res) => return Ok(res),
+                // Continuation invoked by a nested run may return from a
+                // frame of this run or resume its state
+                Err(ScmError::Escape(cont, val)) => self.resume(ctx, &cont, val),
+                Err(err) => Err(err),
+            };
+
+            // Errors are raised as non-continuable exceptions. Calling the
+            // handler can fail as well, then the outer handler gets the new
+            // error.
+            while let Err(ScmError::Raised(obj)) = res {
+                if ctx.dynamic_state.handlers.is_none() {
+                    return Err(ScmError::Raised(obj));
+                }
+                res = self.raise(ctx, obj, false);
+            }
+
+            if let Some(res) = res? {
+                return Ok(res);
+            }
+        }
+    }
+}
+
+// The outermost call starts a new evaluation, which is checked against the
+// limits of the context
+pub fn exec_callable(
+    ctx: &mut ScmExecContext,
+    proc: &ScmCallable,
+    call_args: &[ScmValue],
+) -> ScmResult {
+    ctx.usage.enter(&ctx.limits)?;
+    let res = run_callable(ctx, proc, call_args);
+    ctx.usage.leave(res)
+}
+
+// Errors not handled by the callable leave the dynamic state as it was
+// before the call, running `after` thunks of the winders left.
+fn run_callable(ctx: &mut ScmExecContext, proc: &ScmCallable, call_args: &[ScmValue]) -> ScmResult {
+    match proc {
+        ScmCallable::Builtin(func) => return (func)(ctx, call_args),
+        ScmCallable::Native(native) => return native.call(ctx, call_args),
+        _ => (),
+    }
+
+    // Other callables are called from a trampoline procedure, which only
+    // calls the callable placed on its stack and returns the result
+    let trampoline = Rc::new(ScmProcedure {
+        template: Rc::new(ScmProcTemplate {
+            name: None,
+            params_cnt: 0,
+            has_rest: false,
+            frame_size: 0,
+            code: vec![ScmOp::Call(call_args.len() as u32), ScmOp::Return],
+            consts: Vec::new(),
+            templates: Vec::new(),
+        }),
+        env: None,
+    });
+
+    let mut stack = vec![ScmValue::Procedure(proc.clone())];
+    stack.extend_from_slice(call_args);
+
+    let mut vm = ScmVm {
+        run: ScmRunId::new(ctx.usage.is_outermost()),
+        stack,
+        frames: Vec::new(),
+        frame: ScmFrame::new(ctx, trampoline, Vec::new(), 0)?,
+    };
+
+    let saved = ctx.dynamic_state.clone();
+    ctx.vm_runs.push(vm.run);
+    let res = vm.run(ctx);
+    ctx.vm_runs.pop();
+    if res.is_err() && do_wind(ctx, saved.clone()).is_err() {
+        ctx.dynamic_state = saved;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::test_utils::*, *};
+
+    #[test]
+    fn closures() {
+        // Closures capture variables, not their values, so `set!` on a
+        // captured local is seen by every closure sharing it
+        let code = "
+            (define (make-counter)
+              (let ((n 0))
+                (list (lambda () (set! n (+ n 1)) n)
+                      (lambda () n))))
+            (define c1 (make-counter))
+            (define c2 (make-counter))
+            ((car c1))
+            ((car c1))
+            ((car c2))
+            (list ((cadr c1)) ((cadr c2)))";
+        assert!(scm_equal(&run(code), &run("'(2 1)")));
+
+        // Named let, with a body capturing the loop variables
+        let code = "
+            (let loop ((i 0) (acc '()))
+              (if (= i 3)
+                  (map (lambda (f) (f)) acc)
+                  (loop (+ i 1) (cons (lambda () i) acc))))";
+        assert!(scm_equal(&run(code), &run("'(2 1 0)")));
+    }
+
+    #[test]
+    fn deep_recursion() {
+        // Frames of Scheme procedures are on the heap, so recursion much
+        // deeper than the Rust stack of the thread allows works
+        let thread = std::thread::Builder::new().stack_size(256 << 10);
+        let res = thread.spawn(|| {
+            let code = "
+                (define (count n) (if (= n 0) 0 (+ 1 (count (- n 1)))))
+                (count 100000)";
+            scm_equal(&run(code), &int(100000))
+        });
+        assert!(res.unwrap().join().unwrap());
+    }
+
+    #[test]
+    fn errors_keep_context() {
+        // An error deep in the recursion leaves the context usable, with the
+        // definitions made before it
+        let mut ctx = ScmExecContext::new();
+        let code = "
+            (define calls 0)
+            (define (walk n) (set! calls (+ calls 1)) (if (= n 0) (car '()) (+ 1 (walk (- n 1)))))
+            (walk 1000)
+            (list calls (walk2 1))
+            (define (walk2 n) (if (= n 0) 0 (+ 1 (walk2 (- n 1)))))
+            (list calls (walk2 10))";
+        let callables = Parser::new(code).parse().unwrap();
+        for idx in [0, 1] {
+            exec_callable(&mut ctx, &callables[idx], &[]).unwrap();
+        }
+        for idx in [2, 3] {
+            assert!(exec_callable(&mut ctx, &callables[idx], &[]).is_err());
+        }
+        exec_callable(&mut ctx, &callables[4], &[]).unwrap();
+        let res = exec_callable(&mut ctx, &callables[5], &[]).unwrap();
+        assert!(scm_equal(&res, &run("'(1001 10)")));
+    }
+
+    #[test]
+    fn early_exit() {
+        let code = "
+            (define (first-above limit)
+              (call/cc
+                (lambda (return)
+                  (let loop ((i 0))
+                    (if (< limit i) (return i))
+                    (loop (+ i 1))))))
+            (first-above 5)";
+        assert!(scm_equal(&run(code), &int(6)));
+
+        let code = "(+ 1 (call/ec (lambda (k) (+ 10 (k 5)))))";
+        assert!(scm_equal(&run(code), &int(6)));
+    }
+
+    #[test]
+    fn tail_calls() {
+        // Tail calls reuse the frame, so loops run within a small depth
+        // limit, also through mutual recursion and `apply`
+        let mut ctx = ScmExecContext::new();
+        ctx.limits.max_depth = Some(8);
+        let code = "
+            (define (even? n) (if (= n 0) #t (odd? (- n 1))))
+            (define (odd? n) (if (= n 0) #f (apply even? (list (- n 1)))))
+            (even? 100000)";
+        assert!(scm_equal(&run_in(&mut ctx, code), &ScmValue::Bool(true)));
+
+        let code = "(define (count n) (if (= n 0) 0 (+ 1 (count (- n 1))))) (count 100)";
+        let callables = Parser::new(code).parse().unwrap();
+        exec_callable(&mut ctx, &callables[0], &[]).unwrap();
+        let err = exec_callable(&mut ctx, &callables[1], &[]).unwrap_err();
+        assert!(matches!(err, ScmError::Limit(ScmLimit::Depth)));
+    }
+
+    #[test]
+    fn reentry() {
+        let code = "
+            (let ()
+              (define k #f)
+              (define n 0)
+              (define acc '())
+              (set! acc (cons (call/cc (lambda (c) (set! k c) 0)) acc))
+              (set! n (+ n 1))
+              (if (< n 3) (k n))
+              acc)";
+        assert!(scm_equal(&run(code), &run("'(2 1 0)")));
+    }
+
+    #[test]
+    fn nested_runs() {
+        // Leaving a builtin for the outer run, also from a later evaluation
+        let code = "
+            (+ 1 (call/cc (lambda (k)
+                            (member 0 '(1 2 3) (lambda (a b) (if (= b 2) (k 10) #f))))))";
+        assert!(scm_equal(&run(code), &int(11)));
+        let code = "
+            (define k #f)
+            (define n (+ 1 (call/cc (lambda (c) (set! k c) 1))))
+            (if (= n 2) (member 0 '(5) (lambda (a b) (k b))))
+            n";
+        assert!(scm_equal(&run(code), &int(6)));
+
+        // The rest of the builtin call is gone once it has returned
+        let code = "
+            (let ((k #f) (n 0))
+              (let ((r (member 2 '(1 2 3)
+                               (lambda (a b)
+                                 (call/cc (lambda (c) (if (= b 2) (set! k c)) (= a b)))))))
+                (set! n (+ n 1))
+                (if (< n 2)
+                    (guard (e ((error-object? e) 'resumed)) (k #t))
+                    r)))";
+        assert!(scm_equal(&run(code), &sym("resumed")));
+    }
+
+    #[test]
+    fn backtracking() {
+        // amb-style search: re-entering `choose` yields its next alternative
+        let code = "
+            (define fail #f)
+            (define (choose n)
+              (let ((prev fail))
+                (if (= n 0)
+                    (prev)
+                    (call/cc (lambda (k)
+                      (set! fail (lambda ()
+                                   (set! fail prev)
+                                   (k (choose (- n 1)))))
+                      n)))))
+            (let ((a (choose 5))
+                  (b (choose 5)))
+              (if (if (< a b) (= (+ a b) 9) #f)
+                  (list a b)
+                  (fail)))";
+        assert!(scm_equal(&run(code), &run("'(4 5)")));
+    }
+
+    #[test]
+    fn dynamic_wind() {
+        // Re-entering the extent runs `before` again
+        let code = "
+            (define path '())
+            (define (add s) (set! path (cons s path)))
+            (define k #f)
+            (define n 0)
+            (dynamic-wind
+              (lambda () (add 'connect))
+              (lambda () (add (call/cc (lambda (c) (set! k c) 'talk1))))
+              (lambda () (add 'disconnect)))
+            (set! n (+ n 1))
+            (if (< n 2) (k 'talk2))
+            path";
+        let expected = "'(disconnect talk2 connect disconnect talk1 connect)";
+        assert!(scm_equal(&run(code), &run(expected)));
+
+        // Escaping from the extent runs `after`
+        let code = "
+            (define path '())
+            (call/ec (lambda (k)
+              (dynamic-wind
+                (lambda () (set! path (cons 'in path)))
+                (lambda () (k 0) (set! path (cons 'body path)))
+                (lambda () (set! path (cons 'out path))))))
+            path";
+        assert!(scm_equal(&run(code), &run("'(out in)")));
+    }
+
+    #[test]
+    fn parameters() {
+        let code = "
+            (define p (make-parameter 10 (lambda (x) (+ x 1))))
+            (define (get) (p))
+            (list (get)
+                  (parameterize ((p 20)) (get))
+                  (call/ec (lambda (k) (parameterize ((p 30)) (k (get)))))
+                  (get))";
+        assert!(scm_equal(&run(code), &run("'(11 21 31 11)")));
+    }
+
+    #[test]
+    fn exceptions() {
+        let code = "
+            (define (safe-div a b)
+              (if (= b 0) (error \"Division by zero\" a) (/ a b)))
+            (list
+              (guard (e ((error-object? e)
+                         (cons (error-object-message e) (error-object-irritants e))))
+                (safe-div 1 0))
+              (guard (e ((symbol? e) (list 'sym e))
+                        ((length e) => (lambda (n) (list 'len n)))
+                        (else 'other))
+                (raise (list 1 2)))
+              (with-exception-handler
+                (lambda (e) (+ e 1))
+                (lambda () (+ 10 (raise-continuable 1))))
+              (guard (e (#t (error-object-irritants e)))
+                (undefined-variable))
+              (guard (outer (#t (list 'outer outer)))
+                (guard (inner ((number? inner) 'inner))
+                  (raise 'oops))))";
+        let expected = "'((\"Division by zero\" 1) (len 2) 12 (undefined-variable) (outer oops))";
+        assert!(scm_equal(&run(code), &run(expected)));
+
+        // Returning from the handler of `raise` raises a secondary exception
+        let code = "
+            (guard (e ((error-object? e) (error-object-irritants e)))
+              (with-exception-handler
+                (lambda (e) 0)
+                (lambda () (raise 'boom))))";
+        assert!(scm_equal(&run(code), &run("'(boom)")));
+    }
+
+    #[test]
+    fn uncaught_error() {
+        let mut ctx = ScmExecContext::new();
+        let code = "
+            (define p (make-parameter 1))
+            (parameterize ((p 2)) (raise 'boom))
+            (p)";
+        let callables = Parser::new(code).parse().unwrap();
+        assert!(exec_callable(&mut ctx, &callables[0], &[]).is_ok());
+        match exec_callable(&mut ctx, &callables[1], &[]) {
+            Err(ScmError::Raised(obj)) => assert!(scm_equal(&obj, &run("'boom"))),
+            _ => panic!("Exception expected"),
+        }
+        // The context stays usable and the dynamic state is restored
+        let res = exec_callable(&mut ctx, &callables[2], &[]).unwrap();
+        assert!(scm_equal(&res, &int(1)));
+    }
+}
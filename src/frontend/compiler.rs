@@ -0,0 +1,945 @@
+use crate::{engine::*, frontend::Parser};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+// Expressions nested deeper are rejected, so that compiling them doesn't
+// overflow the Rust stack
+const MAX_NESTING: usize = 128;
+
+// Code, constant pool and nested lambdas of the template being compiled
+struct TemplateBuilder {
+    code: Vec<ScmOp>,
+    consts: Vec<ScmValue>,
+    templates: Vec<Rc<ScmProcTemplate>>,
+}
+
+impl TemplateBuilder {
+    fn new() -> Self {
+        Self {
+            code: Vec::new(),
+            consts: Vec::new(),
+            templates: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, op: ScmOp) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    fn emit_const(&mut self, val: ScmValue) {
+        self.consts.push(val);
+        self.emit(ScmOp::Const(self.consts.len() as u32 - 1));
+    }
+
+    // Points the jump at `pos` to the next emitted instruction
+    fn patch_jump(&mut self, pos: usize) {
+        let target = self.code.len() as u32;
+        self.code[pos] = match self.code[pos] {
+            ScmOp::Jump(_) => ScmOp::Jump(target),
+            ScmOp::JumpIfFalse(_) => ScmOp::JumpIfFalse(target),
+            op => panic!("Not a jump: {:?}", op),
+        };
+    }
+
+    fn build(
+        self,
+        name: Option<Symbol>,
+        params_cnt: usize,
+        has_rest: bool,
+        frame_size: usize,
+    ) -> Rc<ScmProcTemplate> {
+        Rc::new(ScmProcTemplate {
+            name,
+            params_cnt,
+            has_rest,
+            frame_size,
+            code: self.code,
+            consts: self.consts,
+            templates: self.templates,
+        })
+    }
+}
+
+fn expect_symbol(val: &ScmValue) -> Result<Symbol, ScmError> {
+    match val {
+        ScmValue::Symbol(sym) => Ok(*sym),
+        _ => Err(ScmError::new(
+            ScmErrorKind::Error,
+            String::from("Expected identifier"),
+            vec![val.clone()],
+        )),
+    }
+}
+
+// Items of the form, which must be a proper list
+fn list_items(form: &ScmValue) -> Result<Vec<ScmValue>, ScmError> {
+    match scm_is_list(form) {
+        true => Ok(scm_list_to_vec(form)),
+        false => Err(ScmError::new(
+            ScmErrorKind::Error,
+            String::from("Expected list"),
+            vec![form.clone()],
+        )),
+    }
+}
+
+fn scm_list(items: Vec<ScmValue>) -> ScmValue {
+    items
+        .into_iter()
+        .rev()
+        .fold(ScmValue::Nil, |list, item| ScmValue::cons(item, list))
+}
+
+fn keyword(name: &str) -> ScmValue {
+    ScmValue::Symbol(Symbol::intern(name))
+}
+
+fn primitive(primitive: ScmPrimitive) -> ScmValue {
+    ScmValue::Procedure(ScmCallable::Primitive(primitive))
+}
+
+fn lambda(formals: Vec<ScmValue>, body: Vec<ScmValue>) -> ScmValue {
+    ScmValue::cons(
+        keyword("lambda"),
+        ScmValue::cons(scm_list(formals), scm_list(body)),
+    )
+}
+
+// Clauses of `guard` as nested `if`s, `fallback` is used if no clause matches
+fn guard_clauses(clauses: &[ScmValue], fallback: ScmValue) -> ScmResult {
+    let Some((clause, rest)) = clauses.split_first() else {
+        return Ok(fallback);
+    };
+    let clause = list_items(clause)?;
+    scm_ensure!(!clause.is_empty(), "Guard clause cannot be empty");
+
+    Ok(match &clause[..] {
+        [ScmValue::Symbol(sym), body @ ..] if sym.as_str() == "else" => {
+            scm_ensure!(rest.is_empty(), "Else must be the last guard clause");
+            ScmValue::cons(keyword("begin"), scm_list(body.to_vec()))
+        }
+        // (test => receiver) and (test) use the value of the test
+        [test, ScmValue::Symbol(sym), receiver] if sym.as_str() == "=>" => {
+            let tmp = ScmValue::Symbol(Symbol::uninterned("guard"));
+            let branch = scm_list(vec![
+                keyword("if"),
+                tmp.clone(),
+                scm_list(vec![receiver.clone(), tmp.clone()]),
+                guard_clauses(rest, fallback)?,
+            ]);
+            scm_list(vec![
+                keyword("let"),
+                scm_list(vec![scm_list(vec![tmp, test.clone()])]),
+                branch,
+            ])
+        }
+        [test] => {
+            let tmp = ScmValue::Symbol(Symbol::uninterned("guard"));
+            let branch = scm_list(vec![
+                keyword("if"),
+                tmp.clone(),
+                tmp.clone(),
+                guard_clauses(rest, fallback)?,
+            ]);
+            scm_list(vec![
+                keyword("let"),
+                scm_list(vec![scm_list(vec![tmp, test.clone()])]),
+                branch,
+            ])
+        }
+        [test, body @ ..] => scm_list(vec![
+            keyword("if"),
+            test.clone(),
+            ScmValue::cons(keyword("begin"), scm_list(body.to_vec())),
+            guard_clauses(rest, fallback)?,
+        ]),
+        [] => unreachable!(),
+    })
+}
+
+// Definitions of (define-record-type <name> (ctor field ...) pred
+// (field accessor [modifier]) ...), as names and expressions creating the
+// values. The constructor can also be a single name, then it initializes all
+// fields.
+fn record_type_definitions(items: &[ScmValue]) -> Result<Vec<(Symbol, ScmValue)>, ScmError> {
+    scm_ensure!(
+        items.len() >= 4,
+        "Define-record-type requires name, constructor and predicate"
+    );
+    let type_name = expect_symbol(&items[1])?;
+
+    let specs = items[4..]
+        .iter()
+        .map(list_items)
+        .collect::<Result<Vec<_>, _>>()?;
+    let fields = specs
+        .iter()
+        .map(|spec| {
+            scm_ensure!(
+                spec.len() == 2 || spec.len() == 3,
+                "Record field must be (field accessor [modifier])"
+            );
+            expect_symbol(&spec[0])
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let field_index = |field: &ScmValue| {
+        let field = expect_symbol(field)?;
+        match fields.iter().position(|name| *name == field) {
+            Some(index) => Ok(index.into_scm()),
+            None => Err(scm_error!("Unknown record field: {}", field)),
+        }
+    };
+
+    let quote = |val: ScmValue| scm_list(vec![keyword("quote"), val]);
+    let field_names = fields
+        .iter()
+        .map(|field| ScmValue::Symbol(*field))
+        .collect();
+    let rtype = scm_list(vec![
+        ScmValue::Procedure(ScmCallable::Builtin(scm_make_record_type)),
+        quote(ScmValue::Symbol(type_name)),
+        quote(scm_list(field_names)),
+    ]);
+    let mut res = vec![(type_name, rtype)];
+
+    // (record-procedure 'kind <name> 'name field ...)
+    let record_proc = |kind: &str, name: Symbol, fields: Vec<ScmValue>| {
+        let mut call = vec![
+            ScmValue::Procedure(ScmCallable::Builtin(scm_record_procedure)),
+            quote(keyword(kind)),
+            ScmValue::Symbol(type_name),
+            quote(ScmValue::Symbol(name)),
+        ];
+        call.extend(fields);
+        (name, scm_list(call))
+    };
+
+    match &items[2] {
+        ScmValue::Symbol(name) => {
+            let all = (0..fields.len()).map(IntoScm::into_scm).collect();
+            res.push(record_proc("constructor", *name, all));
+        }
+        ScmValue::Bool(false) => (),
+        spec => {
+            let spec = list_items(spec)?;
+            scm_ensure!(!spec.is_empty(), "Record constructor spec cannot be empty");
+            let ctor_fields = spec[1..]
+                .iter()
+                .map(field_index)
+                .collect::<Result<Vec<_>, _>>()?;
+            res.push(record_proc(
+                "constructor",
+                expect_symbol(&spec[0])?,
+                ctor_fields,
+            ));
+        }
+    }
+
+    res.push(record_proc(
+        "predicate",
+        expect_symbol(&items[3])?,
+        Vec::new(),
+    ));
+
+    for spec in specs.iter() {
+        let field = field_index(&spec[0])?;
+        res.push(record_proc(
+            "accessor",
+            expect_symbol(&spec[1])?,
+            vec![field.clone()],
+        ));
+        if let Some(name) = spec.get(2) {
+            res.push(record_proc("modifier", expect_symbol(name)?, vec![field]));
+        }
+    }
+
+    Ok(res)
+}
+
+// Returns params and the name of the rest param, if any
+fn parse_formals(formals: &ScmValue) -> Result<(Vec<Symbol>, Option<Symbol>), ScmError> {
+    let mut params = Vec::new();
+    let mut cur = formals.clone();
+    loop {
+        match cur {
+            ScmValue::Nil => return Ok((params, None)),
+            ScmValue::Symbol(rest) => return Ok((params, Some(rest))),
+            ScmValue::DotPair(pair) => {
+                params.push(expect_symbol(&pair.borrow().car)?);
+                cur = pair.borrow().cdr.clone();
+            }
+            _ => return Err(scm_error!("Formals expected")),
+        }
+    }
+}
+
+pub(super) struct Compiler {
+    scopes: LexicalScopes,
+    // Bindings of the immutable environment the code is evaluated in. Free
+    // variables are compiled into constants holding their values or into
+    // references to the globals of library variables, other globals of the
+    // context are not visible.
+    bindings: Option<Rc<HashMap<Symbol, ScmBinding>>>,
+    // Files being compiled, `include` paths are relative to the last one
+    pub(super) sources: Vec<PathBuf>,
+    // Top-level definitions of the library body, stored in globals under
+    // uninterned names, so they don't clash with definitions of other code
+    pub(super) definitions: HashMap<Symbol, Symbol>,
+    // Files `include` may read
+    pub(super) fs_access: ScmFsAccess,
+    // Expressions being compiled, one inside another
+    nesting: usize,
+}
+
+impl Compiler {
+    pub(super) fn new() -> Self {
+        Self {
+            scopes: LexicalScopes::new(),
+            bindings: None,
+            sources: Vec::new(),
+            definitions: HashMap::new(),
+            fs_access: ScmFsAccess::unrestricted(),
+            nesting: 0,
+        }
+    }
+
+    pub(super) fn with_source(path: &Path) -> Self {
+        Self {
+            scopes: LexicalScopes::new(),
+            bindings: None,
+            sources: vec![path.to_path_buf()],
+            definitions: HashMap::new(),
+            fs_access: ScmFsAccess::unrestricted(),
+            nesting: 0,
+        }
+    }
+
+    pub(super) fn with_bindings(bindings: Rc<HashMap<Symbol, ScmBinding>>) -> Self {
+        Self {
+            scopes: LexicalScopes::new(),
+            bindings: Some(bindings),
+            sources: Vec::new(),
+            definitions: HashMap::new(),
+            fs_access: ScmFsAccess::unrestricted(),
+            nesting: 0,
+        }
+    }
+
+    // Names introduced by definitions at the top of a body (including
+    // definitions spliced by `begin` and `include`)
+    fn scan_definitions(
+        &mut self,
+        body: &[ScmValue],
+        res: &mut Vec<Symbol>,
+    ) -> Result<(), ScmError> {
+        for form in body.iter() {
+            let items = match form {
+                ScmValue::DotPair(_) if scm_is_list(form) => scm_list_to_vec(form),
+                _ => continue,
+            };
+            match items[0] {
+                ScmValue::Symbol(sym) if sym.as_str() == "define" && items.len() > 1 => {
+                    let name = match &items[1] {
+                        ScmValue::DotPair(pair) => expect_symbol(&pair.borrow().car)?,
+                        target => expect_symbol(target)?,
+                    };
+                    if !res.contains(&name) {
+                        res.push(name);
+                    }
+                }
+                ScmValue::Symbol(sym) if sym.as_str() == "begin" => {
+                    self.scan_definitions(&items[1..], res)?;
+                }
+                ScmValue::Symbol(sym) if sym.as_str() == "include" => {
+                    for (path, data) in self.read_includes(&items)? {
+                        self.sources.push(path);
+                        let scanned = self.scan_definitions(&data, res);
+                        self.sources.pop();
+                        scanned?;
+                    }
+                }
+                ScmValue::Symbol(sym) if sym.as_str() == "define-record-type" => {
+                    for (name, _) in record_type_definitions(&items)? {
+                        if !res.contains(&name) {
+                            res.push(name);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    // Paths and data of the files named by (include file ...)
+    fn read_includes(&self, items: &[ScmValue]) -> Result<Vec<(PathBuf, Vec<ScmValue>)>, ScmError> {
+        scm_ensure!(items.len() >= 2, "Include requires file names");
+        let dir = self.sources.last().and_then(|source| source.parent());
+        let mut res = Vec::new();
+        for name in items[1..].iter() {
+            let ScmValue::String(name) = name else {
+                return Err(scm_error!("Include requires argument of type String"));
+            };
+            let path = match dir {
+                Some(dir) => dir.join(&*name.borrow()),
+                None => PathBuf::from(&*name.borrow()),
+            };
+            self.fs_access.check(&path)?;
+            let code = fs::read_to_string(&path)
+                .map_err(|err| io_error(err, &path.display().to_string()))?;
+            let mut parser = Parser::new(&code);
+            let mut data = Vec::new();
+            while let Some(datum) = parser.read()? {
+                data.push(datum);
+            }
+            res.push((path, data));
+        }
+        Ok(res)
+    }
+
+    // Library body compiled against the imported bindings. Global names of
+    // its definitions are left in `definitions`.
+    pub(super) fn compile_library(
+        &mut self,
+        body: &[ScmValue],
+    ) -> Result<Vec<Rc<ScmProcTemplate>>, ScmError> {
+        let mut names = Vec::new();
+        self.scan_definitions(body, &mut names)?;
+        self.definitions = names
+            .into_iter()
+            .map(|name| (name, Symbol::uninterned(name.as_str())))
+            .collect();
+        body.iter()
+            .map(|form| self.compile_toplevel(form))
+            .collect()
+    }
+
+    // Top-level form becomes a procedure without params. Definitions on the
+    // top level are global.
+    pub(super) fn compile_toplevel(
+        &mut self,
+        expr: &ScmValue,
+    ) -> Result<Rc<ScmProcTemplate>, ScmError> {
+        let mut builder = TemplateBuilder::new();
+        self.compile_expr(expr, &mut builder, true)?;
+        builder.emit(ScmOp::Return);
+        Ok(builder.build(None, 0, false, 0))
+    }
+
+    fn compile_expr(
+        &mut self,
+        expr: &ScmValue,
+        b: &mut TemplateBuilder,
+        tail: bool,
+    ) -> Result<(), ScmError> {
+        match expr {
+            ScmValue::Symbol(name) => self.compile_ref(*name, b)?,
+            ScmValue::DotPair(_) => {
+                scm_ensure!(
+                    self.nesting < MAX_NESTING,
+                    "Expression is nested too deeply"
+                );
+                self.nesting += 1;
+                let res = self.compile_form(expr, b, tail);
+                self.nesting -= 1;
+                res?;
+            }
+            _ => b.emit_const(expr.clone()),
+        }
+        Ok(())
+    }
+
+    fn compile_form(
+        &mut self,
+        expr: &ScmValue,
+        b: &mut TemplateBuilder,
+        tail: bool,
+    ) -> Result<(), ScmError> {
+        let items = list_items(expr)?;
+        let special = match items[0] {
+            ScmValue::Symbol(name) if self.scopes.resolve(name).is_none() => {
+                self.compile_special(name.as_str(), &items, b, tail)?
+            }
+            _ => false,
+        };
+        if !special {
+            self.compile_call(&items, b, tail)?;
+        }
+        Ok(())
+    }
+
+    fn compile_ref(&mut self, name: Symbol, b: &mut TemplateBuilder) -> Result<(), ScmError> {
+        match (self.scopes.resolve(name), &self.bindings) {
+            (Some((depth, index)), _) => {
+                b.emit(ScmOp::LocalRef(depth as u16, index as u16));
+            }
+            (None, _) if self.definitions.contains_key(&name) => {
+                b.emit(ScmOp::GlobalRef(self.definitions[&name]));
+            }
+            (None, None) => {
+                b.emit(ScmOp::GlobalRef(name));
+            }
+            (None, Some(bindings)) => match bindings.get(&name) {
+                Some(ScmBinding::Value(val)) => b.emit_const(val.clone()),
+                Some(ScmBinding::Global(global)) => {
+                    b.emit(ScmOp::GlobalRef(*global));
+                }
+                None => {
+                    return Err(ScmError::new(
+                        ScmErrorKind::Error,
+                        String::from("Unbound variable"),
+                        vec![ScmValue::Symbol(name)],
+                    ))
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn compile_set(
+        &mut self,
+        name: Symbol,
+        b: &mut TemplateBuilder,
+        define: bool,
+    ) -> Result<(), ScmError> {
+        match self.scopes.resolve(name) {
+            Some((depth, index)) => {
+                b.emit(ScmOp::LocalSet(depth as u16, index as u16));
+            }
+            None => {
+                let global = match self.definitions.get(&name) {
+                    Some(global) => *global,
+                    None => {
+                        scm_ensure!(
+                            self.bindings.is_none(),
+                            "Cannot assign {} in immutable environment",
+                            name
+                        );
+                        name
+                    }
+                };
+                b.emit(match define {
+                    true => ScmOp::GlobalDefine(global),
+                    false => ScmOp::GlobalSet(global),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Returns false if `name` is not a special form
+    fn compile_special(
+        &mut self,
+        name: &str,
+        items: &[ScmValue],
+        b: &mut TemplateBuilder,
+        tail: bool,
+    ) -> Result<bool, ScmError> {
+        match name {
+            "quote" => {
+                scm_ensure!(items.len() == 2, "Quote requires exactly 1 argument");
+                b.emit_const(items[1].clone());
+            }
+
+            "if" => {
+                scm_ensure!(
+                    items.len() == 3 || items.len() == 4,
+                    "If requires 2 or 3 arguments"
+                );
+                self.compile_expr(&items[1], b, false)?;
+                let false_jump = b.emit(ScmOp::JumpIfFalse(0));
+                self.compile_expr(&items[2], b, tail)?;
+                let end_jump = b.emit(ScmOp::Jump(0));
+                b.patch_jump(false_jump);
+                match items.get(3) {
+                    Some(expr) => self.compile_expr(expr, b, tail)?,
+                    None => b.emit_const(ScmValue::Nil),
+                }
+                b.patch_jump(end_jump);
+            }
+
+            "define" => {
+                scm_ensure!(items.len() >= 2, "Define requires arguments");
+                match &items[1] {
+                    ScmValue::DotPair(pair) => {
+                        let name = expect_symbol(&pair.borrow().car)?;
+                        let formals = pair.borrow().cdr.clone();
+                        self.compile_lambda(Some(name), &formals, &items[2..], b)?;
+                        self.compile_set(name, b, true)?;
+                    }
+                    target => {
+                        scm_ensure!(items.len() == 3, "Define requires exactly 1 value");
+                        self.compile_expr(&items[2], b, false)?;
+                        self.compile_set(expect_symbol(target)?, b, true)?;
+                    }
+                }
+            }
+
+            "set!" => {
+                scm_ensure!(items.len() == 3, "Set! requires exactly 2 arguments");
+                self.compile_expr(&items[2], b, false)?;
+                self.compile_set(expect_symbol(&items[1])?, b, false)?;
+            }
+
+            "lambda" => {
+                scm_ensure!(items.len() >= 3, "Lambda requires formals and body");
+                self.compile_lambda(None, &items[1], &items[2..], b)?;
+            }
+
+            "begin" => {
+                if items.len() == 1 {
+                    b.emit_const(ScmValue::Nil);
+                }
+                self.compile_sequence(&items[1..], b, tail)?;
+            }
+
+            "include" => {
+                // Contents of the files spliced as with `begin`
+                let files = self.read_includes(items)?;
+                for (i, (path, data)) in files.into_iter().enumerate() {
+                    let last = i + 1 == items.len() - 1;
+                    let expr = ScmValue::cons(keyword("begin"), scm_list(data));
+                    self.sources.push(path);
+                    let res = self.compile_expr(&expr, b, tail && last);
+                    self.sources.pop();
+                    res?;
+                    if !last {
+                        b.emit(ScmOp::Pop);
+                    }
+                }
+            }
+
+            "define-library" | "import" => {
+                // Both are handled at runtime, by a call with the quoted form
+                scm_ensure!(
+                    self.bindings.is_none(),
+                    "{} is not allowed in immutable environment",
+                    name
+                );
+                let handler: fn(&mut ScmExecContext, &[ScmValue]) -> ScmResult = match name {
+                    "import" => scm_import,
+                    _ => scm_define_library,
+                };
+                b.emit_const(ScmValue::Procedure(ScmCallable::Builtin(handler)));
+                b.emit_const(scm_list(items.to_vec()));
+                b.emit(ScmOp::Call(1));
+            }
+
+            "let" => self.compile_let(items, b, tail)?,
+
+            "parameterize" => {
+                // (parameterize ((param value) ...) body ...) is a call of the
+                // parameterize primitive: (thunk param1 value1 ...)
+                scm_ensure!(items.len() >= 3, "Parameterize requires bindings and body");
+                let (params, values) = Self::let_bindings(&items[1])?;
+                b.emit_const(ScmValue::Procedure(ScmCallable::Primitive(
+                    ScmPrimitive::Parameterize,
+                )));
+                self.compile_lambda(None, &ScmValue::Nil, &items[2..], b)?;
+                for (param, value) in params.iter().zip(values.iter()) {
+                    self.compile_expr(param, b, false)?;
+                    self.compile_expr(value, b, false)?;
+                }
+                b.emit(ScmOp::Call(2 * params.len() as u32 + 1));
+            }
+
+            "define-record-type" => {
+                let defines = record_type_definitions(items)?
+                    .into_iter()
+                    .map(|(name, val)| {
+                        scm_list(vec![keyword("define"), ScmValue::Symbol(name), val])
+                    })
+                    .collect();
+                let expr = ScmValue::cons(keyword("begin"), scm_list(defines));
+                self.compile_expr(&expr, b, tail)?;
+            }
+
+            "guard" => self.compile_guard(items, b, tail)?,
+
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn compile_let(
+        &mut self,
+        items: &[ScmValue],
+        b: &mut TemplateBuilder,
+        tail: bool,
+    ) -> Result<(), ScmError> {
+        scm_ensure!(items.len() >= 3, "Let requires bindings and body");
+        let expr = match &items[1] {
+            // Named let: (let loop ((var init) ...) body ...)
+            ScmValue::Symbol(loop_name) => {
+                let (vars, inits) = Self::let_bindings(&items[2])?;
+                let tmps: Vec<ScmValue> = vars
+                    .iter()
+                    .map(|_| ScmValue::Symbol(Symbol::uninterned("let")))
+                    .collect();
+                let loop_proc = ScmValue::cons(
+                    ScmValue::Symbol(Symbol::intern("lambda")),
+                    ScmValue::cons(scm_list(vars), scm_list(items[3..].to_vec())),
+                );
+                let define = scm_list(vec![
+                    ScmValue::Symbol(Symbol::intern("define")),
+                    ScmValue::Symbol(*loop_name),
+                    loop_proc,
+                ]);
+                let call = ScmValue::cons(ScmValue::Symbol(*loop_name), scm_list(tmps.clone()));
+                let outer = scm_list(vec![
+                    ScmValue::Symbol(Symbol::intern("lambda")),
+                    scm_list(tmps),
+                    define,
+                    call,
+                ]);
+                ScmValue::cons(outer, scm_list(inits))
+            }
+            bindings => {
+                let (vars, inits) = Self::let_bindings(bindings)?;
+                let proc = ScmValue::cons(
+                    ScmValue::Symbol(Symbol::intern("lambda")),
+                    ScmValue::cons(scm_list(vars), scm_list(items[2..].to_vec())),
+                );
+                ScmValue::cons(proc, scm_list(inits))
+            }
+        };
+        self.compile_expr(&expr, b, tail)
+    }
+
+    fn compile_guard(
+        &mut self,
+        items: &[ScmValue],
+        b: &mut TemplateBuilder,
+        tail: bool,
+    ) -> Result<(), ScmError> {
+        // (guard (var clause ...) body ...) evaluates the body with a
+        // handler, which escapes to the guard and evaluates clauses
+        // there. If no clause matches, the condition is re-raised
+        // with `raise-continuable` in the dynamic environment of the
+        // original raise:
+        //
+        // ((call/ec (lambda (guard-k)
+        //    (with-exception-handler
+        //      (lambda (condition)
+        //        ((call/cc (lambda (handler-k)
+        //           (guard-k (lambda ()
+        //             (let ((var condition))
+        //               <clauses, else
+        //                (handler-k (lambda ()
+        //                  (raise-continuable condition))))))))))
+        //      (lambda ()
+        //        (let ((res (let () body ...)))
+        //          (lambda () res))))))))
+        scm_ensure!(items.len() >= 3, "Guard requires clauses and body");
+        let spec = list_items(&items[1])?;
+        scm_ensure!(!spec.is_empty(), "Guard requires a variable");
+        let var = ScmValue::Symbol(expect_symbol(&spec[0])?);
+
+        let guard_k = ScmValue::Symbol(Symbol::uninterned("guard-k"));
+        let handler_k = ScmValue::Symbol(Symbol::uninterned("handler-k"));
+        let condition = ScmValue::Symbol(Symbol::uninterned("condition"));
+        let res = ScmValue::Symbol(Symbol::uninterned("res"));
+
+        let reraise = lambda(
+            vec![],
+            vec![scm_list(vec![
+                primitive(ScmPrimitive::RaiseContinuable),
+                condition.clone(),
+            ])],
+        );
+        let clauses = guard_clauses(&spec[1..], scm_list(vec![handler_k.clone(), reraise]))?;
+        let bind_var = scm_list(vec![
+            keyword("let"),
+            scm_list(vec![scm_list(vec![var, condition.clone()])]),
+            clauses,
+        ]);
+        let to_guard = scm_list(vec![guard_k.clone(), lambda(vec![], vec![bind_var])]);
+        let handler = lambda(
+            vec![condition],
+            vec![scm_list(vec![scm_list(vec![
+                primitive(ScmPrimitive::CallCC),
+                lambda(vec![handler_k], vec![to_guard]),
+            ])])],
+        );
+
+        let body = ScmValue::cons(
+            keyword("let"),
+            ScmValue::cons(ScmValue::Nil, scm_list(items[2..].to_vec())),
+        );
+        let thunk = lambda(
+            vec![],
+            vec![scm_list(vec![
+                keyword("let"),
+                scm_list(vec![scm_list(vec![res.clone(), body])]),
+                lambda(vec![], vec![res]),
+            ])],
+        );
+
+        let expr = scm_list(vec![scm_list(vec![
+            primitive(ScmPrimitive::CallEC),
+            lambda(
+                vec![guard_k],
+                vec![scm_list(vec![
+                    primitive(ScmPrimitive::WithExceptionHandler),
+                    handler,
+                    thunk,
+                ])],
+            ),
+        ])]);
+        self.compile_expr(&expr, b, tail)
+    }
+
+    fn let_bindings(bindings: &ScmValue) -> Result<(Vec<ScmValue>, Vec<ScmValue>), ScmError> {
+        let mut vars = Vec::new();
+        let mut inits = Vec::new();
+        for binding in list_items(bindings)?.iter() {
+            let binding = list_items(binding)?;
+            scm_ensure!(binding.len() == 2, "Let binding must be (name value)");
+            vars.push(binding[0].clone());
+            inits.push(binding[1].clone());
+        }
+        Ok((vars, inits))
+    }
+
+    fn compile_sequence(
+        &mut self,
+        body: &[ScmValue],
+        b: &mut TemplateBuilder,
+        tail: bool,
+    ) -> Result<(), ScmError> {
+        for (i, expr) in body.iter().enumerate() {
+            let last = i + 1 == body.len();
+            self.compile_expr(expr, b, tail && last)?;
+            if !last {
+                b.emit(ScmOp::Pop);
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_lambda(
+        &mut self,
+        name: Option<Symbol>,
+        formals: &ScmValue,
+        body: &[ScmValue],
+        b: &mut TemplateBuilder,
+    ) -> Result<(), ScmError> {
+        scm_ensure!(!body.is_empty(), "Procedure body cannot be empty");
+
+        let (mut vars, rest) = parse_formals(formals)?;
+        vars.extend(rest);
+        let params_cnt = vars.len();
+        self.scan_definitions(body, &mut vars)?;
+        scm_ensure!(vars.len() <= u16::MAX as usize, "Too many local variables");
+        let frame_size = vars.len();
+
+        let mut builder = TemplateBuilder::new();
+        self.scopes.push(vars);
+        let res = self.compile_sequence(body, &mut builder, true);
+        self.scopes.pop();
+        res?;
+        builder.emit(ScmOp::Return);
+
+        let template = builder.build(name, params_cnt, rest.is_some(), frame_size);
+        b.templates.push(template);
+        b.emit(ScmOp::Closure(b.templates.len() as u32 - 1));
+        Ok(())
+    }
+
+    fn compile_call(
+        &mut self,
+        items: &[ScmValue],
+        b: &mut TemplateBuilder,
+        tail: bool,
+    ) -> Result<(), ScmError> {
+        for item in items.iter() {
+            self.compile_expr(item, b, false)?;
+        }
+        let args_cnt = items.len() as u32 - 1;
+        if tail {
+            b.emit(ScmOp::TailCall(args_cnt));
+        } else {
+            b.emit(ScmOp::Call(args_cnt));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ScmOp::*;
+
+    fn compile(code: &str) -> Rc<ScmProcTemplate> {
+        let datum = Parser::new(code).read().unwrap().unwrap();
+        Compiler::new().compile_toplevel(&datum).unwrap()
+    }
+
+    fn global(name: &str) -> ScmOp {
+        GlobalRef(Symbol::intern(name))
+    }
+
+    #[test]
+    fn local_refs() {
+        // Locals are found by the depth of their frame and the index in it,
+        // internal definitions follow the params
+        let outer = compile("(lambda (a b) (define c 1) (lambda (d) (list d b c)))");
+        let outer = &outer.templates[0];
+        assert_eq!((outer.params_cnt, outer.frame_size), (2, 3));
+        let inner = &outer.templates[0];
+        let expected = [
+            global("list"),
+            LocalRef(0, 0),
+            LocalRef(1, 1),
+            LocalRef(1, 2),
+            TailCall(3),
+            Return,
+        ];
+        assert_eq!(inner.code, expected);
+    }
+
+    #[test]
+    fn jumps() {
+        // Jumps point past the branch they skip, also in nested `if`
+        let expected = [
+            global("a"),
+            JumpIfFalse(8),
+            global("b"),
+            JumpIfFalse(6),
+            Const(0),
+            Jump(7),
+            Const(1),
+            Jump(9),
+            Const(2),
+            Return,
+        ];
+        assert_eq!(compile("(if a (if b 1 2) 3)").code, expected);
+    }
+
+    #[test]
+    fn tail_calls() {
+        // Only calls whose result is returned are tail calls
+        let proc = compile("(lambda () (if a (f (g)) (begin (g) (h))))");
+        let expected = [
+            global("a"),
+            JumpIfFalse(7),
+            global("f"),
+            global("g"),
+            Call(0),
+            TailCall(1),
+            Jump(12),
+            global("g"),
+            Call(0),
+            Pop,
+            global("h"),
+            TailCall(0),
+            Return,
+        ];
+        assert_eq!(proc.templates[0].code, expected);
+    }
+}
@@ -1,5 +1,10 @@
 use crate::engine::scm_core::ScmValue;
+use crate::engine::scm_error::{ScmError, ScmErrorKind};
+use crate::engine::scm_port::ScmPort;
+use crate::engine::symbol::Symbol;
 use crate::engine::typed_num::TypedNum;
+use std::collections::VecDeque;
+use std::iter::Peekable;
 use std::vec::IntoIter;
 
 #[derive(Debug, Clone)]
@@ -7,66 +12,149 @@ pub enum Token {
     Identifier(String),
     Value(ScmValue),
     OpenParen,
+    OpenVector,
     ClosingParen,
+    Quote,
     Sentiel,
 }
 
-pub(super) struct Lexer {
-    chars: IntoIter<char>,
-    tokens: Vec<Token>,
+// Source of the lexer: code in a string or an input port. The current char
+// is only peeked, so a port keeps the text after the datum unread.
+pub(super) trait CharSource {
+    fn peek(&mut self) -> Result<Option<char>, ScmError>;
+    fn next(&mut self) -> Result<Option<char>, ScmError>;
+}
+
+impl CharSource for Peekable<IntoIter<char>> {
+    fn peek(&mut self) -> Result<Option<char>, ScmError> {
+        Ok(Peekable::peek(self).copied())
+    }
+
+    fn next(&mut self) -> Result<Option<char>, ScmError> {
+        Ok(Iterator::next(self))
+    }
+}
+
+// Input port, calling `tick` for each char read from it
+struct PortSource<'a, F> {
+    port: &'a ScmPort,
+    tick: F,
+}
+
+impl<F: FnMut() -> Result<(), ScmError>> CharSource for PortSource<'_, F> {
+    fn peek(&mut self) -> Result<Option<char>, ScmError> {
+        self.port.peek_char()
+    }
+
+    fn next(&mut self) -> Result<Option<char>, ScmError> {
+        (self.tick)()?;
+        self.port.read_char()
+    }
+}
+
+pub(super) struct Lexer<'a> {
+    source: Box<dyn CharSource + 'a>,
+    tokens: VecDeque<Token>,
     current: Option<char>,
     line: u32,
     column: u32,
 }
 
-impl Lexer {
+impl<'a> Lexer<'a> {
     pub(super) fn new(s: &str) -> Self {
+        let chars = s.chars().collect::<Vec<char>>().into_iter().peekable();
+        Self::from_source(Box::new(chars))
+    }
+
+    pub(super) fn from_port(
+        port: &'a ScmPort,
+        tick: impl FnMut() -> Result<(), ScmError> + 'a,
+    ) -> Self {
+        Self::from_source(Box::new(PortSource { port, tick }))
+    }
+
+    fn from_source(source: Box<dyn CharSource + 'a>) -> Self {
         Self {
-            chars: s.chars().collect::<Vec<char>>().into_iter(),
-            tokens: Vec::new(),
+            source,
+            tokens: VecDeque::new(),
             current: None,
             line: 1,
             column: 1,
         }
     }
 
-    pub(super) fn run(&mut self) -> Vec<Token> {
-        self.increment();
-
-        while let Some(c) = self.current {
+    // Tokens are produced on demand, so reading a datum from a port doesn't
+    // consume the rest of its input
+    pub(super) fn next_token(&mut self) -> Result<Token, ScmError> {
+        while self.tokens.is_empty() {
+            self.current = self.source.peek()?;
+            let Some(c) = self.current else {
+                return Ok(Token::Sentiel);
+            };
             match c {
-                ' ' | '\n' => {
-                    self.increment();
+                ' ' | '\n' | '\t' | '\r' => {
+                    self.increment()?;
                 }
                 '(' => {
-                    self.tokens.push(Token::OpenParen);
-                    self.increment();
+                    self.tokens.push_back(Token::OpenParen);
+                    self.increment()?;
                 }
                 ')' => {
-                    self.tokens.push(Token::ClosingParen);
-                    self.increment();
+                    self.tokens.push_back(Token::ClosingParen);
+                    self.increment()?;
+                }
+                '\'' => {
+                    self.tokens.push_back(Token::Quote);
+                    self.increment()?;
                 }
                 '#' => {
-                    self.increment();
+                    self.increment()?;
                     match self.current {
-                        Some('\'') => self.parse_char(),
-                        _ => self.parse_boolean(),
+                        Some('\'') | Some('\\') => self.parse_char()?,
+                        Some('(') => {
+                            self.tokens.push_back(Token::OpenVector);
+                            self.increment()?;
+                        }
+                        Some(
+                            'x' | 'X' | 'b' | 'B' | 'o' | 'O' | 'd' | 'D' | 'e' | 'E' | 'i' | 'I',
+                        ) => self.parse_prefixed_number()?,
+                        _ => self.parse_boolean()?,
                     }
                 }
-                '0'..='9' => self.parse_number(),
-                '\"' => self.parse_string(),
-                '[' | ']' | '{' | '}' | '|' | '\\' => {
-                    panic!("Unexpected {} {}", self.line, self.column);
+                '\"' => self.parse_string()?,
+                '|' => self.parse_bar_symbol()?,
+                '[' | ']' | '{' | '}' | '\\' => {
+                    return Err(self.error(&format!("Unexpected {}", c)));
                 }
-                _ => self.parse_identifier(),
+                _ => self.parse_atom()?,
+            }
+        }
+
+        Ok(self.tokens.pop_front().unwrap())
+    }
+
+    #[cfg(test)]
+    pub(super) fn run(&mut self) -> Result<Vec<Token>, ScmError> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            let end = matches!(token, Token::Sentiel);
+            tokens.push(token);
+            if end {
+                return Ok(tokens);
             }
         }
+    }
 
-        self.tokens.push(Token::Sentiel);
-        std::mem::take(&mut self.tokens)
+    fn error(&self, message: &str) -> ScmError {
+        ScmError::new(
+            ScmErrorKind::Read,
+            format!("{} on {} {}", message, self.line, self.column),
+            Vec::new(),
+        )
     }
 
-    fn increment(&mut self) {
+    fn increment(&mut self) -> Result<(), ScmError> {
         if let Some('\n') = self.current {
             self.line += 1;
             self.column = 1;
@@ -74,105 +162,207 @@ impl Lexer {
             self.column += 1;
         }
 
-        self.current = self.chars.next();
+        self.source.next()?;
+        self.current = self.source.peek()?;
+        Ok(())
     }
 
-    fn parse_number(&mut self) {
-        let mut s = String::new();
-
+    // Numbers and identifiers: anything that parses as a number is one
+    // Chars up to the next delimiter, appended to `s`
+    fn read_atom(&mut self, mut s: String) -> Result<String, ScmError> {
         while let Some(c) = self.current {
             match c {
-                '0'..='9' => {
+                ' ' | '(' | ')' | '\n' | '\t' | '\r' => break,
+                _ => {
                     s.push(c);
-                    self.increment();
+                    self.increment()?;
                 }
-                _ => break,
             }
         }
+        Ok(s)
+    }
 
-        let s = s.parse().unwrap();
-        self.tokens
-            .push(Token::Value(ScmValue::Number(TypedNum::Integer(s))));
+    fn parse_atom(&mut self) -> Result<(), ScmError> {
+        let s = self.read_atom(String::new())?;
 
-        self.parse_delimiter();
+        match TypedNum::parse(&s, 10) {
+            Some(num) => self.tokens.push_back(Token::Value(ScmValue::Number(num))),
+            None => self.tokens.push_back(Token::Identifier(s)),
+        }
+
+        self.parse_delimiter()
     }
 
-    fn parse_identifier(&mut self) {
-        let mut s = String::new();
+    // #xff, #e1e3 and other numbers with prefixes, the '#' is already read
+    fn parse_prefixed_number(&mut self) -> Result<(), ScmError> {
+        let s = self.read_atom(String::from("#"))?;
 
-        while let Some(c) = self.current {
-            match c {
-                ' ' | '(' | ')' | '\n' => break,
-                _ => {
-                    s.push(c);
-                    self.increment();
-                }
-            }
+        match TypedNum::parse_prefixed(&s, 10) {
+            Some(num) => self.tokens.push_back(Token::Value(ScmValue::Number(num))),
+            None => return Err(self.error(&format!("Invalid number {}", s))),
         }
 
-        self.tokens.push(Token::Identifier(s));
-
-        self.parse_delimiter();
+        self.parse_delimiter()
     }
 
-    fn parse_boolean(&mut self) {
+    fn parse_boolean(&mut self) -> Result<(), ScmError> {
         let val = match self.current {
             Some('t') => true,
             Some('f') => false,
-            _ => panic!("Lexer error bool type on {} {}", self.line, self.column),
+            _ => return Err(self.error("Lexer error bool type")),
         };
 
-        self.increment();
+        self.increment()?;
 
-        self.tokens.push(Token::Value(ScmValue::Bool(val)));
+        self.tokens.push_back(Token::Value(ScmValue::Bool(val)));
 
-        self.parse_delimiter();
+        self.parse_delimiter()
     }
 
-    fn parse_string(&mut self) {
-        self.increment();
+    fn parse_string(&mut self) -> Result<(), ScmError> {
+        self.increment()?;
 
         let mut s = String::new();
 
-        while let Some(c) = self.current {
-            match c {
-                '\"' => {
-                    self.increment();
+        loop {
+            match self.current {
+                Some('\"') => {
+                    self.increment()?;
                     break;
                 }
-                _ => {
+                Some('\\') => {
+                    self.increment()?;
+                    let c = self.parse_string_escape()?;
                     s.push(c);
-                    self.increment();
                 }
+                Some(c) => {
+                    s.push(c);
+                    self.increment()?;
+                }
+                None => return Err(self.error("Unterminated string")),
             }
         }
 
-        self.tokens.push(Token::Value(ScmValue::String(s)));
+        self.tokens
+            .push_back(Token::Value(ScmValue::new_string(&s)));
 
-        self.parse_delimiter();
+        self.parse_delimiter()
     }
 
-    fn parse_char(&mut self) {
-        self.increment();
+    // |hello world| is a symbol with any chars, escaped as in strings
+    fn parse_bar_symbol(&mut self) -> Result<(), ScmError> {
+        self.increment()?;
 
-        match self.current {
-            Some(c @ 'a'..='z') => self.tokens.push(Token::Value(ScmValue::Char(c))),
-            _ => panic!("Lexer error on {} {}", self.line, self.column),
+        let mut s = String::new();
+
+        loop {
+            match self.current {
+                Some('|') => {
+                    self.increment()?;
+                    break;
+                }
+                Some('\\') => {
+                    self.increment()?;
+                    let c = self.parse_string_escape()?;
+                    s.push(c);
+                }
+                Some(c) => {
+                    s.push(c);
+                    self.increment()?;
+                }
+                None => return Err(self.error("Unterminated symbol")),
+            }
         }
 
-        self.increment();
+        self.tokens
+            .push_back(Token::Value(ScmValue::Symbol(Symbol::intern(&s))));
+
+        self.parse_delimiter()
+    }
+
+    // Char after the backslash: \n, \t, \r, \x3bb; or the char itself
+    fn parse_string_escape(&mut self) -> Result<char, ScmError> {
+        let c = match self.current {
+            Some(c) => c,
+            None => return Err(self.error("Unterminated string")),
+        };
+        self.increment()?;
+        Ok(match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'a' => '\u{7}',
+            'x' => {
+                let mut hex = String::new();
+                while let Some(c) = self.current {
+                    self.increment()?;
+                    if c == ';' {
+                        break;
+                    }
+                    hex.push(c);
+                }
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error("Invalid char code"))?
+            }
+            _ => c,
+        })
+    }
+
+    // #\a, #\space, #\x3bb and the older #'a form
+    fn parse_char(&mut self) -> Result<(), ScmError> {
+        self.increment()?;
+
+        let first = match self.current {
+            Some(c) => c,
+            None => return Err(self.error("Missing character")),
+        };
+        self.increment()?;
+
+        let mut name = String::from(first);
+        while let Some(c) = self.current {
+            match c {
+                ' ' | '(' | ')' | '\n' | '\t' | '\r' => break,
+                _ => {
+                    name.push(c);
+                    self.increment()?;
+                }
+            }
+        }
+
+        let c = match name.as_str() {
+            _ if name.chars().count() == 1 => first,
+            "space" => ' ',
+            "newline" => '\n',
+            "tab" => '\t',
+            "return" => '\r',
+            "null" | "nul" => '\0',
+            "alarm" => '\u{7}',
+            "backspace" => '\u{8}',
+            "escape" => '\u{1b}',
+            "delete" => '\u{7f}',
+            _ => match name.strip_prefix('x') {
+                Some(hex) => u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error("Invalid char code"))?,
+                None => return Err(self.error(&format!("Unknown character name {}", name))),
+            },
+        };
+        self.tokens.push_back(Token::Value(ScmValue::Char(c)));
 
-        self.parse_delimiter();
+        self.parse_delimiter()
     }
 
-    fn parse_delimiter(&mut self) {
+    fn parse_delimiter(&mut self) -> Result<(), ScmError> {
         match self.current {
             Some(')') => {
-                self.tokens.push(Token::ClosingParen);
-                self.increment();
+                self.tokens.push_back(Token::ClosingParen);
+                self.increment()
             }
-            Some(' ') | Some('\n') | None => (),
-            Some(_) => panic!("Lexer error on {} {}", self.line, self.column),
+            Some(' ') | Some('\n') | Some('\t') | Some('\r') | None => Ok(()),
+            Some(_) => Err(self.error("Lexer error")),
         }
     }
 }
@@ -187,7 +377,7 @@ mod tests {
         let contents = fs::read_to_string("test.scm").expect("No such file");
         let mut l = Lexer::new(&contents);
 
-        let v = l.run();
+        let v = l.run().unwrap();
 
         println!("{:#?}", v);
     }
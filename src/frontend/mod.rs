@@ -1,4 +1,5 @@
+mod compiler;
 mod lex;
 mod parser;
 
-pub use parser::Parser;
+pub use parser::{compile_datum, compile_library, load_file, parse_file, read_datum, Parser};
@@ -1,268 +1,239 @@
-use std::vec;
+use std::{collections::HashMap, fs, path::Path, rc::Rc};
 
-use crate::{engine::*, frontend::lex::*};
+use crate::{
+    engine::*,
+    frontend::{compiler::Compiler, lex::*},
+};
 
-pub struct Parser {
-    tokens: Vec<Token>,
-    idx: usize,
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<Token>,
 }
 
-impl Parser {
-    fn peek(&self) -> Token {
-        self.tokens[self.idx].clone()
-    }
-
-    fn next(&mut self) -> Token {
-        if let Token::Sentiel = self.tokens[self.idx] {
-            return Token::Sentiel;
-        };
-        let res = self.tokens[self.idx].clone();
-        self.idx += 1;
-        res
-    }
+// List, vector or quotation the parser is reading
+enum Open {
+    List(Vec<ScmValue>),
+    Tail(Vec<ScmValue>), // Items of a dotted list, before its tail
+    Vector(Vec<ScmValue>),
+    Quote,
+}
 
-    // Parsing
+fn scm_list(items: Vec<ScmValue>, tail: ScmValue) -> ScmValue {
+    items
+        .into_iter()
+        .rev()
+        .fold(tail, |list, item| ScmValue::cons(item, list))
+}
 
-    fn parse_value(&mut self, instr: &mut Vec<ScmProcUnit>) {
-        match self.peek() {
-            Token::Identifier(ident) => {
-                instr.push(ScmProcUnit::Variable(ident));
-                self.next();
-            }
-            Token::Value(val) => {
-                instr.push(ScmProcUnit::Val(val));
-                self.next();
-            }
-            Token::OpenParen => {
-                self.parse_expr(instr);
-            }
-            _ => {
-                panic!("Unexpected symbol");
-            }
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<Token, ScmError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lexer.next_token(),
         }
     }
 
-    fn gen_define(&mut self, instr: &mut Vec<ScmProcUnit>) {
-        match self.peek() {
-            Token::Identifier(ident) => {
-                instr.push(ScmProcUnit::Assign(ident));
-                self.next();
+    fn peek(&mut self) -> Result<Token, ScmError> {
+        let token = self.next()?;
+        self.peeked = Some(token.clone());
+        Ok(token)
+    }
 
-                // Get one value. If there are others, error will be raised later.
-                self.parse_value(instr);
-            }
+    fn error(message: &str) -> ScmError {
+        ScmError::new(ScmErrorKind::Read, String::from(message), Vec::new())
+    }
 
-            Token::OpenParen => {
-                self.next();
+    // Parsing. Code is read as data first and then compiled. Lists and
+    // vectors being read are kept on the heap, so deeply nested data doesn't
+    // use the Rust stack.
 
-                if let Token::Identifier(ident) = self.peek() {
-                    instr.push(ScmProcUnit::Assign(ident));
-                } else {
-                    panic!("Expected procedure name");
+    fn parse_datum(&mut self) -> ScmResult {
+        let mut open = Vec::new();
+        loop {
+            let token = self.next()?;
+            let mut datum = match token {
+                Token::ClosingParen => match open.pop() {
+                    Some(Open::List(items)) => scm_list(items, ScmValue::Nil),
+                    Some(Open::Vector(items)) => ScmValue::new_vector(items),
+                    _ => return Err(Self::error("Unexpected )")),
+                },
+                Token::Identifier(ident)
+                    if ident == "." && matches!(open.last(), Some(Open::List(_))) =>
+                {
+                    let Some(Open::List(items)) = open.pop() else {
+                        unreachable!()
+                    };
+                    open.push(Open::Tail(items));
+                    continue;
                 }
-                self.next();
-
-                let mut params = Vec::new();
-                while let Token::Identifier(ident) = self.peek() {
-                    params.push(ident);
-                    self.next();
+                Token::Identifier(ident) => ScmValue::Symbol(Symbol::intern(&ident)),
+                Token::Value(val) => val,
+                Token::Quote => {
+                    open.push(Open::Quote);
+                    continue;
                 }
-                assert!(matches!(self.next(), Token::ClosingParen));
-
-                let start_idx = instr.len();
-                self.parse_expr(instr);
-                let lambda_size = instr.len() - start_idx;
-
-                instr.push(ScmProcUnit::Lambda {
-                    args: params,
-                    units_cnt: lambda_size,
-                });
-            }
+                Token::OpenParen => {
+                    open.push(Open::List(Vec::new()));
+                    continue;
+                }
+                Token::OpenVector => {
+                    open.push(Open::Vector(Vec::new()));
+                    continue;
+                }
+                Token::Sentiel => return Err(Self::error("Unexpected end of input")),
+            };
 
-            _ => {
-                panic!("Unexpected symbol");
+            // The datum is complete, it goes to the innermost open one
+            loop {
+                match open.last_mut() {
+                    None => return Ok(datum),
+                    Some(Open::List(items) | Open::Vector(items)) => {
+                        items.push(datum);
+                        break;
+                    }
+                    Some(Open::Quote) => {
+                        open.pop();
+                        datum = scm_list(
+                            vec![ScmValue::Symbol(Symbol::intern("quote")), datum],
+                            ScmValue::Nil,
+                        );
+                    }
+                    Some(Open::Tail(_)) => {
+                        if !matches!(self.next()?, Token::ClosingParen) {
+                            return Err(Self::error("Expected ) after the tail of dotted list"));
+                        }
+                        let Some(Open::Tail(items)) = open.pop() else {
+                            unreachable!()
+                        };
+                        datum = scm_list(items, datum);
+                    }
+                }
             }
-        };
+        }
     }
 
-    fn gen_lambda(&mut self, instr: &mut Vec<ScmProcUnit>) {
-        let params = if let Token::Identifier(ident) = self.peek() {
-            vec![ident]
-        } else if let Token::OpenParen = self.peek() {
-            self.next();
-
-            let mut res = Vec::new();
-            while let Token::Identifier(param) = self.peek() {
-                res.push(param);
-                self.next();
-            }
-
-            assert!(matches!(self.next(), Token::ClosingParen));
-
-            res
-        } else {
-            panic!("Formals expected");
-        };
-
-        let start_idx = instr.len();
-        self.parse_value(instr);
-        let lambda_size = instr.len() - start_idx;
+    // Next datum, None at the end of input
+    pub fn read(&mut self) -> Result<Option<ScmValue>, ScmError> {
+        match self.peek()? {
+            Token::Sentiel => Ok(None),
+            _ => self.parse_datum().map(Some),
+        }
+    }
 
-        instr.push(ScmProcUnit::Lambda {
-            args: params,
-            units_cnt: lambda_size,
-        });
+    pub fn parse(&mut self) -> Result<Vec<ScmCallable>, ScmError> {
+        self.compile(Compiler::new())
     }
 
-    fn gen_condif(&mut self, instr: &mut Vec<ScmProcUnit>) {
-        let mut cond_instr = Vec::new();
-        self.parse_value(&mut cond_instr);
+    // Same, but `include` may read only the files the context allows
+    pub fn parse_in(&mut self, ctx: &ScmExecContext) -> Result<Vec<ScmCallable>, ScmError> {
+        let mut compiler = Compiler::new();
+        compiler.fs_access = ctx.fs_access.clone();
+        self.compile(compiler)
+    }
 
-        let mut true_instr = Vec::new();
-        self.parse_value(&mut true_instr);
+    fn compile(&mut self, mut compiler: Compiler) -> Result<Vec<ScmCallable>, ScmError> {
+        let mut res = Vec::new();
 
-        let has_else = !matches!(self.peek(), Token::ClosingParen);
-        if has_else {
-            let start_idx = instr.len();
-            self.parse_value(instr);
-            let fbr_size = instr.len() - start_idx;
-            instr.push(ScmProcUnit::FalseBranch(fbr_size));
-        } else {
-            instr.push(ScmProcUnit::Val(ScmValue::Nil));
-            instr.push(ScmProcUnit::FalseBranch(1));
+        while let Some(datum) = self.read()? {
+            res.push(ScmCallable::CustomProc(Rc::new(ScmProcedure {
+                template: compiler.compile_toplevel(&datum)?,
+                env: None,
+            })));
         }
 
-        let tbr_size = true_instr.len() + 1;
-        instr.append(&mut true_instr);
-        instr.push(ScmProcUnit::TrueBranch(tbr_size));
-
-        instr.append(&mut cond_instr);
+        Ok(res)
     }
 
-    fn parse_expr(&mut self, instr: &mut Vec<ScmProcUnit>) {
-        assert!(matches!(self.next(), Token::OpenParen));
-
-        let mut header_idx = None;
-        let mut args_cnt: usize = 666;
-
-        // Get first element (callable)
-        match self.peek() {
-            Token::Identifier(var) if var == "define" => {
-                self.next();
-                instr.push(ScmProcUnit::Val(ScmValue::Nil));
-                self.gen_define(instr);
-                assert!(matches!(self.next(), Token::ClosingParen));
-                return;
-            }
-
-            Token::Identifier(var) if var == "lambda" => {
-                self.next();
-                self.gen_lambda(instr);
-                assert!(matches!(self.next(), Token::ClosingParen));
-                return;
-            }
-
-            Token::Identifier(var) if var == "if" => {
-                self.next();
-                self.gen_condif(instr);
-                assert!(matches!(self.next(), Token::ClosingParen));
-                return;
-            }
-
-            Token::Identifier(var) => {
-                header_idx = Some(instr.len());
-                args_cnt = 0;
-                instr.push(ScmProcUnit::ProcCall(var, 666));
-                self.next();
-            }
-
-            Token::Value(val) => {
-                instr.push(ScmProcUnit::ProcCall(String::from("apply"), 2));
-                instr.push(ScmProcUnit::Val(val));
-
-                header_idx = Some(instr.len());
-                args_cnt = 0;
-                instr.push(ScmProcUnit::ProcCall(String::from("list"), 666));
-                self.next();
-            }
-
-            Token::OpenParen => {
-                instr.push(ScmProcUnit::ProcCall(String::from("apply"), 2));
-                self.parse_expr(instr); // Consumed here
-
-                header_idx = Some(instr.len());
-                args_cnt = 0;
-                instr.push(ScmProcUnit::ProcCall(String::from("list"), 666));
-            }
-
-            Token::ClosingParen => {
-                instr.push(ScmProcUnit::Val(ScmValue::Nil));
-            }
-
-            Token::Sentiel => {
-                panic!("");
-            }
-        };
-
-        // Arguments
-        loop {
-            match self.peek() {
-                Token::ClosingParen => {
-                    break;
-                }
-                Token::Sentiel => {
-                    panic!("Unexpected EOF");
-                }
-                _ => {
-                    self.parse_value(instr);
-                }
-            };
-            args_cnt += 1;
+    pub fn new(s: &str) -> Self {
+        Self {
+            lexer: Lexer::new(s),
+            peeked: None,
         }
+    }
 
-        if let Some(idx) = header_idx {
-            if let ScmProcUnit::ProcCall(_, cnt) = &mut instr[idx] {
-                *cnt = args_cnt;
-            } else {
-                panic!("WTF");
-            }
+    pub fn from_port(port: &'a ScmPort, tick: impl FnMut() -> Result<(), ScmError> + 'a) -> Self {
+        Self {
+            lexer: Lexer::from_port(port, tick),
+            peeked: None,
         }
-
-        assert!(matches!(self.next(), Token::ClosingParen));
     }
+}
 
-    pub fn parse(&mut self) -> Vec<ScmCallable> {
-        let mut res = Vec::new();
-
-        while let Token::OpenParen = self.peek() {
-            let mut instr = Vec::new();
+// Datum compiled into a procedure without params, which evaluates it. The
+// code sees only the bindings, if they are given, or the globals otherwise.
+pub fn compile_datum(
+    datum: &ScmValue,
+    bindings: Option<Rc<HashMap<Symbol, ScmBinding>>>,
+    fs_access: &ScmFsAccess,
+) -> Result<ScmCallable, ScmError> {
+    let mut compiler = match bindings {
+        Some(bindings) => Compiler::with_bindings(bindings),
+        None => Compiler::new(),
+    };
+    compiler.fs_access = fs_access.clone();
+    Ok(ScmCallable::CustomProc(Rc::new(ScmProcedure {
+        template: compiler.compile_toplevel(datum)?,
+        env: None,
+    })))
+}
 
-            self.parse_expr(&mut instr);
-            res.push(ScmCallable::CustomProc(ScmProcedure {
-                params: Vec::<String>::new(),
-                instructions: instr,
-            }));
-        }
+// Body of the library compiled into procedures. The code sees the imported
+// bindings and its own definitions, whose global names are returned too.
+pub fn compile_library(
+    body: &[ScmValue],
+    imports: Rc<HashMap<Symbol, ScmBinding>>,
+    source: Option<&Path>,
+    fs_access: &ScmFsAccess,
+) -> Result<(Vec<ScmCallable>, HashMap<Symbol, Symbol>), ScmError> {
+    let mut compiler = Compiler::with_bindings(imports);
+    compiler.sources.extend(source.map(Path::to_path_buf));
+    compiler.fs_access = fs_access.clone();
+    let callables = compiler
+        .compile_library(body)?
+        .into_iter()
+        .map(|template| {
+            ScmCallable::CustomProc(Rc::new(ScmProcedure {
+                template,
+                env: None,
+            }))
+        })
+        .collect();
+    Ok((callables, compiler.definitions))
+}
 
-        assert!(matches!(self.peek(), Token::Sentiel));
+// Datum read from the port by `read`, the eof object at the end of input.
+// Each char read counts against the limits of the context, and so do the
+// symbols the datum adds.
+pub fn read_datum(ctx: &ScmExecContext, port: &ScmPort) -> ScmResult {
+    let mut parser = Parser::from_port(port, || ctx.usage.tick());
+    let datum = parser.read()?;
+    ctx.reserve_alloc(0)?;
+    Ok(datum.unwrap_or(ScmValue::Eof))
+}
 
-        res
-    }
+// Forms of the file compiled into procedures, `include` inside the file is
+// resolved against its directory and checked against the file access
+pub fn parse_file(path: &Path, fs_access: &ScmFsAccess) -> Result<Vec<ScmCallable>, ScmError> {
+    let code =
+        fs::read_to_string(path).map_err(|err| io_error(err, &path.display().to_string()))?;
+    let mut compiler = Compiler::with_source(path);
+    compiler.fs_access = fs_access.clone();
+    Parser::new(&code).compile(compiler)
+}
 
-    pub fn new(s: &str) -> Self {
-        let tokens = Lexer::new(s).run();
-        Self { tokens, idx: 0 }
+// Runs the file in the context, returns the value of its last form. The
+// file is compiled completely before running. The file itself isn't checked
+// against the file access of the context, the files it includes are.
+pub fn load_file(ctx: &mut ScmExecContext, path: &Path) -> ScmResult {
+    let callables = parse_file(path, &ctx.fs_access)?;
+    let prev = ctx.loading.replace(path.to_path_buf());
+    let mut res = Ok(ScmValue::Nil);
+    for callable in callables.iter() {
+        res = exec_callable(ctx, callable, &[]);
+        if res.is_err() {
+            break;
+        }
     }
+    ctx.loading = prev;
+    res
 }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn test() {
-//         // let p = Parser::new("(+ 1 2)");
-
-//         // println!("{:?}", p);
-//     }
-// }
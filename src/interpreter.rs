@@ -0,0 +1,108 @@
+use crate::{
+    engine::*,
+    frontend::{load_file, Parser},
+};
+use std::path::Path;
+
+// Execution context with the frontend attached: evaluates code from strings
+// and files and calls Scheme procedures from the host program. The context
+// stays usable after errors.
+pub struct Interpreter {
+    ctx: ScmExecContext,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter {
+            ctx: ScmExecContext::new(),
+        }
+    }
+
+    // Interpreter over a prepared context, e.g. a sandboxed one
+    pub fn with_context(ctx: ScmExecContext) -> Interpreter {
+        Interpreter { ctx }
+    }
+
+    // For registering native procedures, library paths and so on
+    pub fn context(&mut self) -> &mut ScmExecContext {
+        &mut self.ctx
+    }
+
+    // Value of the last form, nil for empty code. Forms are compiled before
+    // any of them runs.
+    pub fn eval_str(&mut self, code: &str) -> ScmResult {
+        let mut res = ScmValue::Nil;
+        for callable in Parser::new(code).parse_in(&self.ctx)?.iter() {
+            res = exec_callable(&mut self.ctx, callable, &[])?;
+        }
+        Ok(res)
+    }
+
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> ScmResult {
+        load_file(&mut self.ctx, path.as_ref())
+    }
+
+    // Calls the procedure bound to the global
+    pub fn call(&mut self, name: &str, args: &[ScmValue]) -> ScmResult {
+        let proc = match self.get_global(name) {
+            Some(ScmValue::Procedure(proc)) => proc,
+            Some(val) => return Err(scm_error!("Value cannot be called: {}", scm_write(&val))),
+            None => {
+                return Err(ScmError::new(
+                    ScmErrorKind::Error,
+                    String::from("Unbound variable"),
+                    vec![ScmValue::new_string(name)],
+                ))
+            }
+        };
+        exec_callable(&mut self.ctx, &proc, args)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<ScmValue> {
+        // Names not interned yet can't be bound, and looking them up
+        // shouldn't add them to the symbol table
+        self.ctx.globals.get(Symbol::lookup(name)?).cloned()
+    }
+
+    pub fn set_global(&mut self, name: &str, val: impl IntoScm) {
+        self.ctx.add_or_assign_var(name, val.into_scm());
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn facade() {
+        let mut interp = Interpreter::new();
+        interp.set_global("base", 10i64);
+        interp
+            .eval_str("(define (add x) (+ x base)) (define last 0)")
+            .unwrap();
+        let res = interp.call("add", &[5i64.into_scm()]).unwrap();
+        assert_eq!(i64::from_scm(&res), Some(15));
+        assert_eq!(
+            i64::from_scm(&interp.eval_str("(set! last (add 1)) last").unwrap()),
+            Some(11)
+        );
+        assert!(interp.get_global("missing global").is_none());
+        assert!(Symbol::lookup("missing global").is_none());
+        assert!(interp.call("missing", &[]).is_err());
+        let err = interp.call("last", &[]).unwrap_err();
+        assert_eq!(err.to_string(), "Error: Value cannot be called: 11");
+        assert!(interp.eval_str("(car '())").is_err());
+        assert!(interp.eval_str("(add 1").is_err());
+        assert!(interp.eval_file("/nonexistent/file.scm").is_err());
+        assert_eq!(
+            i64::from_scm(&interp.eval_str("(add 0)").unwrap()),
+            Some(10)
+        );
+    }
+}
@@ -1,5 +1,7 @@
 pub mod engine;
 pub mod frontend;
+pub mod interpreter;
 
 pub use engine::*;
 pub use frontend::*;
+pub use interpreter::Interpreter;
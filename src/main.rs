@@ -3,24 +3,34 @@ use project::*;
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
-    let mut filename = &"test.scm".to_owned();
+    // Files run one after another and share the context, so later files see
+    // the definitions of earlier ones. `-L dir` adds a library directory.
+    let mut interp = Interpreter::new();
+    let mut filenames = Vec::new();
 
-    if args.len() == 2 {
-        filename = &args[1];
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-L" => match args.next() {
+                Some(dir) => interp.context().add_library_path(dir),
+                None => {
+                    eprintln!("-L requires a directory");
+                    std::process::exit(1);
+                }
+            },
+            _ => filenames.push(arg.as_str()),
+        }
     }
 
-    let code = std::fs::read_to_string(filename).expect("Error with file reading");
-
-    let mut ctx = ScmExecContext::new();
-    let mut parser = Parser::new(&code);
-
-    let callables = parser.parse();
+    if filenames.is_empty() {
+        filenames.push("test.scm");
+    }
 
-    for callable in callables.into_iter() {
-        // if let ScmCallable::CustomProc(proc) = &callable {
-        //     println!("{}", proc);
-        // }
-        exec_callable(&mut ctx, &callable, &Vec::new());
+    for filename in filenames.iter() {
+        if let Err(err) = interp.eval_file(filename) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
     }
     println!();
 }